@@ -0,0 +1,781 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use log::{debug, info, trace, warn};
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_dispatch, delegate_noop,
+    globals::{GlobalList, GlobalListContents},
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_compositor::WlCompositor,
+        wl_keyboard::{self, WlKeyboard},
+        wl_output::{self, WlOutput},
+        wl_pointer::{self, WlPointer},
+        wl_registry::{self, WlRegistry},
+        wl_seat::{self, WlSeat},
+        wl_shm::WlShm,
+        wl_surface::WlSurface,
+    },
+};
+use wayland_protocols::{
+    wp::cursor_shape::v1::client::{
+        wp_cursor_shape_device_v1::{self, WpCursorShapeDeviceV1},
+        wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
+    },
+    xdg::activation::v1::client::{
+        xdg_activation_token_v1::{self, XdgActivationTokenV1},
+        xdg_activation_v1::XdgActivationV1,
+    },
+};
+
+use crate::{
+    caps::CompositorCaps,
+    cursor::FallbackCursor,
+    error::{Error, Result},
+    input_log::{InputEvent, InputRecorder, InputReplay},
+    options::Options,
+    saved_geometry::SavedGeometry,
+    session::{self, Session, protocol::xdg_session_manager_v1::XdgSessionManagerV1},
+    shm::{BufferHandle, BufferPool},
+    toplevel::ToplevelStates,
+    vulkan::{self, pipeline_cache::PipelineCache},
+    window::{self, Window},
+};
+
+// From `linux/input-event-codes.h`.
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+
+/// Identifies one of the app's windows.
+///
+/// Objects belonging to a window carry its ID as their user data, so that their events can be
+/// routed to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WindowId(u32);
+
+impl WindowId {
+    /// Names the window in compositor sessions. This stays the same across runs as long as
+    /// windows are opened in the same order.
+    pub fn session_name(self) -> String {
+        format!("window-{}", self.0)
+    }
+}
+
+/// The state all events are dispatched to: every open window, along with the objects they share,
+/// such as outputs and input devices.
+pub struct App {
+    conn: Connection,
+    windows: HashMap<WindowId, Window>,
+    next_window_id: u32,
+    /// Shared by all windows, which only create their own surfaces and swapchains. Declared
+    /// after `windows` so that they are dropped with the device still around.
+    allocator: Arc<vulkan::Allocator>,
+    pipeline_cache: Arc<PipelineCache>,
+    compositor: WlCompositor,
+    shm: WlShm,
+    /// All outputs known to the compositor, bound so that surface enter/leave events can refer
+    /// to them. Each carries the name of its global.
+    outputs: Vec<WlOutput>,
+    pointer: Option<WlPointer>,
+    /// The window the pointer is currently over.
+    pointer_focus: Option<WindowId>,
+    cursor_shape_manager: Option<WpCursorShapeManagerV1>,
+    cursor_shape_device: Option<WpCursorShapeDeviceV1>,
+    /// Our own cursor, for when the compositor doesn't support cursor shapes.
+    fallback_cursor: Option<FallbackCursor>,
+    keyboard: Option<WlKeyboard>,
+    /// The window receiving key events.
+    keyboard_focus: Option<WindowId>,
+    input_recorder: Option<InputRecorder>,
+    input_replay: Option<InputReplay>,
+    activation: Option<XdgActivationV1>,
+    /// The token we were launched with, if any, handed to the first window so that it gets
+    /// focused.
+    startup_activation_token: Option<String>,
+    session: Option<Session>,
+    /// Handle to the queue for high-frequency objects (frame callbacks and input devices).
+    input_qh: QueueHandle<Self>,
+    /// The first error hit while handling events, which the main loop stops with.
+    fatal_error: Option<Error>,
+}
+
+impl App {
+    pub fn new(
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        input_qh: &QueueHandle<Self>,
+        globals: &GlobalList,
+        options: &Options,
+        startup_activation_token: Option<String>,
+    ) -> Result<Self> {
+        let compositor: WlCompositor = window::bind_global(globals, qh, 4..=6)?;
+        let shm: WlShm = window::bind_global(globals, qh, 1..=2)?;
+
+        let vk_device = window::create_vk_device(conn, options)?;
+        let pipeline_cache = Arc::new(PipelineCache::load(&vk_device)?);
+
+        let outputs = globals.contents().with_list(|list| {
+            list.iter()
+                .filter(|global| global.interface == WlOutput::interface().name)
+                .map(|global| bind_output(globals.registry(), global.name, global.version, qh))
+                .collect()
+        });
+
+        let cursor_shape_manager: Option<WpCursorShapeManagerV1> =
+            window::bind_optional_global(globals, qh, 1..=1);
+
+        // The seat is optional: without one we simply never receive input. Its own events are
+        // all we need from it, as input events carry their seat along.
+        let _seat: Option<WlSeat> = window::bind_optional_global(globals, qh, 1..=9);
+
+        let activation: Option<XdgActivationV1> = window::bind_optional_global(globals, qh, 1..=1);
+        if activation.is_none() {
+            info!("compositor does not support xdg-activation, windows may not get focus");
+        }
+
+        let session_manager: Option<XdgSessionManagerV1> =
+            window::bind_optional_global(globals, qh, 1..=1);
+        let session = session_manager.map(|session_manager| {
+            let session = Session::new(
+                &session_manager,
+                // Without an explicit ID, pick up where the last run left off.
+                options.session_id.clone().or_else(session::load_id),
+                qh,
+            );
+            // Sessions outlive their manager.
+            session_manager.destroy();
+            session
+        });
+
+        Ok(Self {
+            conn: conn.clone(),
+            windows: HashMap::new(),
+            next_window_id: 0,
+            allocator: vulkan::Allocator::new(&vk_device),
+            pipeline_cache,
+            compositor,
+            shm,
+            outputs,
+            pointer: None,
+            pointer_focus: None,
+            cursor_shape_manager,
+            cursor_shape_device: None,
+            fallback_cursor: None,
+            keyboard: None,
+            keyboard_focus: None,
+            input_recorder: options
+                .record_input
+                .as_deref()
+                .map(InputRecorder::create)
+                .transpose()?,
+            input_replay: options
+                .replay_input
+                .as_deref()
+                .map(InputReplay::load)
+                .transpose()?,
+            activation,
+            startup_activation_token,
+            session,
+            input_qh: input_qh.clone(),
+            fatal_error: None,
+        })
+    }
+
+    /// Opens a new toplevel window, which starts receiving events right away.
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_window(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        globals: &GlobalList,
+        width: u32,
+        height: u32,
+        title: String,
+        app_id: String,
+        options: &Options,
+        caps: &CompositorCaps,
+    ) -> Result<WindowId> {
+        let id = self.allocate_window_id();
+        let surface = self.compositor.create_surface(qh, id);
+        let window = Window::from_parts(
+            &self.conn,
+            qh,
+            &self.input_qh,
+            globals,
+            id,
+            surface,
+            &self.allocator,
+            &self.pipeline_cache,
+            width,
+            height,
+            title,
+            app_id,
+            options,
+            caps,
+            self.session.as_ref(),
+            SavedGeometry::load(&id.session_name()),
+        )?;
+        debug!(
+            "window {id:?} has wl_display {:?} and wl_surface {:?}",
+            window.display_ptr(),
+            window.surface_ptr(),
+        );
+        debug!(
+            "window {id:?} renders into {:?} swapchain images in {:?}",
+            window.swapchain_format(),
+            window.swapchain_color_space()
+        );
+
+        if let Some(activation) = &self.activation
+            && let Some(token) = self.startup_activation_token.take()
+        {
+            debug!("activating window {id:?} with startup token");
+            activation.activate(token, window.surface());
+        }
+
+        self.insert_window(window);
+        Ok(id)
+    }
+
+    /// Asks the compositor to activate window `id`, i.e. to focus it or at least draw the user's
+    /// attention to it.
+    ///
+    /// This goes through a fresh activation token, which arrives asynchronously. Lacking an input
+    /// event to prove that the user asked for this, compositors will usually only mark the window
+    /// as urgent. Windows that are already active are left alone.
+    pub fn request_activation(&self, qh: &QueueHandle<Self>, id: WindowId) {
+        let (Some(activation), Some(window)) = (&self.activation, self.windows.get(&id)) else {
+            return;
+        };
+        if window.states().contains(ToplevelStates::ACTIVATED) {
+            return;
+        }
+
+        let token = activation.get_activation_token(qh, id);
+        token.set_surface(window.surface());
+        token.commit();
+    }
+
+    /// Returns the ID to restore the compositor session with on the next run, for the app to store
+    /// somewhere.
+    ///
+    /// This is `None` until the compositor has set up the session, and if it doesn't support
+    /// sessions at all.
+    pub fn session_id(&self) -> Option<&str> {
+        self.session.as_ref()?.id()
+    }
+
+    pub fn session_mut(&mut self) -> Option<&mut Session> {
+        self.session.as_mut()
+    }
+
+    /// Stops tracking windows in the compositor session, e.g. because another client took it over.
+    pub fn end_session(&mut self) {
+        for window in self.windows.values_mut() {
+            window.leave_session();
+        }
+        self.session = None;
+    }
+
+    /// Keeps the error from handling an event, if any, for the main loop to pick up with
+    /// [`App::take_fatal_error`], as event handlers have no way of returning it.
+    ///
+    /// Only the first error is kept, since anything failing after it is likely fallout.
+    pub fn record_result(&mut self, result: Result<()>) {
+        if let Err(err) = result {
+            if self.fatal_error.is_none() {
+                self.fatal_error = Some(err);
+            } else {
+                debug!("ignoring error after an earlier one: {err}");
+            }
+        }
+    }
+
+    /// Takes the error recorded while handling events, if any.
+    pub fn take_fatal_error(&mut self) -> Option<Error> {
+        self.fatal_error.take()
+    }
+
+    pub fn window_mut(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.get_mut(&id)
+    }
+
+    pub fn has_windows(&self) -> bool {
+        !self.windows.is_empty()
+    }
+
+    /// Makes window `id` a child of window `parent`, or a standalone window again.
+    pub fn set_parent(&mut self, id: WindowId, parent: Option<WindowId>) {
+        // The compositor treats parent loops as a protocol error.
+        let mut ancestor = parent;
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                warn!("not making {id:?} a child of {parent:?}, as that would form a loop");
+                return;
+            }
+            ancestor = self.windows.get(&ancestor_id).and_then(Window::parent);
+        }
+
+        // Take the child out of the map, so that the parent can be borrowed alongside it.
+        let Some(mut window) = self.windows.remove(&id) else {
+            return;
+        };
+        window.set_parent(parent.and_then(|parent| self.windows.get(&parent)));
+        self.windows.insert(id, window);
+    }
+
+    /// Shuts down and drops every window that has been closed, along with all of its children.
+    pub fn remove_closed_windows(&mut self) -> Result<()> {
+        let mut closed: Vec<_> = self
+            .windows
+            .values()
+            .filter(|window| window.closed)
+            .map(Window::id)
+            .collect();
+
+        while let Some(id) = closed.pop() {
+            // Children may have been closed on their own as well.
+            let Some(mut window) = self.windows.remove(&id) else {
+                continue;
+            };
+            debug!(
+                "closing window {id:?}, which had capabilities {:?}",
+                window.wm_capabilities()
+            );
+            if let (Some(gpu_frame_time), Some(smoothed)) =
+                (window.gpu_frame_time(), window.smoothed_gpu_frame_time())
+            {
+                debug!(
+                    "last frame of {id:?} took {gpu_frame_time:?} on the gpu \
+                     (smoothed {smoothed:?})"
+                );
+            }
+            // Losing the geometry isn't worth failing over.
+            if let Err(err) = window.saved_geometry().save(&id.session_name()) {
+                warn!("failed to save window geometry: {err}");
+            }
+            window.shutdown()?;
+
+            for child in self
+                .windows
+                .values_mut()
+                .filter(|child| child.parent() == Some(id))
+            {
+                child.closed = true;
+                closed.push(child.id());
+            }
+
+            if self.pointer_focus == Some(id) {
+                self.pointer_focus = None;
+            }
+            if self.keyboard_focus == Some(id) {
+                self.keyboard_focus = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends all requests queued so far to the compositor.
+    ///
+    /// Apart from presents, which flush on their own, requests are only buffered until this is
+    /// called, letting callers batch everything resulting from a round of event handling.
+    pub fn flush(&self) -> Result<()> {
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Returns when the next replayed input event is due, if any.
+    pub fn next_replay_deadline(&self) -> Option<Instant> {
+        self.input_replay.as_ref()?.next_deadline()
+    }
+
+    /// Injects all replayed input events that are due by now.
+    ///
+    /// Replayed events go to the focused window, or to the oldest one if none has focus.
+    pub fn replay_input(&mut self) {
+        // Input can only arrive once a window is mapped, so this is where recording and replay
+        // times are measured from.
+        if self.windows.values().any(Window::is_configured) {
+            if let Some(input_recorder) = &mut self.input_recorder {
+                input_recorder.start();
+            }
+            if let Some(input_replay) = &mut self.input_replay {
+                input_replay.start();
+            }
+        }
+
+        let now = Instant::now();
+        while let Some(event) = self
+            .input_replay
+            .as_mut()
+            .and_then(|input_replay| input_replay.pop_due(now))
+        {
+            let Some(id) = self
+                .keyboard_focus
+                .or_else(|| self.windows.keys().min().copied())
+            else {
+                return;
+            };
+            trace!("replaying {event:?} to {id:?}");
+            self.handle_input(id, event);
+        }
+    }
+
+    /// Allocates the ID for a window about to be created.
+    fn allocate_window_id(&mut self) -> WindowId {
+        let id = WindowId(self.next_window_id);
+        self.next_window_id += 1;
+        id
+    }
+
+    /// Adds a window created with an ID from [`App::allocate_window_id`], so that it starts
+    /// receiving events.
+    fn insert_window(&mut self, window: Window) {
+        self.windows.insert(window.id(), window);
+    }
+
+    /// Finds the window `surface` belongs to, if it's one of ours at all.
+    fn window_for_surface(&self, surface: &WlSurface) -> Option<WindowId> {
+        self.windows
+            .values()
+            .find(|window| window.contains_surface(surface))
+            .map(Window::id)
+    }
+
+    /// Returns the modal dialog window `id` has up, if any, in which case it mustn't take clicks or
+    /// keys.
+    fn modal_child(&self, id: WindowId) -> Option<WindowId> {
+        self.windows
+            .values()
+            .find(|window| window.parent() == Some(id) && window.is_modal())
+            .map(Window::id)
+    }
+
+    /// Handles a decoded input event for window `id`, whether it came from the compositor or a
+    /// replay.
+    fn handle_input(&mut self, id: WindowId, event: InputEvent) {
+        if let Some(input_recorder) = &mut self.input_recorder
+            && let Err(err) = input_recorder.record(event)
+        {
+            warn!("failed to record input, giving up: {err}");
+            self.input_recorder = None;
+        }
+
+        let qh = self.input_qh.clone();
+        if let Some(window) = self.windows.get_mut(&id) {
+            let result = window.handle_input(&qh, event);
+            self.record_result(result);
+        }
+    }
+
+    /// Sets the cursor image for `pointer`, which just entered a window with the given scale.
+    fn set_cursor(&mut self, qh: &QueueHandle<Self>, pointer: &WlPointer, serial: u32, scale: f64) {
+        if let Some(cursor_shape_device) = &self.cursor_shape_device {
+            cursor_shape_device.set_shape(serial, wp_cursor_shape_device_v1::Shape::Default);
+            return;
+        }
+
+        // Without a cursor, the compositor keeps showing whatever it showed last, which beats
+        // failing over it.
+        match &mut self.fallback_cursor {
+            Some(fallback_cursor) => {
+                if let Err(err) = fallback_cursor.set_scale(scale, qh) {
+                    warn!("failed to rescale cursor: {err}");
+                    return;
+                }
+            }
+            None => match FallbackCursor::new(&self.compositor, &self.shm, scale, qh) {
+                Ok(fallback_cursor) => self.fallback_cursor = Some(fallback_cursor),
+                Err(err) => {
+                    warn!("failed to create cursor: {err}");
+                    return;
+                }
+            },
+        }
+        if let Some(fallback_cursor) = &self.fallback_cursor {
+            fallback_cursor.apply(pointer, serial);
+        }
+    }
+
+    fn add_output(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        registry: &WlRegistry,
+        name: u32,
+        version: u32,
+    ) {
+        self.outputs.push(bind_output(registry, name, version, qh));
+    }
+
+    fn remove_output(&mut self, name: u32) {
+        let Some(idx) = self
+            .outputs
+            .iter()
+            .position(|output| output.data::<u32>() == Some(&name))
+        else {
+            return;
+        };
+
+        let output = self.outputs.remove(idx);
+        let result = self
+            .windows
+            .values_mut()
+            .try_for_each(|window| window.output_removed(&output));
+        self.record_result(result);
+        if window::supports(&output, wl_output::REQ_RELEASE_SINCE) {
+            output.release();
+        }
+    }
+}
+
+fn bind_output(registry: &WlRegistry, name: u32, version: u32, qh: &QueueHandle<App>) -> WlOutput {
+    let output = registry.bind(name, version.min(4), qh, name);
+    debug!("bound wl_output {name} version {}", output.version());
+    output
+}
+
+delegate_noop!(App: ignore WpCursorShapeManagerV1);
+delegate_noop!(App: ignore WpCursorShapeDeviceV1);
+delegate_noop!(App: XdgActivationV1);
+
+delegate_dispatch!(App: [WlBuffer: Arc<BufferHandle>] => BufferPool);
+
+impl Dispatch<WlRegistry, GlobalListContents> for App {
+    fn event(
+        app: &mut Self,
+        registry: &WlRegistry,
+        event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == WlOutput::interface().name => {
+                app.add_output(qh, registry, name, version)
+            }
+            wl_registry::Event::GlobalRemove { name } => app.remove_output(name),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlOutput, u32> for App {
+    fn event(
+        _app: &mut Self,
+        _output: &WlOutput,
+        _event: wl_output::Event,
+        _data: &u32,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlSeat, ()> for App {
+    fn event(
+        app: &mut Self,
+        seat: &WlSeat,
+        event: wl_seat::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        {
+            let has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+            match (has_pointer, app.pointer.take()) {
+                (true, None) => {
+                    let pointer = seat.get_pointer(&app.input_qh, seat.clone());
+                    app.cursor_shape_device = app
+                        .cursor_shape_manager
+                        .as_ref()
+                        .map(|manager| manager.get_pointer(&pointer, &app.input_qh, ()));
+                    app.pointer = Some(pointer);
+                }
+                (false, Some(pointer)) => {
+                    if let Some(cursor_shape_device) = app.cursor_shape_device.take() {
+                        cursor_shape_device.destroy();
+                    }
+                    if window::supports(&pointer, wl_pointer::REQ_RELEASE_SINCE) {
+                        pointer.release();
+                    }
+                    app.pointer_focus = None;
+                }
+                (_, pointer) => app.pointer = pointer,
+            }
+
+            let has_keyboard = capabilities.contains(wl_seat::Capability::Keyboard);
+            match (has_keyboard, app.keyboard.take()) {
+                (true, None) => app.keyboard = Some(seat.get_keyboard(&app.input_qh, ())),
+                (false, Some(keyboard)) => {
+                    if window::supports(&keyboard, wl_keyboard::REQ_RELEASE_SINCE) {
+                        keyboard.release();
+                    }
+                    app.keyboard_focus = None;
+                }
+                (_, keyboard) => app.keyboard = keyboard,
+            }
+        }
+    }
+}
+
+impl Dispatch<WlPointer, WlSeat> for App {
+    fn event(
+        app: &mut Self,
+        pointer: &WlPointer,
+        event: wl_pointer::Event,
+        seat: &WlSeat,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_pointer::Event::Enter {
+            serial,
+            surface,
+            surface_x,
+            surface_y,
+        } = event
+        {
+            // The cursor surface is the only one of ours not belonging to a window, and the
+            // pointer never enters that.
+            let Some(id) = app.window_for_surface(&surface) else {
+                return;
+            };
+            app.pointer_focus = Some(id);
+
+            let window = app.window_mut(id).unwrap();
+            window.pointer_enter(surface);
+            let scale = window.scale();
+
+            app.set_cursor(qh, pointer, serial, scale);
+            app.handle_input(
+                id,
+                InputEvent::PointerMotion {
+                    x: surface_x,
+                    y: surface_y,
+                },
+            );
+            return;
+        }
+
+        let Some(id) = app.pointer_focus else {
+            return;
+        };
+
+        match event {
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => app.handle_input(
+                id,
+                InputEvent::PointerMotion {
+                    x: surface_x,
+                    y: surface_y,
+                },
+            ),
+            wl_pointer::Event::Leave { .. } => {
+                app.pointer_focus = None;
+                if let Some(window) = app.window_mut(id) {
+                    window.pointer_leave();
+                }
+            }
+            wl_pointer::Event::Button {
+                serial,
+                button,
+                state: WEnum::Value(wl_pointer::ButtonState::Pressed),
+                ..
+            } => {
+                // Point the user at the dialog that's in the way instead.
+                if let Some(dialog) = app.modal_child(id) {
+                    app.request_activation(qh, dialog);
+                    return;
+                }
+                let Some(window) = app.window_mut(id) else {
+                    return;
+                };
+                match button {
+                    BTN_LEFT => window.handle_left_click(seat, serial),
+                    BTN_RIGHT => window.handle_right_click(qh, seat, serial),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for App {
+    fn event(
+        app: &mut Self,
+        _keyboard: &WlKeyboard,
+        event: wl_keyboard::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_keyboard::Event::Enter { surface, keys, .. } => {
+                let Some(id) = app.window_for_surface(&surface) else {
+                    return;
+                };
+                app.keyboard_focus = Some(id);
+                app.window_mut(id)
+                    .unwrap()
+                    .keyboard_enter(window::decode_u32_array(&keys));
+            }
+            wl_keyboard::Event::Leave { .. } => {
+                if let Some(id) = app.keyboard_focus.take()
+                    && let Some(window) = app.window_mut(id)
+                {
+                    window.keyboard_leave();
+                }
+            }
+            wl_keyboard::Event::Key {
+                key,
+                state: WEnum::Value(state),
+                ..
+            } => {
+                if let Some(id) = app.keyboard_focus
+                    && app.modal_child(id).is_none()
+                {
+                    app.handle_input(
+                        id,
+                        InputEvent::Key {
+                            key,
+                            pressed: state == wl_keyboard::KeyState::Pressed,
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<XdgActivationTokenV1, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        token: &XdgActivationTokenV1,
+        event: xdg_activation_token_v1::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_activation_token_v1::Event::Done {
+            token: token_string,
+        } = event
+        {
+            if let Some(activation) = &app.activation
+                && let Some(window) = app.windows.get(id)
+            {
+                activation.activate(token_string, window.surface());
+            }
+            token.destroy();
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use log::debug;
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+    globals::GlobalList,
+    protocol::wl_shm::{self, WlShm},
+};
+use wayland_protocols::wp::presentation_time::client::wp_presentation::{self, WpPresentation};
+
+use crate::error::Result;
+
+/// Compositor features that are only known once the events sent when binding globals have
+/// arrived.
+///
+/// These are collected up front with a dedicated roundtrip, so that the window can be set up
+/// knowing all of them instead of racing against their events.
+#[derive(Debug, Default)]
+pub struct CompositorCaps {
+    /// Pixel formats supported for `wl_shm` buffers.
+    pub shm_formats: Vec<wl_shm::Format>,
+    /// Raw `clockid_t` of the clock used for presentation timestamps, if `wp_presentation` is
+    /// available.
+    pub presentation_clock: Option<u32>,
+    /// Whether the compositor can draw decorations for us through `xdg-decoration`.
+    pub server_side_decorations: bool,
+}
+
+impl CompositorCaps {
+    pub fn query(conn: &Connection, globals: &GlobalList) -> Result<Self> {
+        let mut queue = conn.new_event_queue();
+        let qh = queue.handle();
+
+        let mut caps = Self {
+            server_side_decorations: globals.contents().with_list(|list| {
+                list.iter()
+                    .any(|global| global.interface == "zxdg_decoration_manager_v1")
+            }),
+            ..Default::default()
+        };
+
+        // These are only bound for the events they send on creation, and released right after.
+        let shm: Option<WlShm> = globals.bind(&qh, 1..=2, ()).ok();
+        let presentation: Option<WpPresentation> = globals.bind(&qh, 1..=2, ()).ok();
+
+        queue.roundtrip(&mut caps)?;
+
+        if let Some(shm) = shm
+            && shm.version() >= wl_shm::REQ_RELEASE_SINCE
+        {
+            shm.release();
+        }
+        if let Some(presentation) = presentation {
+            presentation.destroy();
+        }
+
+        debug!("compositor capabilities: {caps:?}");
+        Ok(caps)
+    }
+}
+
+impl Dispatch<WlShm, ()> for CompositorCaps {
+    fn event(
+        caps: &mut Self,
+        _shm: &WlShm,
+        event: wl_shm::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_shm::Event::Format {
+            format: WEnum::Value(format),
+        } = event
+        {
+            caps.shm_formats.push(format);
+        }
+    }
+}
+
+impl Dispatch<WpPresentation, ()> for CompositorCaps {
+    fn event(
+        caps: &mut Self,
+        _presentation: &WpPresentation,
+        event: wp_presentation::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            caps.presentation_clock = Some(clk_id);
+        }
+    }
+}
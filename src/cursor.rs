@@ -0,0 +1,156 @@
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_pointer::WlPointer,
+        wl_shm::WlShm,
+        wl_surface::{self, WlSurface},
+    },
+};
+
+use crate::{
+    app::App,
+    draw::Canvas,
+    error::Result,
+    shm::{BufferPool, to_buffer_scale},
+};
+
+/// A classic arrow, in logical pixels: `B` is the outline and `W` the fill.
+const ARROW: [&[u8; 12]; 16] = [
+    b"B...........",
+    b"BB..........",
+    b"BWB.........",
+    b"BWWB........",
+    b"BWWWB.......",
+    b"BWWWWB......",
+    b"BWWWWWB.....",
+    b"BWWWWWWB....",
+    b"BWWWWWWWB...",
+    b"BWWWWWWWWB..",
+    b"BWWWWWBBBBB.",
+    b"BWWBWWB.....",
+    b"BWB.BWWB....",
+    b"BB..BWWB....",
+    b"B....BWWB...",
+    b".....BBB....",
+];
+const WIDTH: u32 = 12;
+const HEIGHT: u32 = 16;
+
+/// The arrow's tip, in logical pixels.
+const HOTSPOT: (i32, i32) = (0, 0);
+
+const OUTLINE_COLOR: u32 = 0xff000000;
+const FILL_COLOR: u32 = 0xffffffff;
+
+/// Marks the cursor's surface, so that its events aren't mistaken for the main surface's.
+pub struct CursorSurface;
+
+/// A built-in arrow cursor, for compositors that can't draw one for us through
+/// `wp_cursor_shape_v1`.
+pub struct FallbackCursor {
+    surface: WlSurface,
+    buffers: BufferPool,
+    buffer_scale: u32,
+}
+
+impl FallbackCursor {
+    pub fn new(
+        compositor: &WlCompositor,
+        shm: &WlShm,
+        scale: f64,
+        qh: &QueueHandle<App>,
+    ) -> Result<Self> {
+        let surface = compositor.create_surface(qh, CursorSurface);
+
+        let buffer_scale = to_buffer_scale(scale);
+        let buffers = BufferPool::new(shm, qh, WIDTH * buffer_scale, HEIGHT * buffer_scale)?;
+
+        let mut cursor = Self {
+            surface,
+            buffers,
+            buffer_scale,
+        };
+        cursor.redraw(qh)?;
+
+        Ok(cursor)
+    }
+
+    /// Makes this the pointer's cursor, in response to the enter event with `serial`.
+    pub fn apply(&self, pointer: &WlPointer, serial: u32) {
+        // The hotspot is in surface-local coordinates, so it stays the same no matter what buffer
+        // scale the arrow is drawn at.
+        let (hotspot_x, hotspot_y) = HOTSPOT;
+        pointer.set_cursor(serial, Some(&self.surface), hotspot_x, hotspot_y);
+    }
+
+    /// Re-renders the cursor for a new window scale, if it calls for a different buffer scale.
+    pub fn set_scale(&mut self, scale: f64, qh: &QueueHandle<App>) -> Result<()> {
+        let buffer_scale = to_buffer_scale(scale);
+        if buffer_scale != self.buffer_scale {
+            self.buffer_scale = buffer_scale;
+            self.buffers
+                .reconfigure(WIDTH * buffer_scale, HEIGHT * buffer_scale);
+            self.redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    fn redraw(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        let width = WIDTH * self.buffer_scale;
+        let height = HEIGHT * self.buffer_scale;
+
+        let (buffer, pixels) = self.buffers.get_buffer(qh)?;
+        draw(pixels, width, height, self.buffer_scale);
+
+        // Cursor surfaces aren't synchronized to anything, so this takes effect right away.
+        self.surface.set_buffer_scale(self.buffer_scale as i32);
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.surface.commit();
+
+        Ok(())
+    }
+}
+
+impl Drop for FallbackCursor {
+    fn drop(&mut self) {
+        self.surface.destroy();
+    }
+}
+
+fn draw(pixels: &mut [u32], width: u32, height: u32, buffer_scale: u32) {
+    let mut canvas = Canvas::new(pixels, width, height);
+    canvas.fill(0);
+
+    // Blow every logical pixel up to a `buffer_scale`-sized square.
+    for (y, row) in (0..).zip(ARROW) {
+        for (x, pixel) in (0..).zip(row) {
+            let color = match pixel {
+                b'B' => OUTLINE_COLOR,
+                b'W' => FILL_COLOR,
+                _ => continue,
+            };
+            canvas.fill_rect(
+                x * buffer_scale as i32,
+                y * buffer_scale as i32,
+                buffer_scale,
+                buffer_scale,
+                color,
+            );
+        }
+    }
+}
+
+impl Dispatch<WlSurface, CursorSurface> for App {
+    fn event(
+        _app: &mut Self,
+        _surface: &WlSurface,
+        _event: wl_surface::Event,
+        _data: &CursorSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
@@ -0,0 +1,222 @@
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_shm::WlShm,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
+        wl_surface::{self, WlSurface},
+    },
+};
+
+use crate::{
+    app::App,
+    draw::Canvas,
+    error::Result,
+    shm::{BufferPool, to_buffer_scale},
+};
+use wayland_protocols::xdg::shell::client::xdg_toplevel::ResizeEdge;
+
+/// Height of the titlebar, in logical pixels.
+pub const TITLEBAR_HEIGHT: u32 = 24;
+const CLOSE_BUTTON_SIZE: u32 = 16;
+/// How close to the edge of the window the pointer has to be to resize it, in logical pixels.
+const RESIZE_BORDER: f64 = 6.0;
+
+const TITLEBAR_COLOR: u32 = 0xff303030;
+const CLOSE_BUTTON_COLOR: u32 = 0xffb22222;
+const ICON_COLOR: u32 = 0xffffffff;
+
+/// The parts of the titlebar that react to the pointer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TitlebarPart {
+    CloseButton,
+    /// Everything else, which moves the window when dragged.
+    Background,
+}
+
+/// Marks the titlebar's surface, so that its events aren't mistaken for the main surface's.
+pub struct TitlebarSurface;
+
+/// A minimal client-side titlebar with a close button, shown as a subsurface just above the
+/// window's main surface.
+pub struct Titlebar {
+    surface: WlSurface,
+    subsurface: WlSubsurface,
+    buffers: BufferPool,
+    width: u32,
+    buffer_scale: u32,
+}
+
+impl Titlebar {
+    pub fn new(
+        compositor: &WlCompositor,
+        subcompositor: &WlSubcompositor,
+        shm: &WlShm,
+        parent: &WlSurface,
+        width: u32,
+        scale: f64,
+        qh: &QueueHandle<App>,
+    ) -> Result<Self> {
+        let surface = compositor.create_surface(qh, TitlebarSurface);
+        let subsurface = subcompositor.get_subsurface(&surface, parent, qh, ());
+        subsurface.set_position(0, -(TITLEBAR_HEIGHT as i32));
+
+        let buffer_scale = to_buffer_scale(scale);
+        let buffers = BufferPool::new(
+            shm,
+            qh,
+            width * buffer_scale,
+            TITLEBAR_HEIGHT * buffer_scale,
+        )?;
+
+        let mut titlebar = Self {
+            surface,
+            subsurface,
+            buffers,
+            width,
+            buffer_scale,
+        };
+        titlebar.redraw(qh)?;
+
+        Ok(titlebar)
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Finds the part of the titlebar at the given surface-local position.
+    pub fn hit_test(&self, x: f64, y: f64) -> TitlebarPart {
+        // Matches the layout in `draw`, but in logical pixels.
+        let margin = (TITLEBAR_HEIGHT - CLOSE_BUTTON_SIZE) / 2;
+        let button_x = (self.width - margin - CLOSE_BUTTON_SIZE) as f64;
+        let button_y = margin as f64;
+        let button_size = CLOSE_BUTTON_SIZE as f64;
+
+        if (button_x..button_x + button_size).contains(&x)
+            && (button_y..button_y + button_size).contains(&y)
+        {
+            TitlebarPart::CloseButton
+        } else {
+            TitlebarPart::Background
+        }
+    }
+
+    /// Re-renders the titlebar to span a new window width.
+    pub fn set_width(&mut self, width: u32, qh: &QueueHandle<App>) -> Result<()> {
+        if width != self.width {
+            self.width = width;
+            self.buffers.reconfigure(
+                width * self.buffer_scale,
+                TITLEBAR_HEIGHT * self.buffer_scale,
+            );
+            self.redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Re-renders the titlebar for a new window scale, if it calls for a different buffer scale.
+    pub fn set_scale(&mut self, scale: f64, qh: &QueueHandle<App>) -> Result<()> {
+        let buffer_scale = to_buffer_scale(scale);
+        if buffer_scale != self.buffer_scale {
+            self.buffer_scale = buffer_scale;
+            self.buffers
+                .reconfigure(self.width * buffer_scale, TITLEBAR_HEIGHT * buffer_scale);
+            self.redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Draws into a fresh buffer and damages all of it, e.g. in case the compositor dropped the old
+    /// contents.
+    pub fn redraw(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        let width = self.width * self.buffer_scale;
+        let height = TITLEBAR_HEIGHT * self.buffer_scale;
+
+        let (buffer, pixels) = self.buffers.get_buffer(qh)?;
+        draw(pixels, width, height, self.buffer_scale);
+
+        // The subsurface is synchronized, so this only takes effect with the parent's next commit.
+        self.surface.set_buffer_scale(self.buffer_scale as i32);
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.surface.commit();
+
+        Ok(())
+    }
+}
+
+impl Drop for Titlebar {
+    fn drop(&mut self) {
+        // Destroying the subsurface unmaps it immediately.
+        self.subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+fn draw(pixels: &mut [u32], width: u32, height: u32, buffer_scale: u32) {
+    let mut canvas = Canvas::new(pixels, width, height);
+    canvas.fill(TITLEBAR_COLOR);
+
+    // The close button sits at the right end, vertically centered.
+    let button_size = CLOSE_BUTTON_SIZE * buffer_scale;
+    let margin = (height - button_size) / 2;
+    let x = (width - margin - button_size) as i32;
+    let y = margin as i32;
+    canvas.fill_rect(x, y, button_size, button_size, CLOSE_BUTTON_COLOR);
+
+    let size = button_size as i32;
+    let inset = size / 4;
+    for offset in 0..buffer_scale as i32 {
+        let x = x + offset;
+        canvas.draw_line(
+            (x + inset, y + inset),
+            (x + size - inset, y + size - inset),
+            ICON_COLOR,
+        );
+        canvas.draw_line(
+            (x + inset, y + size - inset),
+            (x + size - inset, y + inset),
+            ICON_COLOR,
+        );
+    }
+}
+
+/// Finds the window edge or corner to resize from when grabbing the window at `pos`, if any.
+///
+/// Both `pos` and `size` are in window geometry coordinates, titlebar included.
+pub fn resize_edge(pos: (f64, f64), size: (u32, u32)) -> Option<ResizeEdge> {
+    let (x, y) = pos;
+    let (width, height) = (size.0 as f64, size.1 as f64);
+
+    let left = x < RESIZE_BORDER;
+    let right = x >= width - RESIZE_BORDER;
+    let top = y < RESIZE_BORDER;
+    let bottom = y >= height - RESIZE_BORDER;
+
+    match (left, right, top, bottom) {
+        (true, _, true, _) => Some(ResizeEdge::TopLeft),
+        (_, true, true, _) => Some(ResizeEdge::TopRight),
+        (true, _, _, true) => Some(ResizeEdge::BottomLeft),
+        (_, true, _, true) => Some(ResizeEdge::BottomRight),
+        (true, ..) => Some(ResizeEdge::Left),
+        (_, true, ..) => Some(ResizeEdge::Right),
+        (_, _, true, _) => Some(ResizeEdge::Top),
+        (.., true) => Some(ResizeEdge::Bottom),
+        _ => None,
+    }
+}
+
+impl Dispatch<WlSurface, TitlebarSurface> for App {
+    fn event(
+        _app: &mut Self,
+        _surface: &WlSurface,
+        _event: wl_surface::Event,
+        _data: &TitlebarSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
@@ -0,0 +1,165 @@
+use log::error;
+use wayland_client::{
+    Connection,
+    backend::protocol::ProtocolError,
+    protocol::{wl_display, wl_shm, wl_subcompositor, wl_surface},
+};
+use wayland_protocols::{
+    wp::{
+        fractional_scale::v1::client::wp_fractional_scale_manager_v1,
+        viewporter::client::wp_viewport,
+    },
+    xdg::{
+        decoration::zv1::client::zxdg_toplevel_decoration_v1,
+        shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base},
+        toplevel_icon::v1::client::xdg_toplevel_icon_v1,
+    },
+};
+
+/// Logs the protocol error that killed `conn`, if there was one.
+///
+/// wayland-client only reports such errors as a generic connection failure, so this is the place
+/// to spell out what the compositor actually objected to.
+pub fn report_protocol_error(conn: &Connection) {
+    let Some(err) = conn.protocol_error() else {
+        return;
+    };
+
+    error!(
+        "compositor sent protocol error {} on {}@{}: {}",
+        err.code, err.object_interface, err.object_id, err.message
+    );
+    if let Some(hint) = hint(&err) {
+        error!("likely cause: {hint}");
+    }
+}
+
+/// Maps well-known error codes on the interfaces we use to a guess at what we did wrong.
+fn hint(err: &ProtocolError) -> Option<&'static str> {
+    // Every interface numbers its own errors, so the code is meaningless without the interface.
+    match err.object_interface.as_str() {
+        "wl_display" => match decode(err.code)? {
+            wl_display::Error::InvalidObject => Some("a request referenced a destroyed object"),
+            wl_display::Error::InvalidMethod => {
+                Some("a request isn't supported by the bound version of its interface")
+            }
+            wl_display::Error::NoMemory => Some("the compositor ran out of memory"),
+            wl_display::Error::Implementation => Some("the compositor hit an internal error"),
+            _ => None,
+        },
+        "wl_surface" => match decode(err.code)? {
+            wl_surface::Error::InvalidScale => Some("the buffer scale isn't a positive integer"),
+            wl_surface::Error::InvalidSize => {
+                Some("the buffer size isn't a multiple of the buffer scale")
+            }
+            wl_surface::Error::DefunctRoleObject => {
+                Some("the surface was destroyed before its role object")
+            }
+            _ => None,
+        },
+        "wl_shm" | "wl_shm_pool" => match decode(err.code)? {
+            wl_shm::Error::InvalidFormat => Some("the compositor doesn't support the shm format"),
+            wl_shm::Error::InvalidStride => {
+                Some("a buffer's stride or size doesn't fit in its pool")
+            }
+            wl_shm::Error::InvalidFd => Some("the shm pool's fd couldn't be mapped"),
+            _ => None,
+        },
+        "wl_subcompositor" => match decode(err.code)? {
+            wl_subcompositor::Error::BadSurface => {
+                Some("a subsurface was created for a surface that already has a role")
+            }
+            wl_subcompositor::Error::BadParent => {
+                Some("a subsurface's parent is the subsurface itself or one of its descendants")
+            }
+            _ => None,
+        },
+        "xdg_wm_base" => match decode(err.code)? {
+            xdg_wm_base::Error::Role => Some("the surface already has a different role"),
+            xdg_wm_base::Error::DefunctSurfaces => {
+                Some("xdg_wm_base was destroyed while its surfaces were still alive")
+            }
+            xdg_wm_base::Error::Unresponsive => {
+                Some("a ping wasn't answered in time, probably because the event loop stalled")
+            }
+            _ => None,
+        },
+        "xdg_surface" => match decode(err.code)? {
+            xdg_surface::Error::NotConstructed => {
+                Some("the xdg_surface was used before being given a toplevel or popup role")
+            }
+            xdg_surface::Error::AlreadyConstructed => {
+                Some("a buffer was attached before the xdg_surface got its role")
+            }
+            xdg_surface::Error::UnconfiguredBuffer => {
+                Some("a buffer was attached before acking the first configure")
+            }
+            xdg_surface::Error::InvalidSerial => {
+                Some("a configure was acked with a serial that was never sent")
+            }
+            xdg_surface::Error::InvalidSize => {
+                Some("the window geometry has a zero or negative size")
+            }
+            xdg_surface::Error::DefunctRoleObject => {
+                Some("the xdg_surface was destroyed before its toplevel")
+            }
+            _ => None,
+        },
+        "xdg_toplevel" => match decode(err.code)? {
+            xdg_toplevel::Error::InvalidResizeEdge => Some("an interactive resize used a bad edge"),
+            xdg_toplevel::Error::InvalidParent => Some("the toplevel's parent would form a loop"),
+            xdg_toplevel::Error::InvalidSize => {
+                Some("the minimum size is larger than the maximum size")
+            }
+            _ => None,
+        },
+        "zxdg_toplevel_decoration_v1" => match decode(err.code)? {
+            zxdg_toplevel_decoration_v1::Error::UnconfiguredBuffer => {
+                Some("the decoration object was created after a buffer was attached")
+            }
+            zxdg_toplevel_decoration_v1::Error::AlreadyConstructed => {
+                Some("the toplevel already has a decoration object")
+            }
+            zxdg_toplevel_decoration_v1::Error::Orphaned => {
+                Some("the toplevel was destroyed before its decoration object")
+            }
+            _ => None,
+        },
+        "xdg_toplevel_icon_v1" => match decode(err.code)? {
+            xdg_toplevel_icon_v1::Error::InvalidBuffer => {
+                Some("an icon buffer isn't square or isn't backed by shm")
+            }
+            xdg_toplevel_icon_v1::Error::Immutable => {
+                Some("the icon was changed after being set on a toplevel")
+            }
+            xdg_toplevel_icon_v1::Error::NoBuffer => {
+                Some("an icon buffer was destroyed before the icon")
+            }
+            _ => None,
+        },
+        "wp_viewport" => match decode(err.code)? {
+            wp_viewport::Error::BadValue => {
+                Some("the viewport source or destination has a negative or zero size")
+            }
+            wp_viewport::Error::BadSize => {
+                Some("the viewport destination has a fractional size without a source rect")
+            }
+            wp_viewport::Error::OutOfBuffer => {
+                Some("the viewport source rect extends past the buffer")
+            }
+            wp_viewport::Error::NoSurface => Some("the viewport's surface was already destroyed"),
+            _ => None,
+        },
+        "wp_fractional_scale_manager_v1" => match decode(err.code)? {
+            wp_fractional_scale_manager_v1::Error::FractionalScaleExists => {
+                Some("the surface already has a fractional scale object")
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn decode<E: TryFrom<u32>>(code: u32) -> Option<E> {
+    E::try_from(code).ok()
+}
@@ -0,0 +1,356 @@
+use std::ops::Range;
+
+/// A view of an ARGB pixel buffer with some basic drawing operations.
+///
+/// Coordinates may lie partially or entirely outside the buffer; everything is clipped to its
+/// bounds.
+pub struct Canvas<'a> {
+    pixels: &'a mut [u32],
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(pixels: &'a mut [u32], width: u32, height: u32) -> Self {
+        assert!(
+            pixels.len() >= width as usize * height as usize,
+            "pixel buffer too small"
+        );
+
+        Self {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    pub fn fill(&mut self, color: u32) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: u32) {
+        let Some((x_range, y_range)) = self.clip(x, y, width, height) else {
+            return;
+        };
+
+        for y in y_range {
+            let row = y * self.width as usize;
+            self.pixels[row + x_range.start..row + x_range.end].fill(color);
+        }
+    }
+
+    /// Draws a one-pixel line between two points, inclusive.
+    pub fn draw_line(&mut self, (x0, y0): (i32, i32), (x1, y1): (i32, i32), color: u32) {
+        // Bresenham's algorithm, generalized to all octants.
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        let (mut x, mut y) = (x0, y0);
+        let mut err = dx + dy;
+
+        loop {
+            self.put_pixel(x, y, color);
+            if (x, y) == (x1, y1) {
+                break;
+            }
+
+            let err2 = 2 * err;
+            if err2 >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if err2 <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Copies a `width`x`height` block of pixels to `(x, y)`, replacing what was there.
+    pub fn blit(&mut self, x: i32, y: i32, src: &[u32], width: u32, height: u32) {
+        assert!(
+            src.len() >= width as usize * height as usize,
+            "source buffer too small"
+        );
+
+        let Some((x_range, y_range)) = self.clip(x, y, width, height) else {
+            return;
+        };
+
+        // Offsets into the source of the first visible pixel.
+        let src_x = (x_range.start as i64 - x as i64) as usize;
+        let src_y = (y_range.start as i64 - y as i64) as usize;
+
+        for (i, dst_y) in y_range.enumerate() {
+            let src_row = (src_y + i) * width as usize + src_x;
+            let dst_row = dst_y * self.width as usize;
+            self.pixels[dst_row + x_range.start..dst_row + x_range.end]
+                .copy_from_slice(&src[src_row..src_row + x_range.len()]);
+        }
+    }
+
+    fn put_pixel(&mut self, x: i32, y: i32, color: u32) {
+        if let Some((x_range, y_range)) = self.clip(x, y, 1, 1) {
+            self.pixels[y_range.start * self.width as usize + x_range.start] = color;
+        }
+    }
+
+    /// Intersects a rectangle with the canvas, returning the visible columns and rows if any.
+    fn clip(
+        &self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> Option<(Range<usize>, Range<usize>)> {
+        let clip_axis = |start: i32, len: u32, bound: u32| {
+            let start = start as i64;
+            let end = (start + len as i64).min(bound as i64);
+            let start = start.max(0);
+            (start < end).then_some(start as usize..end as usize)
+        };
+
+        Some((
+            clip_axis(x, width, self.width)?,
+            clip_axis(y, height, self.height)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 8;
+    const HEIGHT: u32 = 6;
+    const COLOR: u32 = 0xffff0000;
+
+    /// Runs `draw` on a blank `WIDTH`x`HEIGHT` canvas and returns the coordinates of every pixel
+    /// it set, row by row.
+    fn drawn(draw: impl FnOnce(&mut Canvas<'_>)) -> Vec<(usize, usize)> {
+        let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+        draw(&mut Canvas::new(&mut pixels, WIDTH, HEIGHT));
+        pixels
+            .iter()
+            .enumerate()
+            .filter(|&(_, &pixel)| pixel != 0)
+            .map(|(i, _)| (i % WIDTH as usize, i / WIDTH as usize))
+            .collect()
+    }
+
+    fn rect(x: Range<usize>, y: Range<usize>) -> Vec<(usize, usize)> {
+        y.flat_map(|y| x.clone().map(move |x| (x, y))).collect()
+    }
+
+    fn clip(x: i32, y: i32, width: u32, height: u32) -> Option<(Range<usize>, Range<usize>)> {
+        let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+        Canvas::new(&mut pixels, WIDTH, HEIGHT).clip(x, y, width, height)
+    }
+
+    #[test]
+    fn clip_inside() {
+        assert_eq!(clip(1, 2, 3, 2), Some((1..4, 2..4)));
+        assert_eq!(clip(0, 0, WIDTH, HEIGHT), Some((0..8, 0..6)));
+    }
+
+    #[test]
+    fn clip_negative_origin() {
+        assert_eq!(clip(-2, -1, 4, 3), Some((0..2, 0..2)));
+    }
+
+    #[test]
+    fn clip_partly_off_canvas() {
+        assert_eq!(clip(6, 4, 5, 5), Some((6..8, 4..6)));
+        assert_eq!(clip(-3, -3, 20, 20), Some((0..8, 0..6)));
+    }
+
+    #[test]
+    fn clip_fully_off_canvas() {
+        assert_eq!(clip(-4, 0, 4, 1), None);
+        assert_eq!(clip(8, 0, 4, 1), None);
+        assert_eq!(clip(0, -2, 1, 2), None);
+        assert_eq!(clip(0, 6, 1, 2), None);
+    }
+
+    #[test]
+    fn clip_empty() {
+        assert_eq!(clip(2, 2, 0, 3), None);
+        assert_eq!(clip(2, 2, 3, 0), None);
+    }
+
+    #[test]
+    fn clip_without_overflow() {
+        // The far edge lies beyond `i32::MAX` here, which must not wrap around.
+        assert_eq!(clip(i32::MAX, i32::MAX, u32::MAX, u32::MAX), None);
+        assert_eq!(clip(1, 1, u32::MAX, u32::MAX), Some((1..8, 1..6)));
+        assert_eq!(
+            clip(i32::MIN, i32::MIN, u32::MAX, u32::MAX),
+            Some((0..8, 0..6))
+        );
+        assert_eq!(clip(i32::MIN, 0, u32::MAX / 2, 1), None);
+    }
+
+    #[test]
+    fn fill_rect_inside() {
+        let pixels = drawn(|canvas| canvas.fill_rect(2, 1, 3, 2, COLOR));
+        assert_eq!(pixels, rect(2..5, 1..3));
+    }
+
+    #[test]
+    fn fill_rect_clipped_left() {
+        let pixels = drawn(|canvas| canvas.fill_rect(-2, 1, 3, 2, COLOR));
+        assert_eq!(pixels, rect(0..1, 1..3));
+    }
+
+    #[test]
+    fn fill_rect_clipped_right() {
+        let pixels = drawn(|canvas| canvas.fill_rect(6, 1, 3, 2, COLOR));
+        assert_eq!(pixels, rect(6..8, 1..3));
+    }
+
+    #[test]
+    fn fill_rect_clipped_top() {
+        let pixels = drawn(|canvas| canvas.fill_rect(2, -1, 3, 2, COLOR));
+        assert_eq!(pixels, rect(2..5, 0..1));
+    }
+
+    #[test]
+    fn fill_rect_clipped_bottom() {
+        let pixels = drawn(|canvas| canvas.fill_rect(2, 5, 3, 2, COLOR));
+        assert_eq!(pixels, rect(2..5, 5..6));
+    }
+
+    #[test]
+    fn fill_rect_off_canvas() {
+        assert!(drawn(|canvas| canvas.fill_rect(-3, 1, 3, 2, COLOR)).is_empty());
+        assert!(drawn(|canvas| canvas.fill_rect(1, 6, 3, 2, COLOR)).is_empty());
+    }
+
+    /// Checks that a line from `from` to `to` is continuous and covers exactly one pixel per step
+    /// along its major axis, from one end to the other.
+    fn check_line(from: (i32, i32), to: (i32, i32)) {
+        let pixels = drawn(|canvas| canvas.draw_line(from, to, COLOR));
+        let as_point = |(x, y): (i32, i32)| (x as usize, y as usize);
+        assert!(pixels.contains(&as_point(from)), "{from:?} -> {to:?}");
+        assert!(pixels.contains(&as_point(to)), "{from:?} -> {to:?}");
+
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let steps = dx.abs().max(dy.abs()) as usize;
+        assert_eq!(pixels.len(), steps + 1, "{from:?} -> {to:?}");
+
+        // Each pixel along the major axis appears once, and the minor axis never jumps.
+        let major = |&(x, y): &(usize, usize)| if dx.abs() >= dy.abs() { x } else { y };
+        let mut pixels = pixels;
+        pixels.sort_by_key(major);
+        for pair in pixels.windows(2) {
+            assert_eq!(major(&pair[1]) - major(&pair[0]), 1, "{from:?} -> {to:?}");
+            assert!(pair[0].0.abs_diff(pair[1].0) <= 1, "{from:?} -> {to:?}");
+            assert!(pair[0].1.abs_diff(pair[1].1) <= 1, "{from:?} -> {to:?}");
+        }
+    }
+
+    #[test]
+    fn draw_line_octants() {
+        // One end in each octant around the center, clockwise from east.
+        let center = (3, 2);
+        for to in [
+            (7, 3),
+            (5, 5),
+            (2, 5),
+            (0, 4),
+            (0, 1),
+            (2, 0),
+            (4, 0),
+            (7, 1),
+        ] {
+            check_line(center, to);
+            check_line(to, center);
+        }
+    }
+
+    #[test]
+    fn draw_line_axes_and_diagonals() {
+        let center = (3, 2);
+        for to in [
+            (7, 2),
+            (3, 5),
+            (0, 2),
+            (3, 0),
+            (6, 5),
+            (0, 5),
+            (1, 0),
+            (5, 0),
+        ] {
+            check_line(center, to);
+        }
+    }
+
+    #[test]
+    fn draw_line_single_point() {
+        let pixels = drawn(|canvas| canvas.draw_line((4, 3), (4, 3), COLOR));
+        assert_eq!(pixels, [(4, 3)]);
+    }
+
+    #[test]
+    fn draw_line_clipped() {
+        let pixels = drawn(|canvas| canvas.draw_line((-2, 1), (10, 1), COLOR));
+        assert_eq!(pixels, rect(0..8, 1..2));
+    }
+
+    /// A `width`x`height` source whose pixels encode their own position, so that wrong offsets
+    /// show up as wrong values.
+    fn numbered_source(width: u32, height: u32) -> Vec<u32> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| 0x100 * (y + 1) + x + 1))
+            .collect()
+    }
+
+    fn blitted(x: i32, y: i32, width: u32, height: u32) -> Vec<u32> {
+        let src = numbered_source(width, height);
+        let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+        Canvas::new(&mut pixels, WIDTH, HEIGHT).blit(x, y, &src, width, height);
+        pixels
+    }
+
+    fn at(pixels: &[u32], x: usize, y: usize) -> u32 {
+        pixels[y * WIDTH as usize + x]
+    }
+
+    #[test]
+    fn blit_inside() {
+        let pixels = blitted(2, 1, 3, 2);
+        assert_eq!(at(&pixels, 2, 1), 0x101);
+        assert_eq!(at(&pixels, 4, 2), 0x203);
+        assert_eq!(pixels.iter().filter(|&&pixel| pixel != 0).count(), 6);
+    }
+
+    #[test]
+    fn blit_clipped_top_left() {
+        // Only source rows 2 and 3 and columns 1 to 3 are visible.
+        let pixels = blitted(-1, -2, 4, 4);
+        assert_eq!(at(&pixels, 0, 0), 0x302);
+        assert_eq!(at(&pixels, 2, 0), 0x304);
+        assert_eq!(at(&pixels, 0, 1), 0x402);
+        assert_eq!(at(&pixels, 2, 1), 0x404);
+        assert_eq!(at(&pixels, 3, 0), 0);
+        assert_eq!(at(&pixels, 0, 2), 0);
+        assert_eq!(pixels.iter().filter(|&&pixel| pixel != 0).count(), 6);
+    }
+
+    #[test]
+    fn blit_clipped_bottom_right() {
+        // Only source row 0 and columns 0 and 1 are visible.
+        let pixels = blitted(6, 5, 4, 3);
+        assert_eq!(at(&pixels, 6, 5), 0x101);
+        assert_eq!(at(&pixels, 7, 5), 0x102);
+        assert_eq!(pixels.iter().filter(|&&pixel| pixel != 0).count(), 2);
+    }
+
+    #[test]
+    fn blit_off_canvas() {
+        assert!(blitted(-4, 0, 4, 4).iter().all(|&pixel| pixel == 0));
+        assert!(blitted(0, 6, 4, 4).iter().all(|&pixel| pixel == 0));
+    }
+}
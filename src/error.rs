@@ -0,0 +1,145 @@
+use std::{error, fmt, io, result};
+
+use ash::vk;
+use wayland_client::{DispatchError, backend::WaylandError};
+
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    VulkanLoading(ash::LoadingError),
+    Vulkan(vk::Result),
+    DeviceLost,
+    NoVulkanDevice,
+    NoSuitableMemoryType,
+    MissingGlobal {
+        interface: &'static str,
+    },
+    UnsupportedGlobalVersion {
+        interface: &'static str,
+        version: u32,
+    },
+    ForeignObject {
+        interface: &'static str,
+    },
+    SwapchainCreation(vk::Result),
+    UnsupportedSurfaceFormat(vk::Format),
+    NoSurfaceFormat,
+    ShmPoolTooSmall,
+    ShmPoolTooLarge,
+    InvalidInputLog {
+        line: usize,
+    },
+    NoStateDir,
+    NoCacheDir,
+    Io(io::Error),
+    Wayland(WaylandError),
+    Dispatch(DispatchError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VulkanLoading(_) => write!(f, "failed to load vulkan"),
+            Self::Vulkan(_) => write!(f, "vulkan call failed"),
+            Self::DeviceLost => write!(f, "vulkan device lost"),
+            Self::NoVulkanDevice => write!(f, "no usable vulkan devices available"),
+            Self::NoSuitableMemoryType => {
+                write!(f, "no suitable vulkan memory type for allocation")
+            }
+            Self::MissingGlobal { interface } => {
+                write!(f, "compositor does not support `{interface}`")
+            }
+            Self::UnsupportedGlobalVersion { interface, version } => {
+                write!(
+                    f,
+                    "compositor only supports `{interface}` version {version}, which is too old"
+                )
+            }
+            Self::ForeignObject { interface } => {
+                write!(
+                    f,
+                    "`{interface}` object is dead or belongs to another connection"
+                )
+            }
+            Self::SwapchainCreation(_) => write!(f, "failed to create swapchain"),
+            Self::UnsupportedSurfaceFormat(format) => {
+                write!(f, "surface does not support format {format:?}")
+            }
+            Self::NoSurfaceFormat => write!(f, "surface supports no usable formats"),
+            Self::ShmPoolTooSmall => write!(f, "shared memory too small for requested buffers"),
+            Self::ShmPoolTooLarge => write!(f, "shared memory pool too large for `wl_shm`"),
+            Self::InvalidInputLog { line } => write!(f, "malformed input log at line {line}"),
+            Self::NoStateDir => write!(f, "neither `XDG_STATE_HOME` nor `HOME` is set"),
+            Self::NoCacheDir => write!(f, "neither `XDG_CACHE_HOME` nor `HOME` is set"),
+            Self::Io(_) => write!(f, "I/O error"),
+            Self::Wayland(_) => write!(f, "wayland connection error"),
+            Self::Dispatch(_) => write!(f, "failed to dispatch wayland events"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::VulkanLoading(err) => Some(err),
+            Self::Vulkan(err) | Self::SwapchainCreation(err) => Some(err),
+            Self::Io(err) => Some(err),
+            Self::Wayland(err) => Some(err),
+            Self::Dispatch(err) => Some(err),
+            Self::DeviceLost
+            | Self::NoVulkanDevice
+            | Self::NoSuitableMemoryType
+            | Self::MissingGlobal { .. }
+            | Self::UnsupportedGlobalVersion { .. }
+            | Self::ForeignObject { .. }
+            | Self::UnsupportedSurfaceFormat(_)
+            | Self::NoSurfaceFormat
+            | Self::ShmPoolTooSmall
+            | Self::ShmPoolTooLarge
+            | Self::InvalidInputLog { .. }
+            | Self::NoStateDir
+            | Self::NoCacheDir => None,
+        }
+    }
+}
+
+impl From<ash::LoadingError> for Error {
+    fn from(err: ash::LoadingError) -> Self {
+        Self::VulkanLoading(err)
+    }
+}
+
+impl From<vk::Result> for Error {
+    fn from(err: vk::Result) -> Self {
+        // There's no recovering from this one, so it gets singled out.
+        match err {
+            vk::Result::ERROR_DEVICE_LOST => Self::DeviceLost,
+            err => Self::Vulkan(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<rustix::io::Errno> for Error {
+    fn from(err: rustix::io::Errno) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+impl From<WaylandError> for Error {
+    fn from(err: WaylandError) -> Self {
+        Self::Wayland(err)
+    }
+}
+
+impl From<DispatchError> for Error {
+    fn from(err: DispatchError) -> Self {
+        Self::Dispatch(err)
+    }
+}
@@ -0,0 +1,424 @@
+use std::{sync::Arc, time::Instant};
+
+use ash::vk;
+
+use crate::{
+    error::Result,
+    render,
+    vulkan::{self, pipeline_cache::PipelineCache, shaders::ShaderCache},
+};
+
+// Prebuilt like the triangle's shaders; see the note in `render`.
+const GRADIENT_COMP: &[u8] = include_bytes!("shaders/gradient.comp.spv");
+
+/// The size of the generated image, which is stretched over the whole window.
+const SIZE: u32 = 256;
+
+/// Must match `local_size_x` and `local_size_y` in the shader.
+const LOCAL_SIZE: [u32; 3] = [8, 8, 1];
+
+/// The storage image format, which every device supports for storage and blitting from.
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Push constants as laid out in the shader.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Params {
+    size: [u32; 2],
+    time: f32,
+}
+
+/// A gradient generated by a compute shader every frame and blitted onto swapchain images.
+///
+/// The shader runs on the device's compute queue, which may be separate from the graphics queue.
+/// The image is shared between the two families concurrently, so no ownership transfers are
+/// needed, and the queues take turns through timeline semaphores: generating waits for the last
+/// frame that blitted from the image, and the next frame waits for the image to be generated.
+pub struct Gradient {
+    device: Arc<vulkan::Device>,
+    image: vulkan::Image,
+    view: vk::ImageView,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline: vulkan::ComputePipeline,
+    commands: vulkan::CommandRecorder,
+    /// Counts the images generated so far.
+    timeline: vk::Semaphore,
+    generated: u64,
+    start: Instant,
+}
+
+impl Gradient {
+    /// Checks whether images in `format` can be blitted to, which swapchain images must also have
+    /// been created for.
+    pub fn supports_target(device: &vulkan::Device, format: vk::Format) -> bool {
+        let properties = unsafe {
+            device
+                .instance()
+                .instance()
+                .get_physical_device_format_properties(device.physical_device(), format)
+        };
+        properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::BLIT_DST)
+    }
+
+    pub fn new(allocator: &Arc<vulkan::Allocator>, pipeline_cache: &PipelineCache) -> Result<Self> {
+        let device = allocator.device();
+        let allocation_callbacks = device.allocation_callbacks();
+
+        let queue_family_indices = [
+            device.compute_queue_family_index(),
+            device.graphics_queue_family_index(),
+        ];
+        let (sharing_mode, queue_family_index_count) =
+            if queue_family_indices[0] == queue_family_indices[1] {
+                (vk::SharingMode::EXCLUSIVE, 1)
+            } else {
+                (vk::SharingMode::CONCURRENT, 2)
+            };
+        let image = vulkan::Image::new(
+            allocator,
+            &vk::ImageCreateInfo {
+                image_type: vk::ImageType::TYPE_2D,
+                format: FORMAT,
+                extent: vk::Extent3D {
+                    width: SIZE,
+                    height: SIZE,
+                    depth: 1,
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+                sharing_mode,
+                queue_family_index_count,
+                p_queue_family_indices: queue_family_indices.as_ptr(),
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                ..Default::default()
+            },
+            vulkan::MemoryLocation::GpuOnly,
+        )?;
+
+        let commands = vulkan::CommandRecorder::new(device, queue_family_indices[0], 1)?;
+
+        let set_layout = unsafe {
+            device.device().create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    binding_count: 1,
+                    p_bindings: &vk::DescriptorSetLayoutBinding {
+                        binding: 0,
+                        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                        descriptor_count: 1,
+                        stage_flags: vk::ShaderStageFlags::COMPUTE,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?
+        };
+        // Pipelines don't need their shader modules once created, so the cache can go right away.
+        let pipeline = ShaderCache::new(device)
+            .get(&render::shader_source("gradient.comp.spv", GRADIENT_COMP))
+            .and_then(|shader| {
+                vulkan::ComputePipeline::new(
+                    device,
+                    shader,
+                    &[set_layout],
+                    size_of::<Params>() as u32,
+                    pipeline_cache.cache(),
+                )
+            });
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                unsafe {
+                    device
+                        .device()
+                        .destroy_descriptor_set_layout(set_layout, allocation_callbacks)
+                };
+                return Err(err);
+            }
+        };
+
+        // Anything created from here on is cleaned up by `Drop` if a later step fails, as
+        // destroying null handles does nothing.
+        let mut gradient = Self {
+            device: Arc::clone(device),
+            image,
+            view: vk::ImageView::null(),
+            set_layout,
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline,
+            commands,
+            timeline: vk::Semaphore::null(),
+            generated: 0,
+            start: Instant::now(),
+        };
+        gradient.timeline = device.create_timeline_semaphore(0)?;
+
+        unsafe {
+            gradient.view = device.device().create_image_view(
+                &vk::ImageViewCreateInfo {
+                    image: gradient.image.image(),
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    format: FORMAT,
+                    subresource_range: whole_image(),
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?;
+            gradient.descriptor_pool = device.device().create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo {
+                    max_sets: 1,
+                    pool_size_count: 1,
+                    p_pool_sizes: &vk::DescriptorPoolSize {
+                        ty: vk::DescriptorType::STORAGE_IMAGE,
+                        descriptor_count: 1,
+                    },
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?;
+            gradient.descriptor_set =
+                device
+                    .device()
+                    .allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                        descriptor_pool: gradient.descriptor_pool,
+                        descriptor_set_count: 1,
+                        p_set_layouts: &gradient.set_layout,
+                        ..Default::default()
+                    })?[0];
+            device.device().update_descriptor_sets(
+                &[vk::WriteDescriptorSet {
+                    dst_set: gradient.descriptor_set,
+                    dst_binding: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    p_image_info: &vk::DescriptorImageInfo {
+                        image_view: gradient.view,
+                        image_layout: vk::ImageLayout::GENERAL,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }],
+                &[],
+            );
+        }
+
+        Ok(gradient)
+    }
+
+    /// Returns the timeline semaphore that [`Self::generate`] signals.
+    pub fn timeline(&self) -> vk::Semaphore {
+        self.timeline
+    }
+
+    /// Submits the shader generating the next image to the compute queue, once timeline semaphore
+    /// `after` has reached `after_value`, and returns the value the gradient's timeline reaches
+    /// once the image is ready.
+    ///
+    /// `after` must be signaled once nothing reads from the image anymore, e.g. by the frames
+    /// blitting from it.
+    pub fn generate(&mut self, after: vk::Semaphore, after_value: u64) -> Result<u64> {
+        // With only one command buffer, the last generation has to be done before recording the
+        // next. The frame that blitted it waited for it, so this rarely blocks.
+        self.device
+            .wait_timeline_semaphore(self.timeline, self.generated, None)?;
+
+        let params = Params {
+            size: [SIZE, SIZE],
+            time: self.start.elapsed().as_secs_f32(),
+        };
+        let device = self.device.device();
+        unsafe {
+            self.commands.record(0, |cmd| {
+                // The last image has been blitted by now, so its contents can go.
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[vk::ImageMemoryBarrier {
+                        src_access_mask: vk::AccessFlags::empty(),
+                        dst_access_mask: vk::AccessFlags::SHADER_WRITE,
+                        old_layout: vk::ImageLayout::UNDEFINED,
+                        new_layout: vk::ImageLayout::GENERAL,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        image: self.image.image(),
+                        subresource_range: whole_image(),
+                        ..Default::default()
+                    }],
+                );
+                self.pipeline.cmd_dispatch(
+                    cmd,
+                    &[self.descriptor_set],
+                    as_bytes(&params),
+                    vulkan::workgroup_count([SIZE, SIZE, 1], LOCAL_SIZE),
+                );
+                Ok(())
+            })?;
+        }
+
+        let value = self.generated + 1;
+        let wait_values = [after_value];
+        let signal_values = [value];
+        let timeline_submit_info = vk::TimelineSemaphoreSubmitInfo {
+            wait_semaphore_value_count: wait_values.len() as u32,
+            p_wait_semaphore_values: wait_values.as_ptr(),
+            signal_semaphore_value_count: signal_values.len() as u32,
+            p_signal_semaphore_values: signal_values.as_ptr(),
+            ..Default::default()
+        };
+        unsafe {
+            self.device.compute_queue().submit(
+                &[vk::SubmitInfo {
+                    p_next: (&raw const timeline_submit_info).cast(),
+                    wait_semaphore_count: 1,
+                    p_wait_semaphores: &after,
+                    p_wait_dst_stage_mask: &vk::PipelineStageFlags::COMPUTE_SHADER,
+                    command_buffer_count: 1,
+                    p_command_buffers: &self.commands.command_buffer(0),
+                    signal_semaphore_count: 1,
+                    p_signal_semaphores: &self.timeline,
+                    ..Default::default()
+                }],
+                vk::Fence::null(),
+            )?;
+        }
+
+        self.generated = value;
+        Ok(value)
+    }
+
+    /// Records a blit of the generated image over all of `dst`, which is `extent` in size, leaving
+    /// it ready to present.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be a graphics command buffer in the recording state, outside of any render pass.
+    /// Its submission must wait for the value returned by [`Self::generate`] on the timeline, and
+    /// for `dst` to be acquired, both at the transfer stage. `dst` must have been created for
+    /// transfers to in a format [`Self::supports_target`] accepts.
+    pub unsafe fn cmd_blit(&self, cmd: vk::CommandBuffer, dst: vk::Image, extent: vk::Extent2D) {
+        let device = self.device.device();
+        let to_transfer_dst = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: dst,
+            subresource_range: whole_image(),
+            ..Default::default()
+        };
+        // Presenting waits on a semaphore, which makes the writes visible already.
+        let to_present_src = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::empty(),
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..to_transfer_dst
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer_dst],
+            );
+            device.cmd_blit_image(
+                cmd,
+                self.image.image(),
+                vk::ImageLayout::GENERAL,
+                dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: [
+                        vk::Offset3D::default(),
+                        vk::Offset3D {
+                            x: SIZE as i32,
+                            y: SIZE as i32,
+                            z: 1,
+                        },
+                    ],
+                    dst_subresource: subresource,
+                    dst_offsets: [
+                        vk::Offset3D::default(),
+                        vk::Offset3D {
+                            x: extent.width as i32,
+                            y: extent.height as i32,
+                            z: 1,
+                        },
+                    ],
+                }],
+                vk::Filter::LINEAR,
+            );
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present_src],
+            );
+        }
+    }
+}
+
+impl Drop for Gradient {
+    fn drop(&mut self) {
+        // The owner is responsible for waiting for the frames blitting from the image, but the
+        // shader writing it is ours to wait for.
+        if self.generated > 0 {
+            let _ = self
+                .device
+                .wait_timeline_semaphore(self.timeline, self.generated, None);
+        }
+
+        let device = self.device.device();
+        let allocation_callbacks = self.device.allocation_callbacks();
+        unsafe {
+            device.destroy_semaphore(self.timeline, allocation_callbacks);
+            // Destroying the pool frees the set along with it.
+            device.destroy_descriptor_pool(self.descriptor_pool, allocation_callbacks);
+            device.destroy_descriptor_set_layout(self.set_layout, allocation_callbacks);
+            device.destroy_image_view(self.view, allocation_callbacks);
+        }
+    }
+}
+
+fn whole_image() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+fn as_bytes(params: &Params) -> &[u8] {
+    // SAFETY: `Params` is plain old data without padding.
+    unsafe { std::slice::from_raw_parts((params as *const Params).cast(), size_of::<Params>()) }
+}
@@ -0,0 +1,429 @@
+use std::{fs, path::Path, sync::Arc};
+
+use ash::vk;
+use log::{info, warn};
+
+use crate::{
+    error::Result,
+    options::{Demo, Options},
+    render::{Framebuffers, Renderer},
+    test_pattern,
+    vulkan::{self, pipeline_cache::PipelineCache, upload::Uploader},
+    window::{self, srgb_to_linear},
+};
+
+/// The size to render at, the same as a window's initial size.
+const WIDTH: u32 = 500;
+const HEIGHT: u32 = 500;
+
+/// Both formats rendered to have four bytes per pixel.
+const PIXEL_SIZE: vk::DeviceSize = 4;
+
+/// Renders a single frame as the window would, without connecting to a compositor, and writes it
+/// to `path` as a PPM image.
+pub fn run(options: &Options, path: &Path) -> Result<()> {
+    let device = create_device(options)?;
+
+    if options.demo == Demo::Gradient {
+        warn!("the gradient demo is only shown in windows, rendering the clear color instead");
+    }
+
+    let mut renderer = HeadlessRenderer::for_demo(&device, options, WIDTH, HEIGHT)?;
+    let pixels = renderer.render_demo(options)?;
+
+    write_ppm(path, WIDTH, HEIGHT, &pixels)?;
+    info!("wrote {WIDTH}x{HEIGHT} frame to {}", path.display());
+
+    Ok(())
+}
+
+/// Creates a device for rendering offscreen, without any surface to present to.
+pub fn create_device(options: &Options) -> Result<Arc<vulkan::Device>> {
+    let instance = window::create_vk_instance(options)?;
+    instance.create_device(
+        &vulkan::DeviceFeatures::default(),
+        |_, _, properties| {
+            properties
+                .queue_flags
+                .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::TRANSFER)
+        },
+        // Nothing is presented.
+        |_, _, _| true,
+    )
+}
+
+/// Renders into an offscreen image and reads the result back to the CPU, one frame at a time.
+pub struct HeadlessRenderer {
+    device: Arc<vulkan::Device>,
+    allocator: Arc<vulkan::Allocator>,
+    renderer: Renderer,
+    /// Dropped before `image`, whose view it holds.
+    framebuffers: Framebuffers,
+    image: vulkan::Image,
+    /// Receives the rendered image, tightly packed.
+    readback: vulkan::Buffer,
+    uploader: Uploader,
+    /// An image copied over every frame, along with the value of the uploader's timeline
+    /// semaphore once it is uploaded.
+    overlay: Option<(vulkan::Image, u64)>,
+    commands: vulkan::CommandRecorder,
+    /// Counts the finished frames, as with [`vulkan::FrameRing`].
+    timeline: vk::Semaphore,
+    rendered_frames: u64,
+    extent: vk::Extent2D,
+}
+
+impl HeadlessRenderer {
+    /// Creates a renderer for `width`x`height` RGBA images, set up like a window's renderer would
+    /// be for `options`.
+    pub fn new(
+        device: &Arc<vulkan::Device>,
+        options: &Options,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        // Both are supported as color attachments and copy sources everywhere.
+        let format = if options.srgb {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        };
+
+        let allocator = vulkan::Allocator::new(device);
+        let pipeline_cache = Arc::new(PipelineCache::load(device)?);
+        let renderer = Renderer::new(
+            &allocator,
+            &pipeline_cache,
+            format,
+            options.depth,
+            options.samples,
+            options.dynamic_rendering,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )?;
+
+        let image = vulkan::Image::new(
+            &allocator,
+            &vk::ImageCreateInfo {
+                image_type: vk::ImageType::TYPE_2D,
+                format,
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                sharing_mode: vk::SharingMode::EXCLUSIVE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                ..Default::default()
+            },
+            vulkan::MemoryLocation::GpuOnly,
+        )?;
+        let framebuffers = renderer.create_framebuffers(&[image.image()], width, height)?;
+
+        let readback = vulkan::Buffer::new(
+            &allocator,
+            width as vk::DeviceSize * height as vk::DeviceSize * PIXEL_SIZE,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vulkan::MemoryLocation::GpuToCpu,
+        )?;
+
+        let commands =
+            vulkan::CommandRecorder::new(device, device.graphics_queue_family_index(), 1)?;
+        let timeline = device.create_timeline_semaphore(0)?;
+
+        let uploader = Uploader::new(&allocator)?;
+
+        Ok(Self {
+            device: Arc::clone(device),
+            allocator,
+            renderer,
+            framebuffers,
+            image,
+            readback,
+            uploader,
+            overlay: None,
+            commands,
+            timeline,
+            rendered_frames: 0,
+            extent: vk::Extent2D { width, height },
+        })
+    }
+
+    /// Creates a renderer for the demo selected in `options`, as [`Self::render_demo`] draws it.
+    pub fn for_demo(
+        device: &Arc<vulkan::Device>,
+        options: &Options,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let mut renderer = Self::new(device, options, width, height)?;
+
+        // Windows show the test pattern in a subsurface covering all of their content.
+        if options.demo == Demo::TestPattern {
+            let mut pattern = vec![0; (width * height) as usize];
+            test_pattern::draw(&mut pattern, width, height);
+            renderer.set_overlay(&argb_to_rgba(&pattern))?;
+        }
+
+        Ok(renderer)
+    }
+
+    /// Sets an image to cover every frame rendered from now on, the way a subsurface covering the
+    /// whole window would. `pixels` holds rows of RGBA bytes, top to bottom, at the frame's size.
+    ///
+    /// The image is uploaded in the background, and the next frame waits for it to arrive.
+    pub fn set_overlay(&mut self, pixels: &[u8]) -> Result<()> {
+        let vk::Extent2D { width, height } = self.extent;
+        let image = vulkan::Image::new(
+            &self.allocator,
+            &vk::ImageCreateInfo {
+                image_type: vk::ImageType::TYPE_2D,
+                format: self.image.format(),
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
+                sharing_mode: vk::SharingMode::EXCLUSIVE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                ..Default::default()
+            },
+            vulkan::MemoryLocation::GpuOnly,
+        )?;
+
+        self.uploader
+            .upload_image(&image, pixels, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)?;
+        // There is always something to submit right after an upload.
+        let uploaded = self.uploader.flush()?.unwrap_or_default();
+
+        // Frames using the old overlay were all waited for.
+        self.overlay = Some((image, uploaded));
+        Ok(())
+    }
+
+    /// Renders a frame, clearing to `clear_color` and drawing a triangle over it if `triangle` is
+    /// set, and returns its pixels as rows of RGBA bytes, top to bottom. The overlay, if any, ends
+    /// up on top of everything.
+    ///
+    /// This waits for the GPU to finish.
+    pub fn render(&mut self, clear_color: [f32; 4], triangle: bool) -> Result<Vec<u8>> {
+        let device = self.device.device();
+        let vk::Extent2D { width, height } = self.extent;
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let color_layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        // The previous frame was waited for, so the command buffer is free again.
+        unsafe {
+            self.commands.record(0, |cmd| {
+                // The overlay may have been uploaded on another queue family, which has to hand
+                // it over first.
+                self.uploader.cmd_acquire(cmd);
+
+                self.renderer
+                    .cmd_draw(cmd, &self.framebuffers, 0, clear_color, triangle, false);
+
+                if let Some((overlay, _)) = &self.overlay {
+                    // Everything rendered is covered anyway, but the copy mustn't race the
+                    // render pass writing it.
+                    device.cmd_pipeline_barrier(
+                        cmd,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[vk::ImageMemoryBarrier {
+                            src_access_mask: vk::AccessFlags::empty(),
+                            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            image: self.image.image(),
+                            subresource_range: color_range,
+                            ..Default::default()
+                        }],
+                    );
+                    device.cmd_copy_image(
+                        cmd,
+                        overlay.image(),
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        self.image.image(),
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &[vk::ImageCopy {
+                            src_subresource: color_layers,
+                            src_offset: vk::Offset3D::default(),
+                            dst_subresource: color_layers,
+                            dst_offset: vk::Offset3D::default(),
+                            extent: overlay.extent(),
+                        }],
+                    );
+                    device.cmd_pipeline_barrier(
+                        cmd,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[vk::ImageMemoryBarrier {
+                            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            image: self.image.image(),
+                            subresource_range: color_range,
+                            ..Default::default()
+                        }],
+                    );
+                }
+
+                // The renderer leaves the image ready to copy from.
+                device.cmd_copy_image_to_buffer(
+                    cmd,
+                    self.image.image(),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.readback.buffer(),
+                    &[vk::BufferImageCopy {
+                        buffer_offset: 0,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: color_layers,
+                        image_offset: vk::Offset3D::default(),
+                        image_extent: vk::Extent3D {
+                            width,
+                            height,
+                            depth: 1,
+                        },
+                    }],
+                );
+
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[vk::MemoryBarrier {
+                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        dst_access_mask: vk::AccessFlags::HOST_READ,
+                        ..Default::default()
+                    }],
+                    &[],
+                    &[],
+                );
+                Ok(())
+            })?;
+        }
+
+        // Copying the overlay has to wait for its upload.
+        let (wait_semaphores, wait_values): (Vec<_>, Vec<_>) = self
+            .overlay
+            .iter()
+            .map(|&(_, uploaded)| (self.uploader.timeline(), uploaded))
+            .unzip();
+        let wait_stages = vec![vk::PipelineStageFlags::TRANSFER; wait_semaphores.len()];
+
+        let frame_number = self.rendered_frames + 1;
+        let timeline_submit_info = vk::TimelineSemaphoreSubmitInfo {
+            wait_semaphore_value_count: wait_values.len() as u32,
+            p_wait_semaphore_values: wait_values.as_ptr(),
+            signal_semaphore_value_count: 1,
+            p_signal_semaphore_values: &frame_number,
+            ..Default::default()
+        };
+        unsafe {
+            self.device.graphics_queue().submit(
+                &[vk::SubmitInfo {
+                    p_next: (&raw const timeline_submit_info).cast(),
+                    wait_semaphore_count: wait_semaphores.len() as u32,
+                    p_wait_semaphores: wait_semaphores.as_ptr(),
+                    p_wait_dst_stage_mask: wait_stages.as_ptr(),
+                    command_buffer_count: 1,
+                    p_command_buffers: &self.commands.command_buffer(0),
+                    signal_semaphore_count: 1,
+                    p_signal_semaphores: &self.timeline,
+                    ..Default::default()
+                }],
+                vk::Fence::null(),
+            )?;
+        }
+        self.rendered_frames = frame_number;
+
+        self.device
+            .wait_timeline_semaphore(self.timeline, frame_number, None)?;
+
+        let mut pixels = vec![0; self.readback.size() as usize];
+        self.readback.read(0, &mut pixels);
+        Ok(pixels)
+    }
+
+    /// Renders a frame of the demo selected in `options`, as [`Self::render`] does.
+    ///
+    /// The gradient demo isn't supported, so the clear color shows instead.
+    pub fn render_demo(&mut self, options: &Options) -> Result<Vec<u8>> {
+        // As with an sRGB swapchain, the hardware does the encoding.
+        let [r, g, b] = if options.srgb {
+            options.clear_color.map(srgb_to_linear)
+        } else {
+            options.clear_color
+        };
+        self.render([r, g, b, 1.0], options.demo == Demo::Triangle)
+    }
+}
+
+impl Drop for HeadlessRenderer {
+    fn drop(&mut self) {
+        // A failed render may have left a frame running.
+        let _ = self
+            .device
+            .wait_timeline_semaphore(self.timeline, self.rendered_frames, None);
+        unsafe {
+            self.device
+                .device()
+                .destroy_semaphore(self.timeline, self.device.allocation_callbacks());
+        }
+    }
+}
+
+/// Converts premultiplied ARGB pixels, as drawn for shm buffers, to rows of RGBA bytes.
+///
+/// Opaque pixels come out the same as the compositor would show them, which is all the test
+/// pattern has.
+fn argb_to_rgba(pixels: &[u32]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|&pixel| {
+            let [a, r, g, b] = pixel.to_be_bytes();
+            [r, g, b, a]
+        })
+        .collect()
+}
+
+/// Writes rows of RGBA bytes to `path` as a binary PPM, which has no alpha channel.
+fn write_ppm(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+    ppm.extend(rgba.chunks_exact(4).flat_map(|pixel| &pixel[..3]));
+    fs::write(path, ppm)?;
+    Ok(())
+}
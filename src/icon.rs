@@ -0,0 +1,144 @@
+use std::os::fd::OwnedFd;
+
+use rustix::fs::{MemfdFlags, SealFlags, fcntl_add_seals, ftruncate, memfd_create};
+use wayland_client::{QueueHandle, protocol::wl_shm::WlShm};
+use wayland_protocols::xdg::toplevel_icon::v1::client::{
+    xdg_toplevel_icon_manager_v1::XdgToplevelIconManagerV1, xdg_toplevel_icon_v1::XdgToplevelIconV1,
+};
+
+use crate::{app::App, draw::Canvas, error::Result, shm::BufferPool};
+
+/// Edge sizes of the built-in icon, in pixels.
+const DEFAULT_SIZES: [u32; 4] = [16, 32, 48, 64];
+
+const BACKGROUND_COLOR: u32 = 0xff303030;
+const TILE_COLORS: [u32; 4] = [0xffcc1a1a, 0xff1ab333, 0xff1a4de6, 0xffe6cc1a];
+
+/// An icon for docks and taskbars to show for a window.
+///
+/// Compositors may pick either the named icon or the pixel data, so it's best to provide both.
+#[derive(Clone, Default)]
+pub struct Icon {
+    /// The name of an icon to look up in the user's icon theme.
+    pub name: Option<String>,
+    pub images: Vec<IconImage>,
+}
+
+/// A square ARGB image making up an icon.
+#[derive(Clone)]
+pub struct IconImage {
+    /// The edge length, in pixels.
+    pub size: u32,
+    /// The scale the image is meant for, as with `wl_surface.set_buffer_scale`.
+    pub scale: u32,
+    /// Premultiplied ARGB pixels, row by row. There must be exactly `size * size` of them.
+    pub pixels: Vec<u32>,
+}
+
+impl Icon {
+    /// Our own icon: a grid of colored tiles, rendered at a few common sizes.
+    pub fn builtin(name: Option<String>) -> Self {
+        let images = DEFAULT_SIZES
+            .into_iter()
+            .map(|size| {
+                let mut pixels = vec![0; (size * size) as usize];
+                draw(&mut pixels, size);
+                IconImage {
+                    size,
+                    scale: 1,
+                    pixels,
+                }
+            })
+            .collect();
+
+        Self { name, images }
+    }
+}
+
+/// An icon uploaded to the compositor, ready to be attached to toplevels.
+///
+/// The buffers have to stay around, untouched, for as long as the icon object exists.
+pub struct ToplevelIcon {
+    icon: XdgToplevelIconV1,
+    buffers: Vec<BufferPool>,
+}
+
+impl ToplevelIcon {
+    pub fn new(
+        manager: &XdgToplevelIconManagerV1,
+        shm: &WlShm,
+        icon: &Icon,
+        qh: &QueueHandle<App>,
+    ) -> Result<Self> {
+        let toplevel_icon = manager.create_icon(qh, ());
+
+        if let Some(name) = &icon.name {
+            toplevel_icon.set_name(name.clone());
+        }
+
+        let buffers = icon
+            .images
+            .iter()
+            .map(|image| {
+                let memfd = sealed_memfd(image.size as usize * image.size as usize * 4)?;
+                let mut pool = BufferPool::from_fd(shm, qh, memfd, image.size, image.size)?;
+                // The compositor never releases icon buffers, so this one stays loaned, and thus
+                // unmodified, for as long as the pool lives.
+                let (buffer, pixels) = pool.get_buffer(qh)?;
+                pixels.copy_from_slice(&image.pixels);
+                toplevel_icon.add_buffer(&buffer, image.scale as i32);
+                Ok(pool)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            icon: toplevel_icon,
+            buffers,
+        })
+    }
+
+    pub fn proxy(&self) -> &XdgToplevelIconV1 {
+        &self.icon
+    }
+}
+
+impl Drop for ToplevelIcon {
+    fn drop(&mut self) {
+        // Toplevels keep the icon after it is destroyed, but the buffers may only go once it is.
+        self.icon.destroy();
+        self.buffers.clear();
+    }
+}
+
+fn draw(pixels: &mut [u32], size: u32) {
+    let mut canvas = Canvas::new(pixels, size, size);
+    canvas.fill(BACKGROUND_COLOR);
+
+    // Four tiles in a 2x2 grid, with gaps the same width as the margin.
+    let gap = (size / 16).max(1);
+    let tile = (size - 3 * gap) / 2;
+    for (i, color) in (0..).zip(TILE_COLORS) {
+        let x = gap + (i % 2) * (tile + gap);
+        let y = gap + (i / 2) * (tile + gap);
+        canvas.fill_rect(x as i32, y as i32, tile, tile, color);
+    }
+}
+
+/// Creates `len` bytes of shared memory for an icon buffer, sealed so that its size can never
+/// change.
+///
+/// Unlike other buffers, icon buffers are never released, so the compositor may keep them mapped
+/// for as long as the icon is in use. The seals guarantee it that the memory can't shrink from
+/// under it.
+fn sealed_memfd(len: usize) -> Result<OwnedFd> {
+    let memfd = memfd_create(
+        "wayland-thing-icon",
+        MemfdFlags::CLOEXEC | MemfdFlags::ALLOW_SEALING,
+    )?;
+    ftruncate(&memfd, len as u64)?;
+    fcntl_add_seals(
+        &memfd,
+        SealFlags::SHRINK | SealFlags::GROW | SealFlags::SEAL,
+    )?;
+    Ok(memfd)
+}
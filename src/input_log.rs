@@ -0,0 +1,131 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::error::{Error, Result};
+
+/// An input event, decoded just far enough to be replayed through the window's handlers.
+///
+/// Events that need a compositor serial (like button presses opening the window menu) can't be
+/// replayed meaningfully, so they aren't represented here.
+#[derive(Clone, Copy, Debug)]
+pub enum InputEvent {
+    Key { key: u32, pressed: bool },
+    PointerMotion { x: f64, y: f64 },
+}
+
+/// Writes input events to a file as they happen, one per line.
+///
+/// Each line starts with the time in milliseconds since the log was started, followed by the
+/// event, e.g. `1250 key 16 press` or `1310 motion 120.5 64`.
+pub struct InputRecorder {
+    file: BufWriter<File>,
+    start: Option<Instant>,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            start: None,
+        })
+    }
+
+    /// Starts the clock event times are measured from.
+    pub fn start(&mut self) {
+        self.start.get_or_insert_with(Instant::now);
+    }
+
+    pub fn record(&mut self, event: InputEvent) -> Result<()> {
+        let time = self.start.map_or(Duration::ZERO, |start| start.elapsed());
+        let time = time.as_millis();
+
+        match event {
+            InputEvent::Key { key, pressed } => {
+                let state = if pressed { "press" } else { "release" };
+                writeln!(self.file, "{time} key {key} {state}")?;
+            }
+            InputEvent::PointerMotion { x, y } => writeln!(self.file, "{time} motion {x} {y}")?,
+        }
+
+        // Flush every event, so that the log survives whatever crash is being reproduced.
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Plays back events written by an [`InputRecorder`], at the same pace they were recorded.
+pub struct InputReplay {
+    events: VecDeque<(Duration, InputEvent)>,
+    start: Option<Instant>,
+}
+
+impl InputReplay {
+    pub fn load(path: &Path) -> Result<Self> {
+        let events = fs::read_to_string(path)?
+            .lines()
+            .zip(1..)
+            .filter(|(line, _)| !line.trim().is_empty())
+            .map(|(line, line_number)| {
+                parse_event(line).ok_or(Error::InvalidInputLog { line: line_number })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            events,
+            start: None,
+        })
+    }
+
+    /// Starts the clock the recorded event times are relative to.
+    pub fn start(&mut self) {
+        self.start.get_or_insert_with(Instant::now);
+    }
+
+    /// Returns when the next event is due, or `None` if replay hasn't started or is over.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let (time, _) = self.events.front()?;
+        Some(self.start? + *time)
+    }
+
+    /// Removes and returns the next event, if it is due.
+    pub fn pop_due(&mut self, now: Instant) -> Option<InputEvent> {
+        if self.next_deadline()? > now {
+            return None;
+        }
+        self.events.pop_front().map(|(_, event)| event)
+    }
+}
+
+fn parse_event(line: &str) -> Option<(Duration, InputEvent)> {
+    let mut fields = line.split_whitespace();
+    let time = Duration::from_millis(fields.next()?.parse().ok()?);
+
+    let event = match fields.next()? {
+        "key" => {
+            let key = fields.next()?.parse().ok()?;
+            let pressed = match fields.next()? {
+                "press" => true,
+                "release" => false,
+                _ => return None,
+            };
+            InputEvent::Key { key, pressed }
+        }
+        "motion" => {
+            let x = fields.next()?.parse().ok()?;
+            let y = fields.next()?.parse().ok()?;
+            InputEvent::PointerMotion { x, y }
+        }
+        _ => return None,
+    };
+
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some((time, event))
+}
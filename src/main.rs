@@ -1,28 +1,210 @@
+use std::{env, time::Instant};
+
 use anyhow::Result;
-use wayland_client::{Connection, globals::registry_queue_init};
-use window::Window;
+use app::App;
+use caps::CompositorCaps;
+use log::warn;
+use options::Options;
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use wayland_client::{Connection, backend::WaylandError, globals::registry_queue_init};
 
+mod app;
+mod caps;
+mod cursor;
+mod decoration;
+mod diagnostics;
+mod draw;
+mod error;
+mod gradient;
+mod headless;
+mod icon;
+mod input_log;
+mod options;
+mod overlay;
+mod popup;
+mod presentation;
+mod render;
+mod saved_geometry;
+mod session;
+mod shm;
+mod shm_window;
+mod test_pattern;
+mod toplevel;
 mod vulkan;
 mod window;
 
 fn main() -> Result<()> {
+    // Removing the variable is only sound before anything might have started threads.
+    let activation_token = take_startup_activation_token();
+
     env_logger::init();
 
+    let options = Options::from_args()?;
+
+    // Rendering offscreen doesn't need a compositor at all.
+    if let Some(path) = &options.headless {
+        return Ok(headless::run(&options, path)?);
+    }
+
     let conn = Connection::connect_to_env()?;
-    let (globals, mut queue) = registry_queue_init(&conn)?;
 
-    let mut window = Window::new(
-        &conn,
+    let result = if options.no_gpu || options.shm_present {
+        run_shm(&conn, &options)
+    } else {
+        run(&conn, &options, activation_token)
+    };
+    if result.is_err() {
+        diagnostics::report_protocol_error(&conn);
+    }
+    result
+}
+
+fn run(conn: &Connection, options: &Options, activation_token: Option<String>) -> Result<()> {
+    let (globals, mut queue) = registry_queue_init(conn)?;
+    let caps = CompositorCaps::query(conn, &globals)?;
+
+    // Frame callbacks and input devices get their own queue, so that they are never stuck behind
+    // other events.
+    let mut input_queue = conn.new_event_queue();
+
+    let mut app = App::new(
+        conn,
         &queue.handle(),
+        &input_queue.handle(),
         &globals,
-        500,
-        500,
-        "Wayland Thing".to_owned(),
+        options,
+        activation_token,
     )?;
 
-    while !window.closed {
-        queue.blocking_dispatch(&mut window)?;
+    let mut first_window = None;
+    for i in 1..=options.windows {
+        let title = if i == 1 {
+            "Wayland Thing".to_owned()
+        } else {
+            format!("Wayland Thing ({i})")
+        };
+        let id = app.open_window(
+            &queue.handle(),
+            &globals,
+            500,
+            500,
+            title,
+            "wayland-thing".to_owned(),
+            options,
+            &caps,
+        )?;
+        first_window.get_or_insert(id);
+
+        if options.bench_resize {
+            // The benchmark presents frames, which needs the window to be configured first.
+            while !app
+                .window_mut(id)
+                .is_some_and(|window| window.is_configured())
+            {
+                queue.blocking_dispatch(&mut app)?;
+                if let Some(err) = app.take_fatal_error() {
+                    return Err(err.into());
+                }
+            }
+
+            let window = app.window_mut(id).unwrap();
+            window.bench_resize()?;
+            window.shutdown()?;
+            return Ok(());
+        }
+    }
+
+    if options.dialog {
+        let id = app.open_window(
+            &queue.handle(),
+            &globals,
+            300,
+            200,
+            "Wayland Thing (dialog)".to_owned(),
+            "wayland-thing".to_owned(),
+            options,
+            &caps,
+        )?;
+        app.set_parent(id, first_window);
+        if let Some(dialog) = app.window_mut(id) {
+            dialog.set_modal(&queue.handle(), true);
+        }
+    }
+
+    // Closed windows are shut down and dropped as we go, and we're done once the last one is.
+    while app.has_windows() {
+        input_queue.dispatch_pending(&mut app)?;
+        queue.dispatch_pending(&mut app)?;
+        if let Some(err) = app.take_fatal_error() {
+            return Err(err.into());
+        }
+        app.replay_input();
+        app.remove_closed_windows()?;
+
+        // Batch up everything requested while handling this round of events.
+        app.flush()?;
+
+        read_events(conn, app.next_replay_deadline())?;
+    }
+
+    if let Some(id) = app.session_id()
+        && let Err(err) = session::save_id(id)
+    {
+        warn!("failed to save session ID: {err}");
     }
 
     Ok(())
 }
+
+/// Runs a single window presenting through shm buffers rather than a Vulkan swapchain.
+fn run_shm(conn: &Connection, options: &Options) -> Result<()> {
+    let (globals, queue) = registry_queue_init(conn)?;
+    Ok(shm_window::run(&globals, queue, options)?)
+}
+
+/// Takes the activation token passed to us by whoever launched us, if any.
+///
+/// The variable is removed so that it isn't inherited by processes we might spawn, as the token
+/// is only valid once.
+fn take_startup_activation_token() -> Option<String> {
+    let token = env::var("XDG_ACTIVATION_TOKEN").ok();
+    // SAFETY: This runs first thing in `main`, before the logger, the Vulkan loader or anything
+    // else that could spawn threads reading the environment.
+    unsafe {
+        env::remove_var("XDG_ACTIVATION_TOKEN");
+    }
+    token
+}
+
+/// Blocks until new events are available on `conn` and reads them into their queues, or until
+/// `deadline` passes.
+///
+/// Pending requests should be flushed before calling this, as the compositor may be waiting on
+/// them to send more events.
+fn read_events(conn: &Connection, deadline: Option<Instant>) -> Result<()> {
+    // If this fails, events are already waiting to be dispatched.
+    let Some(guard) = conn.prepare_read() else {
+        return Ok(());
+    };
+
+    let fd = guard.connection_fd();
+    loop {
+        let timeout = deadline
+            .map(|deadline| Timespec::try_from(deadline.saturating_duration_since(Instant::now())))
+            .transpose()?;
+        match poll(
+            &mut [PollFd::new(&fd, PollFlags::IN | PollFlags::ERR)],
+            timeout.as_ref(),
+        ) {
+            Ok(_) => break,
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    match guard.read() {
+        Ok(_) => Ok(()),
+        Err(WaylandError::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
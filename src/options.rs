@@ -0,0 +1,396 @@
+use std::{env, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ash::vk;
+
+/// The kinds of content the window can show.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Demo {
+    /// Clear to a solid color, selectable with the number keys.
+    Clear,
+    /// Color bars and a one-pixel checkerboard, for checking scaling and color correctness.
+    TestPattern,
+    /// A shaded triangle over the clear color, drawn with a graphics pipeline.
+    Triangle,
+    /// A gradient generated by a compute shader and stretched over the window.
+    Gradient,
+}
+
+/// User-facing names for the swapchain present modes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Vsync {
+    /// Always wait for vblank (FIFO).
+    On,
+    /// Never wait for vblank, allowing tearing (IMMEDIATE).
+    Off,
+    /// Wait for vblank unless a frame is late (FIFO_RELAXED).
+    Adaptive,
+}
+
+impl Vsync {
+    pub fn present_mode(self) -> vk::PresentModeKHR {
+        match self {
+            Self::On => vk::PresentModeKHR::FIFO,
+            Self::Off => vk::PresentModeKHR::IMMEDIATE,
+            Self::Adaptive => vk::PresentModeKHR::FIFO_RELAXED,
+        }
+    }
+}
+
+/// Present modes that can be switched between while running, e.g. to compare frame rates with and
+/// without vsync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentPreference {
+    /// Wait for vblank, queueing up every frame.
+    Fifo,
+    /// Wait for vblank, replacing the queued frame with newer ones rather than blocking.
+    Mailbox,
+    /// Present right away, allowing tearing.
+    Immediate,
+}
+
+impl PresentPreference {
+    pub fn present_mode(self) -> vk::PresentModeKHR {
+        match self {
+            Self::Fifo => vk::PresentModeKHR::FIFO,
+            Self::Mailbox => vk::PresentModeKHR::MAILBOX,
+            Self::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+
+    /// Returns the preference after this one, for cycling through them all.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Fifo => Self::Mailbox,
+            Self::Mailbox => Self::Immediate,
+            Self::Immediate => Self::Fifo,
+        }
+    }
+}
+
+/// Color spaces the swapchain can be created in, beyond plain sRGB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB primaries and transfer function, which every surface supports.
+    Srgb,
+    /// The wider Display P3 primaries, with the sRGB transfer function.
+    DisplayP3,
+    /// Linear sRGB primaries with values beyond [0, 1], in a float format (scRGB).
+    ExtendedSrgb,
+    /// BT.2020 primaries with the PQ transfer function, for HDR displays.
+    Hdr10,
+}
+
+/// When windows draw new frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Draw on every frame callback, as games and animations do.
+    Continuous,
+    /// Only draw after something changed, as signaled by `Window::request_redraw`, leaving the GPU
+    /// idle otherwise.
+    OnDemand,
+}
+
+#[derive(Clone)]
+pub struct Options {
+    /// What to draw in the window.
+    pub demo: Demo,
+    /// The initial clear color, in sRGB space.
+    pub clear_color: [f32; 3],
+    /// Whether to use an sRGB swapchain format, letting the hardware apply the transfer
+    /// function.
+    pub srgb: bool,
+    /// The color space to present in, falling back to sRGB if the surface doesn't support it.
+    pub color_space: ColorSpace,
+    /// A swapchain format to use no matter what, for reproducing format-specific issues.
+    pub forced_format: Option<vk::Format>,
+    /// Whether to give each swapchain image a depth buffer, for depth testing.
+    pub depth: bool,
+    /// Samples per pixel to render with, where 1 disables multisampling.
+    pub samples: u32,
+    /// Whether to render without render pass objects where the device supports it.
+    pub dynamic_rendering: bool,
+    /// Whether to enable the Vulkan validation layer, logging what it finds.
+    pub validation: bool,
+    /// Whether to count Vulkan's host memory allocations, logging the totals at exit.
+    pub track_vk_allocations: bool,
+    /// How presentation should sync to the display, or `None` to prefer MAILBOX.
+    pub vsync: Option<Vsync>,
+    /// The scale to create the window at, until the compositor tells us otherwise.
+    pub scale: f64,
+    /// Whether to scale with an integer buffer scale instead of the viewport, so that every buffer
+    /// pixel maps to exactly one screen pixel at integer scales.
+    pub native_resolution: bool,
+    /// Whether to throttle commits to the display refresh with `wp_fifo_v1`, when available.
+    pub fifo: bool,
+    /// Whether to schedule commits for specific refresh cycles with `wp_commit_timing_v1`, when
+    /// available.
+    pub commit_timing: bool,
+    /// Whether to redraw on every frame callback or only when something changed.
+    pub render_mode: RenderMode,
+    /// How many frames the CPU may get ahead of the GPU by.
+    pub frames_in_flight: u32,
+    /// A file to record input events to, for replaying them later.
+    pub record_input: Option<PathBuf>,
+    /// A file of previously recorded input events to play back.
+    pub replay_input: Option<PathBuf>,
+    /// The name of a themed icon to show for the window, instead of one named after the app ID.
+    pub icon_name: Option<String>,
+    /// The compositor session to restore window placement from, as logged on a previous run.
+    /// Without it, the session saved at the end of the last run is restored.
+    pub session_id: Option<String>,
+    /// How many windows to open, all sharing one connection.
+    pub windows: u32,
+    /// Whether to open a modal dialog on top of the first window.
+    pub dialog: bool,
+    /// Limits on the content size of every window, where zero means unlimited.
+    pub min_size: (u32, u32),
+    pub max_size: (u32, u32),
+    /// Whether to measure how long swapchain rebuilds take and exit, instead of running normally.
+    pub bench_resize: bool,
+    /// A file to render a single frame into offscreen, as a PPM image, instead of opening windows.
+    pub headless: Option<PathBuf>,
+    /// Whether to fill the window from shm buffers without using Vulkan at all.
+    pub no_gpu: bool,
+    /// Whether to render offscreen and show the frames through shm buffers instead of a swapchain.
+    pub shm_present: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            demo: Demo::Clear,
+            clear_color: [0.0, 0.0, 0.0],
+            srgb: false,
+            color_space: ColorSpace::Srgb,
+            forced_format: None,
+            depth: false,
+            samples: 1,
+            dynamic_rendering: true,
+            validation: false,
+            track_vk_allocations: false,
+            vsync: None,
+            scale: 1.0,
+            native_resolution: false,
+            fifo: false,
+            commit_timing: false,
+            render_mode: RenderMode::Continuous,
+            frames_in_flight: 2,
+            record_input: None,
+            replay_input: None,
+            icon_name: None,
+            session_id: None,
+            windows: 1,
+            dialog: false,
+            min_size: (0, 0),
+            max_size: (0, 0),
+            bench_resize: false,
+            headless: None,
+            no_gpu: false,
+            shm_present: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self> {
+        let mut options = Self::default();
+
+        // The command line takes precedence over the environment.
+        if let Ok(scale) = env::var("WAYLAND_THING_SCALE") {
+            options.scale = parse_scale(&scale)
+                .with_context(|| format!("invalid `WAYLAND_THING_SCALE` value `{scale}`"))?;
+        }
+
+        // This is a debugging aid rather than a real setting, so it has no command-line
+        // equivalent and overrides `--srgb` and `--color-space`.
+        if let Ok(format) = env::var("WAYLAND_THING_FORMAT") {
+            options.forced_format = Some(
+                parse_format(&format)
+                    .with_context(|| format!("invalid `WAYLAND_THING_FORMAT` value `{format}`"))?,
+            );
+        }
+
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            // Accept both `--name value` and `--name=value`.
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| anyhow!("missing value for `{name}`"))
+            };
+            // Flags are set by their mere presence, so a value would just be silently ignored.
+            let flag = || -> Result<bool> {
+                if inline_value.is_some() {
+                    bail!("`{name}` does not take a value");
+                }
+                Ok(true)
+            };
+
+            match name.as_str() {
+                "--demo" => {
+                    let demo = value()?;
+                    options.demo = match demo.as_str() {
+                        "clear" => Demo::Clear,
+                        "testpattern" => Demo::TestPattern,
+                        "triangle" => Demo::Triangle,
+                        "gradient" => Demo::Gradient,
+                        _ => bail!("unknown demo `{demo}`"),
+                    };
+                }
+                "--clear-color" => {
+                    let color = value()?;
+                    options.clear_color = parse_color(&color)
+                        .with_context(|| format!("invalid clear color `{color}`"))?;
+                }
+                "--srgb" => options.srgb = flag()?,
+                "--color-space" => {
+                    let color_space = value()?;
+                    options.color_space = match color_space.as_str() {
+                        "srgb" => ColorSpace::Srgb,
+                        "display-p3" => ColorSpace::DisplayP3,
+                        "scrgb" => ColorSpace::ExtendedSrgb,
+                        "hdr10" => ColorSpace::Hdr10,
+                        _ => bail!("unknown color space `{color_space}`"),
+                    };
+                }
+                "--vsync" => {
+                    let vsync = value()?;
+                    options.vsync = Some(match vsync.as_str() {
+                        "on" => Vsync::On,
+                        "off" => Vsync::Off,
+                        "adaptive" => Vsync::Adaptive,
+                        _ => bail!("invalid vsync mode `{vsync}`"),
+                    });
+                }
+                "--depth" => options.depth = flag()?,
+                "--msaa" => {
+                    let samples = value()?;
+                    options.samples = parse_sample_count(&samples)
+                        .with_context(|| format!("invalid sample count `{samples}`"))?;
+                }
+                "--no-dynamic-rendering" => options.dynamic_rendering = !flag()?,
+                "--validation" => options.validation = flag()?,
+                "--track-vk-allocations" => options.track_vk_allocations = flag()?,
+                "--native-resolution" => options.native_resolution = flag()?,
+                "--fifo" => options.fifo = flag()?,
+                "--commit-timing" => options.commit_timing = flag()?,
+                "--on-demand" => {
+                    flag()?;
+                    options.render_mode = RenderMode::OnDemand;
+                }
+                "--frames-in-flight" => {
+                    let frames = value()?;
+                    options.frames_in_flight = parse_frames_in_flight(&frames)
+                        .with_context(|| format!("invalid frames in flight count `{frames}`"))?;
+                }
+                "--record" => options.record_input = Some(value()?.into()),
+                "--replay" => options.replay_input = Some(value()?.into()),
+                "--icon" => options.icon_name = Some(value()?),
+                "--session" => options.session_id = Some(value()?),
+                "--windows" => {
+                    let windows = value()?;
+                    options.windows = parse_window_count(&windows)
+                        .with_context(|| format!("invalid window count `{windows}`"))?;
+                }
+                "--dialog" => options.dialog = flag()?,
+                "--min-size" => {
+                    let size = value()?;
+                    options.min_size =
+                        parse_size(&size).with_context(|| format!("invalid size `{size}`"))?;
+                }
+                "--max-size" => {
+                    let size = value()?;
+                    options.max_size =
+                        parse_size(&size).with_context(|| format!("invalid size `{size}`"))?;
+                }
+                "--bench-resize" => options.bench_resize = flag()?,
+                "--headless" => options.headless = Some(value()?.into()),
+                "--no-gpu" => options.no_gpu = flag()?,
+                "--shm-present" => options.shm_present = flag()?,
+                "--scale" => {
+                    let scale = value()?;
+                    options.scale =
+                        parse_scale(&scale).with_context(|| format!("invalid scale `{scale}`"))?;
+                }
+                _ => bail!("unknown argument `{name}`"),
+            }
+        }
+
+        if options.no_gpu && options.shm_present {
+            bail!("`--shm-present` renders with the GPU, so it can't be combined with `--no-gpu`");
+        }
+
+        Ok(options)
+    }
+}
+
+/// Parses an `RRGGBB` hex color into normalized components.
+fn parse_color(s: &str) -> Result<[f32; 3]> {
+    if s.len() != 6 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("expected 6 hex digits (RRGGBB)");
+    }
+
+    let rgb = u32::from_str_radix(s, 16)?;
+    Ok([16, 8, 0].map(|shift| ((rgb >> shift) & 0xff) as f32 / 255.0))
+}
+
+fn parse_format(s: &str) -> Result<vk::Format> {
+    Ok(match s {
+        "bgra8_unorm" => vk::Format::B8G8R8A8_UNORM,
+        "bgra8_srgb" => vk::Format::B8G8R8A8_SRGB,
+        "rgba8_unorm" => vk::Format::R8G8B8A8_UNORM,
+        "rgba8_srgb" => vk::Format::R8G8B8A8_SRGB,
+        "a2rgb10_unorm" => vk::Format::A2R10G10B10_UNORM_PACK32,
+        "a2bgr10_unorm" => vk::Format::A2B10G10R10_UNORM_PACK32,
+        "rgba16_sfloat" => vk::Format::R16G16B16A16_SFLOAT,
+        _ => bail!("unknown format"),
+    })
+}
+
+fn parse_scale(s: &str) -> Result<f64> {
+    let scale: f64 = s.parse()?;
+    if !scale.is_finite() || scale <= 0.0 {
+        bail!("scale must be positive");
+    }
+    Ok(scale)
+}
+
+fn parse_window_count(s: &str) -> Result<u32> {
+    let count: u32 = s.parse()?;
+    if count == 0 {
+        bail!("at least one window is needed");
+    }
+    Ok(count)
+}
+
+/// Parses a `WIDTHxHEIGHT` size.
+fn parse_size(s: &str) -> Result<(u32, u32)> {
+    let Some((width, height)) = s.split_once('x') else {
+        bail!("expected WIDTHxHEIGHT");
+    };
+    Ok((width.parse()?, height.parse()?))
+}
+
+fn parse_frames_in_flight(s: &str) -> Result<u32> {
+    let count: u32 = s.parse()?;
+    if !(1..=8).contains(&count) {
+        bail!("expected between 1 and 8 frames");
+    }
+    Ok(count)
+}
+
+fn parse_sample_count(s: &str) -> Result<u32> {
+    let samples: u32 = s.parse()?;
+    if !samples.is_power_of_two() || samples > 64 {
+        bail!("expected a power of two up to 64");
+    }
+    Ok(samples)
+}
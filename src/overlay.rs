@@ -0,0 +1,186 @@
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_shm::WlShm,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
+        wl_surface::{self, WlSurface},
+    },
+};
+
+use crate::{
+    app::App,
+    draw::Canvas,
+    error::Result,
+    shm::{BufferPool, to_buffer_scale},
+};
+
+const WIDTH: u32 = 240;
+const HEIGHT: u32 = 96;
+const BORDER: u32 = 2;
+const BUTTON_SIZE: u32 = 40;
+
+// Premultiplied ARGB.
+const BACKGROUND_COLOR: u32 = 0xe0202020;
+const BORDER_COLOR: u32 = 0xffc0c0c0;
+const CONFIRM_COLOR: u32 = 0xff2e8b57;
+const DISMISS_COLOR: u32 = 0xffb22222;
+const ICON_COLOR: u32 = 0xffffffff;
+
+/// Marks the overlay's surface, so that its events aren't mistaken for the main surface's.
+pub struct OverlaySurface;
+
+/// A small modal panel asking the user to confirm quitting, shown as a subsurface centered over
+/// the window.
+///
+/// The left (green) button stands for Y and the right (red) one for N.
+pub struct QuitOverlay {
+    surface: WlSurface,
+    subsurface: WlSubsurface,
+    buffers: BufferPool,
+    buffer_scale: u32,
+}
+
+impl QuitOverlay {
+    pub fn new(
+        compositor: &WlCompositor,
+        subcompositor: &WlSubcompositor,
+        shm: &WlShm,
+        parent: &WlSurface,
+        parent_size: (u32, u32),
+        scale: f64,
+        qh: &QueueHandle<App>,
+    ) -> Result<Self> {
+        let surface = compositor.create_surface(qh, OverlaySurface);
+        let subsurface = subcompositor.get_subsurface(&surface, parent, qh, ());
+
+        let buffer_scale = to_buffer_scale(scale);
+        let buffers = BufferPool::new(shm, qh, WIDTH * buffer_scale, HEIGHT * buffer_scale)?;
+
+        let mut overlay = Self {
+            surface,
+            subsurface,
+            buffers,
+            buffer_scale,
+        };
+        overlay.set_parent_size(parent_size);
+        overlay.redraw(qh)?;
+
+        Ok(overlay)
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Keeps the overlay centered over a parent of the given size.
+    ///
+    /// Like everything else about the subsurface, this only takes effect with the parent's next
+    /// commit.
+    pub fn set_parent_size(&mut self, parent_size: (u32, u32)) {
+        let (parent_width, parent_height) = parent_size;
+        self.subsurface.set_position(
+            (parent_width as i32 - WIDTH as i32) / 2,
+            (parent_height as i32 - HEIGHT as i32) / 2,
+        );
+    }
+
+    /// Re-renders the overlay for a new window scale, if it calls for a different buffer scale.
+    pub fn set_scale(&mut self, scale: f64, qh: &QueueHandle<App>) -> Result<()> {
+        let buffer_scale = to_buffer_scale(scale);
+        if buffer_scale != self.buffer_scale {
+            self.buffer_scale = buffer_scale;
+            self.buffers
+                .reconfigure(WIDTH * buffer_scale, HEIGHT * buffer_scale);
+            self.redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Draws into a fresh buffer and damages all of it, e.g. in case the compositor dropped the old
+    /// contents.
+    pub fn redraw(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        let width = WIDTH * self.buffer_scale;
+        let height = HEIGHT * self.buffer_scale;
+
+        let (buffer, pixels) = self.buffers.get_buffer(qh)?;
+        draw(pixels, width, height, self.buffer_scale);
+
+        // The subsurface is synchronized, so this only takes effect with the parent's next commit.
+        self.surface.set_buffer_scale(self.buffer_scale as i32);
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.surface.commit();
+
+        Ok(())
+    }
+}
+
+impl Drop for QuitOverlay {
+    fn drop(&mut self) {
+        // Destroying the subsurface unmaps it immediately.
+        self.subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+fn draw(pixels: &mut [u32], width: u32, height: u32, buffer_scale: u32) {
+    let mut canvas = Canvas::new(pixels, width, height);
+
+    let border = BORDER * buffer_scale;
+    canvas.fill(BORDER_COLOR);
+    canvas.fill_rect(
+        border as i32,
+        border as i32,
+        width - 2 * border,
+        height - 2 * border,
+        BACKGROUND_COLOR,
+    );
+
+    let button_size = BUTTON_SIZE * buffer_scale;
+    let button_y = ((height - button_size) / 2) as i32;
+    let confirm_x = (width / 4 - button_size / 2) as i32;
+    let dismiss_x = (width * 3 / 4 - button_size / 2) as i32;
+
+    canvas.fill_rect(confirm_x, button_y, button_size, button_size, CONFIRM_COLOR);
+    canvas.fill_rect(dismiss_x, button_y, button_size, button_size, DISMISS_COLOR);
+
+    let size = button_size as i32;
+    let inset = size / 4;
+
+    // Thicken the icons by stacking lines, so that they stay visible at higher scales.
+    for offset in 0..2 * buffer_scale as i32 {
+        // A check mark on the confirm button.
+        let (x, y) = (confirm_x, button_y + offset);
+        let corner = (x + size * 2 / 5, y + size - inset);
+        canvas.draw_line((x + inset, y + size / 2), corner, ICON_COLOR);
+        canvas.draw_line(corner, (x + size - inset, y + inset), ICON_COLOR);
+
+        // A cross on the dismiss button.
+        let (x, y) = (dismiss_x + offset, button_y);
+        canvas.draw_line(
+            (x + inset, y + inset),
+            (x + size - inset, y + size - inset),
+            ICON_COLOR,
+        );
+        canvas.draw_line(
+            (x + inset, y + size - inset),
+            (x + size - inset, y + inset),
+            ICON_COLOR,
+        );
+    }
+}
+
+impl Dispatch<WlSurface, OverlaySurface> for App {
+    fn event(
+        _app: &mut Self,
+        _surface: &WlSurface,
+        _event: wl_surface::Event,
+        _data: &OverlaySurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
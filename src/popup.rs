@@ -0,0 +1,261 @@
+use log::warn;
+use wayland_client::{
+    Connection, Dispatch, QueueHandle, delegate_noop,
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_seat::WlSeat,
+        wl_shm::WlShm,
+        wl_surface::{self, WlSurface},
+    },
+};
+use wayland_protocols::xdg::shell::client::{
+    xdg_popup::{self, XdgPopup},
+    xdg_positioner::{self, XdgPositioner},
+    xdg_surface::{self, XdgSurface},
+    xdg_wm_base::XdgWmBase,
+};
+
+use crate::{
+    app::{App, WindowId},
+    draw::Canvas,
+    error::Result,
+    shm::{BufferPool, to_buffer_scale},
+};
+
+/// Size of the popup we ask for, in logical pixels. The compositor may shrink it to fit.
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 32;
+const BORDER: u32 = 1;
+const ICON_SIZE: u32 = 16;
+
+const BACKGROUND_COLOR: u32 = 0xff303030;
+const BORDER_COLOR: u32 = 0xffc0c0c0;
+const ICON_COLOR: u32 = 0xffb22222;
+
+/// Marks the objects making up a popup, with the window it belongs to.
+pub struct PopupParent(pub WindowId);
+
+/// A menu popped up next to a window, with a single entry for closing the window.
+///
+/// The popup is drawn on the CPU, at whatever size the compositor configures it with. It stays up
+/// until the compositor dismisses it (e.g. because of a click elsewhere) or it is dropped.
+pub struct Popup {
+    surface: WlSurface,
+    xdg_surface: XdgSurface,
+    xdg_popup: XdgPopup,
+    buffers: BufferPool,
+    /// The size from the last popup configure, applied along with the surface configure that
+    /// follows it.
+    pending_size: Option<(u32, u32)>,
+    size: (u32, u32),
+    buffer_scale: u32,
+}
+
+impl Popup {
+    /// Creates a popup placed next to `anchor_rect`, which is relative to `parent`'s window
+    /// geometry.
+    ///
+    /// If `grab` holds the seat and serial of a button press, the popup takes an explicit grab
+    /// through it, so that keyboard focus moves to it and clicking elsewhere dismisses it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        compositor: &WlCompositor,
+        shm: &WlShm,
+        xdg_wm_base: &XdgWmBase,
+        parent: &XdgSurface,
+        anchor_rect: (i32, i32, u32, u32),
+        grab: Option<(&WlSeat, u32)>,
+        window: WindowId,
+        scale: f64,
+        qh: &QueueHandle<App>,
+    ) -> Result<Self> {
+        let surface = compositor.create_surface(qh, PopupParent(window));
+        let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, qh, PopupParent(window));
+
+        let positioner = xdg_wm_base.create_positioner(qh, ());
+        let (x, y, width, height) = anchor_rect;
+        positioner.set_size(WIDTH as i32, HEIGHT as i32);
+        positioner.set_anchor_rect(x, y, width as i32, height as i32);
+        positioner.set_anchor(xdg_positioner::Anchor::BottomRight);
+        positioner.set_gravity(xdg_positioner::Gravity::BottomRight);
+        // Rather flip over to the other side of the anchor than get cut off at the edge of the
+        // screen.
+        positioner.set_constraint_adjustment(
+            xdg_positioner::ConstraintAdjustment::FlipX
+                | xdg_positioner::ConstraintAdjustment::FlipY
+                | xdg_positioner::ConstraintAdjustment::SlideX
+                | xdg_positioner::ConstraintAdjustment::SlideY,
+        );
+
+        let xdg_popup = xdg_surface.get_popup(Some(parent), &positioner, qh, PopupParent(window));
+        // The positioner's state is copied into the popup, so it isn't needed anymore.
+        positioner.destroy();
+
+        // Grabs have to be taken before the popup is mapped.
+        if let Some((seat, serial)) = grab {
+            xdg_popup.grab(seat, serial);
+        }
+
+        let buffer_scale = to_buffer_scale(scale);
+        let buffers = BufferPool::new(shm, qh, WIDTH * buffer_scale, HEIGHT * buffer_scale)?;
+
+        // Ask for the initial configure; the popup is drawn once it arrives.
+        surface.commit();
+
+        Ok(Self {
+            surface,
+            xdg_surface,
+            xdg_popup,
+            buffers,
+            pending_size: None,
+            size: (WIDTH, HEIGHT),
+            buffer_scale,
+        })
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Re-renders the popup for a new window scale, if it calls for a different buffer scale.
+    pub fn set_scale(&mut self, scale: f64, qh: &QueueHandle<App>) -> Result<()> {
+        let buffer_scale = to_buffer_scale(scale);
+        if buffer_scale != self.buffer_scale {
+            self.buffer_scale = buffer_scale;
+            self.reconfigure_buffers();
+            self.redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the size from the preceding popup configure, and draws the popup at it.
+    fn configure(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        if let Some(size) = self.pending_size.take()
+            && size != self.size
+        {
+            self.size = size;
+            self.reconfigure_buffers();
+        }
+        self.redraw(qh)
+    }
+
+    fn reconfigure_buffers(&mut self) {
+        let (width, height) = self.size;
+        self.buffers
+            .reconfigure(width * self.buffer_scale, height * self.buffer_scale);
+    }
+
+    /// Draws into a fresh buffer and damages all of it.
+    fn redraw(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        let width = self.size.0 * self.buffer_scale;
+        let height = self.size.1 * self.buffer_scale;
+
+        let (buffer, pixels) = self.buffers.get_buffer(qh)?;
+        draw(pixels, width, height, self.buffer_scale);
+
+        self.surface.set_buffer_scale(self.buffer_scale as i32);
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.surface.commit();
+
+        Ok(())
+    }
+}
+
+impl Drop for Popup {
+    fn drop(&mut self) {
+        self.xdg_popup.destroy();
+        self.xdg_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+fn draw(pixels: &mut [u32], width: u32, height: u32, buffer_scale: u32) {
+    let mut canvas = Canvas::new(pixels, width, height);
+
+    let border = BORDER * buffer_scale;
+    canvas.fill(BORDER_COLOR);
+    canvas.fill_rect(
+        border as i32,
+        border as i32,
+        width.saturating_sub(2 * border),
+        height.saturating_sub(2 * border),
+        BACKGROUND_COLOR,
+    );
+
+    // A cross in the middle, standing for the one entry.
+    let size = (ICON_SIZE * buffer_scale) as i32;
+    let x = (width as i32 - size) / 2;
+    let y = (height as i32 - size) / 2;
+    for offset in 0..2 * buffer_scale as i32 {
+        let x = x + offset;
+        canvas.draw_line((x, y), (x + size, y + size), ICON_COLOR);
+        canvas.draw_line((x, y + size), (x + size, y), ICON_COLOR);
+    }
+}
+
+impl Dispatch<WlSurface, PopupParent> for App {
+    fn event(
+        _app: &mut Self,
+        _surface: &WlSurface,
+        _event: wl_surface::Event,
+        _data: &PopupParent,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<XdgSurface, PopupParent> for App {
+    fn event(
+        app: &mut Self,
+        xdg_surface: &XdgSurface,
+        event: xdg_surface::Event,
+        parent: &PopupParent,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+
+            if let Some(window) = app.window_mut(parent.0)
+                && let Some(popup) = window.popup_mut()
+                && let Err(err) = popup.configure(qh)
+            {
+                warn!("failed to draw popup, closing it: {err}");
+                window.close_popup();
+            }
+        }
+    }
+}
+
+impl Dispatch<XdgPopup, PopupParent> for App {
+    fn event(
+        app: &mut Self,
+        _xdg_popup: &XdgPopup,
+        event: xdg_popup::Event,
+        parent: &PopupParent,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(parent.0) else {
+            return;
+        };
+
+        match event {
+            xdg_popup::Event::Configure { width, height, .. } => {
+                if let Some(popup) = window.popup_mut()
+                    && width > 0
+                    && height > 0
+                {
+                    popup.pending_size = Some((width as u32, height as u32));
+                }
+            }
+            xdg_popup::Event::PopupDone => window.close_popup(),
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(App: XdgPositioner);
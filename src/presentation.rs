@@ -0,0 +1,125 @@
+use std::{collections::VecDeque, time::Duration};
+
+use log::warn;
+use rustix::time::{ClockId, clock_gettime};
+
+/// Number of frame callback intervals the refresh estimate is taken over.
+const ESTIMATE_WINDOW: usize = 15;
+
+/// Frame callback intervals longer than this are gaps in the frame loop rather than refreshes.
+const MAX_CALLBACK_INTERVAL_MS: u32 = 250;
+
+/// Tracks when our frames were actually presented, based on `wp_presentation` feedback.
+#[derive(Default)]
+pub struct PresentationHistory {
+    clock: Option<ClockId>,
+    last_presented: Option<Duration>,
+    refresh: Option<Duration>,
+    /// Fallback for when the compositor doesn't report the refresh rate itself.
+    estimator: RefreshEstimator,
+}
+
+impl PresentationHistory {
+    /// Sets the clock presentation timestamps are reported in, as a raw `clockid_t`.
+    pub fn set_clock(&mut self, clk_id: u32) {
+        self.clock = match clk_id {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            4 => Some(ClockId::MonotonicRaw),
+            7 => Some(ClockId::Boottime),
+            _ => {
+                warn!("unknown presentation clock {clk_id}");
+                None
+            }
+        };
+    }
+
+    /// Feeds the timestamp of a frame callback into the fallback refresh estimate.
+    pub fn record_frame_callback(&mut self, timestamp_ms: u32) {
+        self.estimator.record(timestamp_ms);
+    }
+
+    pub fn record_presented(&mut self, timestamp: Duration, refresh: Duration) {
+        self.last_presented = Some(timestamp);
+        // A zero refresh means the output doesn't have a constant refresh rate.
+        self.refresh = (!refresh.is_zero()).then_some(refresh);
+    }
+
+    /// Returns the current time on the presentation clock.
+    pub fn now(&self) -> Option<Duration> {
+        let now = clock_gettime(self.clock?);
+        Some(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+    }
+
+    /// Picks the earliest refresh cycle starting at least `margin` from now, by extrapolating from
+    /// the last presentation.
+    ///
+    /// The returned time is guaranteed to lie in the future.
+    pub fn next_target(&self, margin: Duration) -> Option<Duration> {
+        let last_presented = self.last_presented?;
+        let refresh = self.refresh()?;
+
+        let earliest = self.now()? + margin;
+        let cycles = earliest
+            .saturating_sub(last_presented)
+            .as_nanos()
+            .div_ceil(refresh.as_nanos())
+            .max(1);
+
+        Some(last_presented + refresh * cycles as u32)
+    }
+
+    /// Returns the refresh interval reported by the compositor, or an estimate if it hasn't
+    /// reported one.
+    pub fn refresh(&self) -> Option<Duration> {
+        self.refresh.or_else(|| self.estimator.estimate())
+    }
+}
+
+/// Estimates the refresh interval from the spacing of frame callbacks.
+///
+/// Callbacks normally arrive once per refresh, but a dropped frame doubles an interval, so the
+/// median of recent intervals is used rather than the mean.
+#[derive(Default)]
+struct RefreshEstimator {
+    last_timestamp_ms: Option<u32>,
+    intervals_ms: VecDeque<u32>,
+}
+
+impl RefreshEstimator {
+    fn record(&mut self, timestamp_ms: u32) {
+        if let Some(last) = self.last_timestamp_ms.replace(timestamp_ms) {
+            // The timestamps wrap around every 49 days or so.
+            let interval = timestamp_ms.wrapping_sub(last);
+            if interval == 0 || interval > MAX_CALLBACK_INTERVAL_MS {
+                return;
+            }
+
+            if self.intervals_ms.len() == ESTIMATE_WINDOW {
+                self.intervals_ms.pop_front();
+            }
+            self.intervals_ms.push_back(interval);
+        }
+    }
+
+    fn estimate(&self) -> Option<Duration> {
+        // Wait for enough samples for the median to mean something.
+        if self.intervals_ms.len() < ESTIMATE_WINDOW / 2 {
+            return None;
+        }
+
+        let mut sorted: Vec<_> = self.intervals_ms.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(Duration::from_millis(sorted[sorted.len() / 2] as u64))
+    }
+}
+
+/// Splits a timestamp into the `(tv_sec_hi, tv_sec_lo, tv_nsec)` triple used by the protocols.
+pub fn encode_timestamp(timestamp: Duration) -> (u32, u32, u32) {
+    let secs = timestamp.as_secs();
+    ((secs >> 32) as u32, secs as u32, timestamp.subsec_nanos())
+}
+
+pub fn decode_timestamp(tv_sec_hi: u32, tv_sec_lo: u32, tv_nsec: u32) -> Duration {
+    Duration::new(((tv_sec_hi as u64) << 32) | tv_sec_lo as u64, tv_nsec)
+}
@@ -0,0 +1,970 @@
+use std::{env, ffi::CStr, path::Path, ptr, sync::Arc};
+
+use ash::vk;
+use log::{debug, warn};
+
+use crate::{
+    error::Result,
+    vulkan::{
+        self,
+        pipeline_cache::PipelineCache,
+        shaders::{ShaderCache, ShaderSource},
+    },
+};
+
+// Prebuilt from the GLSL next to them, so that building doesn't need a shader compiler. Rebuild
+// with `glslc <shader> -o <shader>.spv` after changing a shader.
+const TRIANGLE_VERT: &[u8] = include_bytes!("shaders/triangle.vert.spv");
+const TRIANGLE_FRAG: &[u8] = include_bytes!("shaders/triangle.frag.spv");
+
+/// Environment variable naming a directory to load shaders from instead of the embedded copies,
+/// e.g. `src/shaders` to try out a rebuilt shader without rebuilding the program.
+const SHADER_DIR_VAR: &str = "WAYLAND_THING_SHADER_DIR";
+
+const SHADER_ENTRY_POINT: &CStr = c"main";
+
+/// Depth buffers start out as far away as possible, with stencil unused.
+const DEPTH_CLEAR_VALUE: vk::ClearDepthStencilValue = vk::ClearDepthStencilValue {
+    depth: 1.0,
+    stencil: 0,
+};
+
+/// Depth formats to pick from, best first. `D16_UNORM` is always supported as an attachment, so
+/// there is always one.
+const DEPTH_FORMATS: &[vk::Format] = &[
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D32_SFLOAT,
+    vk::Format::D16_UNORM,
+];
+
+/// The pipelines for drawing into swapchain images of one format, along with the render pass
+/// they draw in unless the device supports dynamic rendering.
+///
+/// Everything is drawn in a single pass, which starts out by clearing the image and, if there is
+/// one, the depth buffer. With multisampling, drawing goes to a multisampled color target instead,
+/// which is resolved into the swapchain image at the end.
+pub struct Renderer {
+    device: Arc<vulkan::Device>,
+    allocator: Arc<vulkan::Allocator>,
+    shaders: ShaderCache,
+    pipeline_cache: Arc<PipelineCache>,
+    /// The format of the depth (and possibly stencil) buffers, if drawing with depth testing.
+    depth_format: Option<vk::Format>,
+    /// The format of the swapchain images.
+    format: vk::Format,
+    /// Samples per pixel, with anything above 1 rendering to multisampled targets.
+    samples: vk::SampleCountFlags,
+    /// The layout images are left in once drawn, e.g. for presenting them.
+    final_layout: vk::ImageLayout,
+    /// Null when rendering dynamically, which needs neither render passes nor framebuffers.
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    triangle_pipeline: vk::Pipeline,
+    /// Draws only the triangle's edges. Null unless the device has `fillModeNonSolid` enabled.
+    wireframe_pipeline: vk::Pipeline,
+}
+
+impl Renderer {
+    /// Creates a renderer for swapchain images in `format`, along with a depth buffer for each of
+    /// them if `depth` is set.
+    ///
+    /// Rendering is multisampled with `samples` samples per pixel, or not at all if the device
+    /// doesn't support that many. It uses dynamic rendering if `dynamic_rendering` is set and the
+    /// device supports it, and a render pass otherwise.
+    ///
+    /// Drawing leaves images in `final_layout`, which is `PRESENT_SRC_KHR` for swapchain images.
+    /// Pipelines are built through `pipeline_cache`, which may be shared with other renderers on
+    /// the same device.
+    pub fn new(
+        allocator: &Arc<vulkan::Allocator>,
+        pipeline_cache: &Arc<PipelineCache>,
+        format: vk::Format,
+        depth: bool,
+        samples: u32,
+        dynamic_rendering: bool,
+        final_layout: vk::ImageLayout,
+    ) -> Result<Self> {
+        let device = allocator.device();
+        let depth_format = depth.then(|| select_depth_format(device));
+        if let Some(depth_format) = depth_format {
+            debug!("using depth format {depth_format:?}");
+        }
+        let samples = select_sample_count(device, samples, depth);
+
+        // Anything created so far is cleaned up by `Drop` if a later step fails, as destroying null
+        // handles does nothing.
+        let mut renderer = Self {
+            device: Arc::clone(device),
+            allocator: Arc::clone(allocator),
+            shaders: ShaderCache::new(device),
+            pipeline_cache: Arc::clone(pipeline_cache),
+            depth_format,
+            format,
+            samples,
+            final_layout,
+            render_pass: vk::RenderPass::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            triangle_pipeline: vk::Pipeline::null(),
+            wireframe_pipeline: vk::Pipeline::null(),
+        };
+
+        if !(dynamic_rendering && device.supports_dynamic_rendering()) {
+            renderer.render_pass = renderer.create_render_pass()?;
+        }
+        renderer.pipeline_layout = unsafe {
+            device
+                .device()
+                .create_pipeline_layout(&Default::default(), device.allocation_callbacks())?
+        };
+        renderer.triangle_pipeline = renderer.create_triangle_pipeline(vk::PolygonMode::FILL)?;
+        if device.enabled_features().fill_mode_non_solid == vk::TRUE {
+            renderer.wireframe_pipeline =
+                renderer.create_triangle_pipeline(vk::PolygonMode::LINE)?;
+        }
+
+        Ok(renderer)
+    }
+
+    /// Creates framebuffers for rendering into `images`, which must have the format the renderer
+    /// was created for and the given size. Each gets a depth buffer of its own if the renderer
+    /// does depth testing.
+    pub fn create_framebuffers(
+        &self,
+        images: &[vk::Image],
+        width: u32,
+        height: u32,
+    ) -> Result<Framebuffers> {
+        let mut framebuffers = Framebuffers {
+            device: Arc::clone(&self.device),
+            image_views: Vec::with_capacity(images.len()),
+            targets: Vec::with_capacity(images.len()),
+            attachment_images: Vec::new(),
+            extent: vk::Extent2D { width, height },
+        };
+
+        for &image in images {
+            let mut target = RenderTarget {
+                framebuffer: vk::Framebuffer::null(),
+                image,
+                view: framebuffers.create_image_view(
+                    image,
+                    self.format,
+                    vk::ImageAspectFlags::COLOR,
+                )?,
+                depth: None,
+                multisampled: None,
+            };
+
+            if let Some(depth_format) = self.depth_format {
+                let depth_image = self.create_attachment_image(
+                    depth_format,
+                    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                    width,
+                    height,
+                )?;
+                target.depth = Some(
+                    framebuffers.add_attachment_image(depth_image, depth_aspects(depth_format))?,
+                );
+            }
+
+            if self.multisampled() {
+                let color_image = self.create_attachment_image(
+                    self.format,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                    width,
+                    height,
+                )?;
+                target.multisampled = Some(
+                    framebuffers.add_attachment_image(color_image, vk::ImageAspectFlags::COLOR)?,
+                );
+            }
+
+            if self.render_pass != vk::RenderPass::null() {
+                // In the order `create_render_pass` sets the attachments up.
+                let attachments: Vec<_> = [Some(target.view)]
+                    .into_iter()
+                    .chain(
+                        [target.depth, target.multisampled].map(|attachment| Some(attachment?.1)),
+                    )
+                    .flatten()
+                    .collect();
+
+                target.framebuffer = unsafe {
+                    self.device.device().create_framebuffer(
+                        &vk::FramebufferCreateInfo {
+                            render_pass: self.render_pass,
+                            attachment_count: attachments.len() as u32,
+                            p_attachments: attachments.as_ptr(),
+                            width,
+                            height,
+                            layers: 1,
+                            ..Default::default()
+                        },
+                        self.device.allocation_callbacks(),
+                    )?
+                };
+            }
+
+            framebuffers.targets.push(target);
+        }
+
+        Ok(framebuffers)
+    }
+
+    /// Creates an image for use as a framebuffer attachment next to a swapchain image, with the
+    /// renderer's sample count.
+    fn create_attachment_image(
+        &self,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        width: u32,
+        height: u32,
+    ) -> Result<vulkan::Image> {
+        vulkan::Image::new(
+            &self.allocator,
+            &vk::ImageCreateInfo {
+                image_type: vk::ImageType::TYPE_2D,
+                format,
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_levels: 1,
+                array_layers: 1,
+                samples: self.samples,
+                tiling: vk::ImageTiling::OPTIMAL,
+                // Attachments never leave the render pass, so tilers can keep them in tile memory.
+                usage: usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                sharing_mode: vk::SharingMode::EXCLUSIVE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                ..Default::default()
+            },
+            vulkan::MemoryLocation::GpuOnly,
+        )
+    }
+
+    fn multisampled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+
+    /// Returns whether [`Self::cmd_draw`] can draw the triangle as a wireframe.
+    pub fn supports_wireframe(&self) -> bool {
+        self.wireframe_pipeline != vk::Pipeline::null()
+    }
+
+    /// Records drawing into framebuffer `index` of `framebuffers`, clearing it to `clear_color`
+    /// and drawing a triangle over it if `triangle` is set. The triangle is only outlined if
+    /// `wireframe` is set and the renderer supports it.
+    ///
+    /// The image ends up in the final layout the renderer was created with.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be a graphics command buffer in the recording state, outside of any render pass.
+    pub unsafe fn cmd_draw(
+        &self,
+        cmd: vk::CommandBuffer,
+        framebuffers: &Framebuffers,
+        index: usize,
+        clear_color: [f32; 4],
+        triangle: bool,
+        wireframe: bool,
+    ) {
+        let device = self.device.device();
+        let target = &framebuffers.targets[index];
+        let extent = framebuffers.extent;
+        let clear_color = vk::ClearColorValue {
+            float32: clear_color,
+        };
+
+        unsafe {
+            if self.render_pass == vk::RenderPass::null() {
+                self.cmd_begin_rendering(cmd, target, extent, clear_color);
+            } else {
+                self.cmd_begin_render_pass(cmd, target, extent, clear_color);
+            }
+
+            if triangle {
+                let pipeline = if wireframe && self.supports_wireframe() {
+                    self.wireframe_pipeline
+                } else {
+                    self.triangle_pipeline
+                };
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                device.cmd_set_viewport(
+                    cmd,
+                    0,
+                    &[vk::Viewport {
+                        x: 0.0,
+                        y: 0.0,
+                        width: extent.width as f32,
+                        height: extent.height as f32,
+                        min_depth: 0.0,
+                        max_depth: 1.0,
+                    }],
+                );
+                device.cmd_set_scissor(
+                    cmd,
+                    0,
+                    &[vk::Rect2D {
+                        offset: vk::Offset2D::default(),
+                        extent,
+                    }],
+                );
+                device.cmd_draw(cmd, 3, 1, 0, 0);
+            }
+
+            if self.render_pass == vk::RenderPass::null() {
+                self.cmd_end_rendering(cmd, target);
+            } else {
+                device.cmd_end_render_pass(cmd);
+            }
+        }
+    }
+
+    /// Begins the render pass, which takes care of clearing and layout transitions.
+    unsafe fn cmd_begin_render_pass(
+        &self,
+        cmd: vk::CommandBuffer,
+        target: &RenderTarget,
+        extent: vk::Extent2D,
+        clear_color: vk::ClearColorValue,
+    ) {
+        // Clear values go by attachment index, in the order `create_render_pass` sets them up.
+        let color = vk::ClearValue { color: clear_color };
+        let mut clear_values = vec![color];
+        if self.depth_format.is_some() {
+            clear_values.push(vk::ClearValue {
+                depth_stencil: DEPTH_CLEAR_VALUE,
+            });
+        }
+        if self.multisampled() {
+            clear_values.push(color);
+        }
+
+        unsafe {
+            self.device.device().cmd_begin_render_pass(
+                cmd,
+                &vk::RenderPassBeginInfo {
+                    render_pass: self.render_pass,
+                    framebuffer: target.framebuffer,
+                    render_area: vk::Rect2D {
+                        offset: vk::Offset2D::default(),
+                        extent,
+                    },
+                    clear_value_count: clear_values.len() as u32,
+                    p_clear_values: clear_values.as_ptr(),
+                    ..Default::default()
+                },
+                vk::SubpassContents::INLINE,
+            );
+        }
+    }
+
+    /// Begins dynamic rendering, transitioning the attachments to the right layouts first as
+    /// there is no render pass to do it for us.
+    unsafe fn cmd_begin_rendering(
+        &self,
+        cmd: vk::CommandBuffer,
+        target: &RenderTarget,
+        extent: vk::Extent2D,
+        clear_color: vk::ClearColorValue,
+    ) {
+        let color_barrier = |image| vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: whole_image(vk::ImageAspectFlags::COLOR),
+            ..Default::default()
+        };
+        let mut barriers = vec![color_barrier(target.image)];
+        if let Some((image, _)) = target.multisampled {
+            barriers.push(color_barrier(image));
+        }
+        if let (Some((image, _)), Some(depth_format)) = (target.depth, self.depth_format) {
+            barriers.push(vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image,
+                subresource_range: whole_image(depth_aspects(depth_format)),
+                ..Default::default()
+            });
+        }
+
+        // The same stages as the render pass's external dependency, for the same reasons.
+        let device = self.device.device();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+
+        let color_attachment = match target.multisampled {
+            Some((_, view)) => vk::RenderingAttachmentInfo {
+                image_view: view,
+                image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                resolve_mode: vk::ResolveModeFlags::AVERAGE,
+                resolve_image_view: target.view,
+                resolve_image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                clear_value: vk::ClearValue { color: clear_color },
+                ..Default::default()
+            },
+            None => vk::RenderingAttachmentInfo {
+                image_view: target.view,
+                image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                clear_value: vk::ClearValue { color: clear_color },
+                ..Default::default()
+            },
+        };
+
+        let depth_attachment = target.depth.map(|(_, view)| vk::RenderingAttachmentInfo {
+            image_view: view,
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue {
+                depth_stencil: DEPTH_CLEAR_VALUE,
+            },
+            ..Default::default()
+        });
+        let has_stencil = self
+            .depth_format
+            .is_some_and(|format| depth_aspects(format).contains(vk::ImageAspectFlags::STENCIL));
+
+        unsafe {
+            self.device.cmd_begin_rendering(
+                cmd,
+                &vk::RenderingInfo {
+                    render_area: vk::Rect2D {
+                        offset: vk::Offset2D::default(),
+                        extent,
+                    },
+                    layer_count: 1,
+                    color_attachment_count: 1,
+                    p_color_attachments: &color_attachment,
+                    p_depth_attachment: depth_attachment
+                        .as_ref()
+                        .map_or(ptr::null(), ptr::from_ref),
+                    p_stencil_attachment: depth_attachment
+                        .as_ref()
+                        .filter(|_| has_stencil)
+                        .map_or(ptr::null(), ptr::from_ref),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Ends dynamic rendering and transitions the image to the final layout.
+    unsafe fn cmd_end_rendering(&self, cmd: vk::CommandBuffer, target: &RenderTarget) {
+        let (dst_stage_mask, dst_access_mask) = self.final_access();
+        unsafe {
+            self.device.cmd_end_rendering(cmd);
+
+            self.device.device().cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[vk::ImageMemoryBarrier {
+                    src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    dst_access_mask,
+                    old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    new_layout: self.final_layout,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: target.image,
+                    subresource_range: whole_image(vk::ImageAspectFlags::COLOR),
+                    ..Default::default()
+                }],
+            );
+        }
+    }
+
+    /// Returns the stages and accesses that use images after drawing, as decided by the final
+    /// layout.
+    fn final_access(&self) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+        match self.final_layout {
+            // Presenting waits on a semaphore, which makes the writes visible already.
+            vk::ImageLayout::PRESENT_SRC_KHR => (
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::AccessFlags::empty(),
+            ),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            _ => (
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_READ,
+            ),
+        }
+    }
+
+    fn create_render_pass(&self) -> Result<vk::RenderPass> {
+        let format = self.format;
+        // The swapchain image comes first, then the depth buffer and the multisampled color target
+        // if there are any.
+        let mut attachments = vec![vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            // Every frame is drawn from scratch, so the previous contents don't matter. With
+            // multisampling, the resolve overwrites the whole image anyway.
+            load_op: if self.multisampled() {
+                vk::AttachmentLoadOp::DONT_CARE
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            },
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: self.final_layout,
+            ..Default::default()
+        }];
+
+        let mut depth_attachment_ref = None;
+        if let Some(depth_format) = self.depth_format {
+            depth_attachment_ref = Some(vk::AttachmentReference {
+                attachment: attachments.len() as u32,
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            });
+            // Depth and stencil are only needed while drawing, so they are never stored.
+            attachments.push(vk::AttachmentDescription {
+                format: depth_format,
+                samples: self.samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::CLEAR,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            });
+        }
+
+        let swapchain_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+        let (color_attachment_ref, resolve_attachment_ref) = if self.multisampled() {
+            let color_attachment_ref = vk::AttachmentReference {
+                attachment: attachments.len() as u32,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            };
+            // Only the resolved image is needed after the render pass.
+            attachments.push(vk::AttachmentDescription {
+                format,
+                samples: self.samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            });
+            (color_attachment_ref, Some(swapchain_attachment_ref))
+        } else {
+            (swapchain_attachment_ref, None)
+        };
+
+        let subpass = vk::SubpassDescription {
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            p_resolve_attachments: resolve_attachment_ref
+                .as_ref()
+                .map_or(ptr::null(), ptr::from_ref),
+            p_depth_stencil_attachment: depth_attachment_ref
+                .as_ref()
+                .map_or(ptr::null(), ptr::from_ref),
+            ..Default::default()
+        };
+
+        // The image is only acquired once the acquire semaphore is waited on, at the color
+        // attachment output stage, so the layout transition and clear have to wait until then.
+        // The depth buffer is reused by whichever frame next renders into the same swapchain image,
+        // so its clear has to wait for the previous frame's depth tests as well.
+        let (final_stage_mask, final_access_mask) = self.final_access();
+        let dependencies = [
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            // Whatever comes after drawing has to wait for it, and for the final layout transition.
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: final_stage_mask,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: final_access_mask,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass = unsafe {
+            self.device.device().create_render_pass(
+                &vk::RenderPassCreateInfo {
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    subpass_count: 1,
+                    p_subpasses: &subpass,
+                    dependency_count: dependencies.len() as u32,
+                    p_dependencies: dependencies.as_ptr(),
+                    ..Default::default()
+                },
+                self.device.allocation_callbacks(),
+            )?
+        };
+
+        Ok(render_pass)
+    }
+
+    fn create_triangle_pipeline(&mut self, polygon_mode: vk::PolygonMode) -> Result<vk::Pipeline> {
+        let vertex_shader = self
+            .shaders
+            .get(&shader_source("triangle.vert.spv", TRIANGLE_VERT))?;
+        let fragment_shader = self
+            .shaders
+            .get(&shader_source("triangle.frag.spv", TRIANGLE_FRAG))?;
+        self.create_pipeline(vertex_shader, fragment_shader, polygon_mode)
+    }
+
+    fn create_pipeline(
+        &self,
+        vertex_shader: vk::ShaderModule,
+        fragment_shader: vk::ShaderModule,
+        polygon_mode: vk::PolygonMode,
+    ) -> Result<vk::Pipeline> {
+        let stages = [
+            vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::VERTEX,
+                module: vertex_shader,
+                p_name: SHADER_ENTRY_POINT.as_ptr(),
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                module: fragment_shader,
+                p_name: SHADER_ENTRY_POINT.as_ptr(),
+                ..Default::default()
+            },
+        ];
+
+        // Vertices come from the shader itself.
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+
+        // The viewport and scissor are set while drawing, so that resizing doesn't require new
+        // pipelines.
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: dynamic_states.as_ptr(),
+            ..Default::default()
+        };
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::CLOCKWISE,
+            line_width: 1.0,
+            ..Default::default()
+        };
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: self.samples,
+            ..Default::default()
+        };
+
+        let depth_test = if self.depth_format.is_some() {
+            vk::TRUE
+        } else {
+            vk::FALSE
+        };
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: depth_test,
+            depth_write_enable: depth_test,
+            depth_compare_op: vk::CompareOp::LESS,
+            ..Default::default()
+        };
+
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::FALSE,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            ..Default::default()
+        };
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            attachment_count: 1,
+            p_attachments: &color_blend_attachment,
+            ..Default::default()
+        };
+
+        // Without a render pass, the pipeline has to know the attachment formats up front.
+        let depth_format = self.depth_format.unwrap_or(vk::Format::UNDEFINED);
+        let rendering_create_info = vk::PipelineRenderingCreateInfo {
+            color_attachment_count: 1,
+            p_color_attachment_formats: &self.format,
+            depth_attachment_format: depth_format,
+            stencil_attachment_format: if depth_aspects(depth_format)
+                .contains(vk::ImageAspectFlags::STENCIL)
+            {
+                depth_format
+            } else {
+                vk::Format::UNDEFINED
+            },
+            ..Default::default()
+        };
+
+        let create_info = vk::GraphicsPipelineCreateInfo {
+            p_next: if self.render_pass == vk::RenderPass::null() {
+                (&raw const rendering_create_info).cast()
+            } else {
+                ptr::null()
+            },
+            stage_count: stages.len() as u32,
+            p_stages: stages.as_ptr(),
+            p_vertex_input_state: &vertex_input_state,
+            p_input_assembly_state: &input_assembly_state,
+            p_viewport_state: &viewport_state,
+            p_rasterization_state: &rasterization_state,
+            p_multisample_state: &multisample_state,
+            p_depth_stencil_state: &depth_stencil_state,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: &dynamic_state,
+            layout: self.pipeline_layout,
+            render_pass: self.render_pass,
+            subpass: 0,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            self.device.device().create_graphics_pipelines(
+                self.pipeline_cache.cache(),
+                &[create_info],
+                self.device.allocation_callbacks(),
+            )
+        }
+        .map_err(|(_, err)| err)?;
+
+        Ok(pipelines[0])
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        let device = self.device.device();
+        let allocation_callbacks = self.device.allocation_callbacks();
+
+        // The owner makes sure the GPU is done with everything first.
+        unsafe {
+            device.destroy_pipeline(self.triangle_pipeline, allocation_callbacks);
+            device.destroy_pipeline(self.wireframe_pipeline, allocation_callbacks);
+            device.destroy_pipeline_layout(self.pipeline_layout, allocation_callbacks);
+            device.destroy_render_pass(self.render_pass, allocation_callbacks);
+        }
+    }
+}
+
+/// Image views and framebuffers for rendering into each image of a swapchain.
+///
+/// These have to be dropped before the swapchain, and only once the GPU is done with them.
+pub struct Framebuffers {
+    device: Arc<vulkan::Device>,
+    /// Views of the swapchain images and attachment images, for destroying them.
+    image_views: Vec<vk::ImageView>,
+    /// What to render into for each swapchain image.
+    targets: Vec<RenderTarget>,
+    /// The depth buffers and multisampled color targets of the framebuffers, if any.
+    attachment_images: Vec<vulkan::Image>,
+    extent: vk::Extent2D,
+}
+
+impl Framebuffers {
+    /// Returns the size of the images rendered into.
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Creates a view of all of `image`, which is destroyed along with the framebuffers.
+    fn create_image_view(
+        &mut self,
+        image: vk::Image,
+        format: vk::Format,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<vk::ImageView> {
+        let image_view = unsafe {
+            self.device.device().create_image_view(
+                &vk::ImageViewCreateInfo {
+                    image,
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    format,
+                    subresource_range: whole_image(aspect_mask),
+                    ..Default::default()
+                },
+                self.device.allocation_callbacks(),
+            )?
+        };
+        self.image_views.push(image_view);
+        Ok(image_view)
+    }
+
+    /// Takes ownership of `image` for use as an attachment, returning it along with a view of it.
+    fn add_attachment_image(
+        &mut self,
+        image: vulkan::Image,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<(vk::Image, vk::ImageView)> {
+        let (handle, format) = (image.image(), image.format());
+        self.attachment_images.push(image);
+        Ok((handle, self.create_image_view(handle, format, aspect_mask)?))
+    }
+}
+
+impl Drop for Framebuffers {
+    fn drop(&mut self) {
+        let device = self.device.device();
+        let allocation_callbacks = self.device.allocation_callbacks();
+
+        // The attachment images go once this returns, after the views of them.
+        unsafe {
+            for target in &self.targets {
+                device.destroy_framebuffer(target.framebuffer, allocation_callbacks);
+            }
+            for &image_view in &self.image_views {
+                device.destroy_image_view(image_view, allocation_callbacks);
+            }
+        }
+    }
+}
+
+/// The images and views for rendering into one swapchain image.
+struct RenderTarget {
+    /// Null when rendering dynamically.
+    framebuffer: vk::Framebuffer,
+    image: vk::Image,
+    view: vk::ImageView,
+    depth: Option<(vk::Image, vk::ImageView)>,
+    /// The target to resolve into the swapchain image, when multisampling.
+    multisampled: Option<(vk::Image, vk::ImageView)>,
+}
+
+/// Picks the first of [`DEPTH_FORMATS`] the device can use as a depth attachment.
+fn select_depth_format(device: &vulkan::Device) -> vk::Format {
+    DEPTH_FORMATS
+        .iter()
+        .copied()
+        .find(|&format| {
+            let properties = unsafe {
+                device
+                    .instance()
+                    .instance()
+                    .get_physical_device_format_properties(device.physical_device(), format)
+            };
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .unwrap_or(vk::Format::D16_UNORM)
+}
+
+/// Returns the aspects of depth format `format`, which may include stencil.
+fn depth_aspects(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+/// Returns the sample count for `requested` samples per pixel if the device supports it for color
+/// (and depth, with `depth`) attachments, or a single sample otherwise.
+fn select_sample_count(
+    device: &vulkan::Device,
+    requested: u32,
+    depth: bool,
+) -> vk::SampleCountFlags {
+    let requested_flags = vk::SampleCountFlags::from_raw(requested);
+    if requested_flags == vk::SampleCountFlags::TYPE_1 {
+        return requested_flags;
+    }
+
+    let limits = &device.properties().limits;
+    let mut supported = limits.framebuffer_color_sample_counts;
+    if depth {
+        supported &= limits.framebuffer_depth_sample_counts;
+    }
+
+    if supported.contains(requested_flags) {
+        debug!("rendering with {requested}x MSAA");
+        requested_flags
+    } else {
+        warn!("{requested}x MSAA not supported, rendering without");
+        vk::SampleCountFlags::TYPE_1
+    }
+}
+
+fn whole_image(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+/// Returns where to load the shader called `name` from: the directory named by `SHADER_DIR_VAR`
+/// if it is set, and `embedded` otherwise.
+pub fn shader_source(name: &str, embedded: &'static [u8]) -> ShaderSource {
+    match env::var_os(SHADER_DIR_VAR) {
+        Some(dir) => ShaderSource::Path(Path::new(&dir).join(name)),
+        None => ShaderSource::Embedded(embedded),
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Error, Result};
+
+/// Directory under the state directory that saved geometry and the session ID go in.
+const STATE_DIR_NAME: &str = "wayland-thing";
+
+/// The size and state of a window when it was last closed, used as the defaults for the window
+/// opened in its place on the next run.
+///
+/// Each window's geometry is stored in its own file under `$XDG_STATE_HOME/wayland-thing`, as a
+/// single line with the content size followed by any states, e.g. `800 600 maximized`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavedGeometry {
+    /// The content size when floating, which the window goes back to when leaving maximized or
+    /// fullscreen.
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+    pub fullscreen: bool,
+}
+
+impl SavedGeometry {
+    /// Loads the geometry saved for the window named `name`.
+    ///
+    /// Missing or malformed files aren't errors, as the window can always fall back to the default
+    /// geometry.
+    pub fn load(name: &str) -> Option<Self> {
+        let contents = fs::read_to_string(state_path(name)?).ok()?;
+        parse(&contents)
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        let mut contents = format!("{} {}", self.width, self.height);
+        if self.maximized {
+            contents.push_str(" maximized");
+        }
+        if self.fullscreen {
+            contents.push_str(" fullscreen");
+        }
+        contents.push('\n');
+
+        write_state(name, &contents)
+    }
+}
+
+/// Writes `contents` to state file `name`, creating the state directory if needed.
+pub fn write_state(name: &str, contents: &str) -> Result<()> {
+    let path = state_path(name).ok_or(Error::NoStateDir)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Returns the path of state file `name`, following the XDG base directory spec.
+pub fn state_path(name: &str) -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        // Relative paths are to be ignored, as per the spec.
+        .filter(|path| path.is_absolute())
+        .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".local/state")))?;
+    Some(state_home.join(STATE_DIR_NAME).join(name))
+}
+
+fn parse(contents: &str) -> Option<SavedGeometry> {
+    let mut fields = contents.split_whitespace();
+    let mut geometry = SavedGeometry {
+        width: fields.next()?.parse().ok()?,
+        height: fields.next()?.parse().ok()?,
+        maximized: false,
+        fullscreen: false,
+    };
+    if geometry.width == 0 || geometry.height == 0 {
+        return None;
+    }
+
+    for state in fields {
+        match state {
+            "maximized" => geometry.maximized = true,
+            "fullscreen" => geometry.fullscreen = true,
+            _ => return None,
+        }
+    }
+
+    Some(geometry)
+}
@@ -0,0 +1,160 @@
+use std::fs;
+
+use log::{debug, info, warn};
+use wayland_client::{Connection, Dispatch, QueueHandle, delegate_noop};
+use wayland_protocols::xdg::shell::client::xdg_toplevel::XdgToplevel;
+
+use crate::{
+    app::{App, WindowId},
+    error::Result,
+    saved_geometry::{state_path, write_state},
+};
+
+use self::protocol::{
+    xdg_session_manager_v1::{self, XdgSessionManagerV1},
+    xdg_session_v1::{self, XdgSessionV1},
+    xdg_toplevel_session_v1::{self, XdgToplevelSessionV1},
+};
+
+/// Bindings for xdg-session-management, which `wayland-protocols` doesn't ship yet.
+pub mod protocol {
+    #![allow(dead_code, non_camel_case_types, unused_imports, clippy::all)]
+
+    use wayland_client;
+    use wayland_client::protocol::*;
+    use wayland_protocols::xdg::shell::client::*;
+
+    pub mod __interfaces {
+        use wayland_client::protocol::__interfaces::*;
+        use wayland_protocols::xdg::shell::client::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocols/xdg-session-management-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_client_code!("protocols/xdg-session-management-v1.xml");
+}
+
+/// State file the session ID is kept in between runs.
+const ID_FILE_NAME: &str = "session";
+
+/// A compositor-side session, in which the compositor remembers where our windows were (and
+/// more), so that it can put them back there on the next run.
+///
+/// Sessions are identified by a string the compositor hands out, which has to be stored
+/// somewhere to restore the session later.
+pub struct Session {
+    session: XdgSessionV1,
+    /// The ID we asked to restore, if any.
+    requested_id: Option<String>,
+    /// The ID to restore the session with on the next run, once the compositor has settled on it.
+    id: Option<String>,
+}
+
+impl Session {
+    /// Restores the session with ID `id`, or starts a new one if there is no ID or the compositor
+    /// doesn't know it (anymore).
+    pub fn new(manager: &XdgSessionManagerV1, id: Option<String>, qh: &QueueHandle<App>) -> Self {
+        let reason = if id.is_some() {
+            xdg_session_manager_v1::Reason::SessionRestore
+        } else {
+            xdg_session_manager_v1::Reason::Launch
+        };
+        let session = manager.get_session(reason, id.clone(), qh, ());
+
+        Self {
+            session,
+            requested_id: id,
+            id: None,
+        }
+    }
+
+    /// Returns the ID to restore the session with, once the compositor has created or restored it.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Adds window `id`'s toplevel to the session, restoring its state from the last run if the
+    /// session knows it.
+    ///
+    /// This has to happen before the toplevel's first commit.
+    pub fn add_toplevel(
+        &self,
+        toplevel: &XdgToplevel,
+        id: WindowId,
+        qh: &QueueHandle<App>,
+    ) -> XdgToplevelSessionV1 {
+        // Unlike `add_toplevel`, this works for names the session doesn't know yet as well.
+        self.session
+            .restore_toplevel(toplevel, id.session_name(), qh, id)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Keeps the session around for the next run, unlike `remove`.
+        self.session.destroy();
+    }
+}
+
+/// Loads the session ID stored by [`save_id`] on the last run, if there is one.
+pub fn load_id() -> Option<String> {
+    let contents = fs::read_to_string(state_path(ID_FILE_NAME)?).ok()?;
+    let id = contents.trim();
+    (!id.is_empty()).then(|| id.to_owned())
+}
+
+/// Stores the session ID for the next run to restore.
+pub fn save_id(id: &str) -> Result<()> {
+    write_state(ID_FILE_NAME, &format!("{id}\n"))
+}
+
+impl Dispatch<XdgSessionV1, ()> for App {
+    fn event(
+        app: &mut Self,
+        _session: &XdgSessionV1,
+        event: xdg_session_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(session) = app.session_mut() else {
+            return;
+        };
+
+        match event {
+            xdg_session_v1::Event::Created { session_id } => {
+                if session.requested_id.is_some() {
+                    info!("could not restore session, created a new one");
+                }
+                info!("created session, restore it with `--session {session_id}`");
+                session.id = Some(session_id);
+            }
+            xdg_session_v1::Event::Restored => {
+                debug!("restored session");
+                session.id = session.requested_id.clone();
+            }
+            xdg_session_v1::Event::Replaced => {
+                warn!("session was taken over by another client");
+                app.end_session();
+            }
+        }
+    }
+}
+
+impl Dispatch<XdgToplevelSessionV1, WindowId> for App {
+    fn event(
+        _app: &mut Self,
+        _toplevel_session: &XdgToplevelSessionV1,
+        event: xdg_toplevel_session_v1::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // The compositor applies the restored state through the initial configure.
+            xdg_toplevel_session_v1::Event::Restored => debug!("restored {id:?} from session"),
+        }
+    }
+}
+
+delegate_noop!(App: XdgSessionManagerV1);
@@ -0,0 +1,294 @@
+use std::{
+    os::fd::{AsFd, OwnedFd},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use log::{debug, warn};
+use memmap2::MmapMut;
+use rustix::fs::{MemfdFlags, SealFlags, fcntl_get_seals, fstat, ftruncate, memfd_create};
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+    },
+};
+
+use crate::error::{Error, Result};
+
+/// A pool of equally-sized ARGB shm buffers, which are recycled once the compositor releases them.
+pub struct BufferPool {
+    width: u32,
+    height: u32,
+    memfd: OwnedFd,
+    /// Whether the memory is ours to grow. Memory supplied by someone else is never truncated.
+    owns_memory: bool,
+    mmap: MmapMut,
+    pool: WlShmPool,
+    /// Offset of the first buffer, past any retired buffers the compositor may still be reading.
+    base_offset: usize,
+    buffers: Vec<(WlBuffer, Arc<BufferHandle>)>,
+    /// Buffers from before the last `reconfigure`, which are destroyed once released.
+    retired: Vec<(WlBuffer, Arc<BufferHandle>)>,
+    /// Number of buffers currently loaned to the compositor, shared with all handles.
+    loaned: Arc<AtomicUsize>,
+}
+
+/// Tracks whether a buffer is currently loaned to the compositor.
+pub struct BufferHandle {
+    in_use: AtomicBool,
+    retired: AtomicBool,
+    loaned: Arc<AtomicUsize>,
+}
+
+impl BufferHandle {
+    fn acquire(&self) {
+        self.in_use.store(true, Ordering::Relaxed);
+        self.loaned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn release(&self) {
+        let was_in_use = self.in_use.swap(false, Ordering::Relaxed);
+        assert!(was_in_use, "buffer released twice");
+        self.loaned.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl BufferPool {
+    pub fn new<State>(shm: &WlShm, qh: &QueueHandle<State>, width: u32, height: u32) -> Result<Self>
+    where
+        State: Dispatch<WlShmPool, ()> + 'static,
+    {
+        let memfd = memfd_create("wayland-thing-shm", MemfdFlags::CLOEXEC)?;
+
+        // Start out with room for a single buffer.
+        ftruncate(&memfd, buffer_size(width, height) as u64)?;
+
+        Self::with_memory(shm, qh, memfd, true, width, height)
+    }
+
+    /// Creates a pool backed by existing shared memory, such as a memfd supplied by another
+    /// process.
+    ///
+    /// The memory is never resized, so only as many buffers as fit in it can be allocated. It
+    /// should be sealed against shrinking, since touching the mapping after the file has been
+    /// truncated raises `SIGBUS`.
+    pub fn from_fd<State>(
+        shm: &WlShm,
+        qh: &QueueHandle<State>,
+        fd: OwnedFd,
+        width: u32,
+        height: u32,
+    ) -> Result<Self>
+    where
+        State: Dispatch<WlShmPool, ()> + 'static,
+    {
+        if (fstat(&fd)?.st_size as usize) < buffer_size(width, height) {
+            return Err(Error::ShmPoolTooSmall);
+        }
+
+        let sealed = fcntl_get_seals(&fd).is_ok_and(|seals| seals.contains(SealFlags::SHRINK));
+        if !sealed {
+            warn!("shared memory for buffer pool is not sealed against shrinking");
+        }
+
+        Self::with_memory(shm, qh, fd, false, width, height)
+    }
+
+    fn with_memory<State>(
+        shm: &WlShm,
+        qh: &QueueHandle<State>,
+        memfd: OwnedFd,
+        owns_memory: bool,
+        width: u32,
+        height: u32,
+    ) -> Result<Self>
+    where
+        State: Dispatch<WlShmPool, ()> + 'static,
+    {
+        let mmap = unsafe { MmapMut::map_mut(&memfd)? };
+        let pool = shm.create_pool(memfd.as_fd(), pool_len(mmap.len())?, qh, ());
+
+        Ok(Self {
+            width,
+            height,
+            memfd,
+            owns_memory,
+            mmap,
+            pool,
+            base_offset: 0,
+            buffers: Vec::new(),
+            retired: Vec::new(),
+            loaned: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Returns a buffer that isn't held by the compositor along with its pixels, allocating a new
+    /// one if necessary.
+    ///
+    /// The buffer is considered loaned to the compositor until it is released, so the caller is
+    /// expected to attach and commit it.
+    pub fn get_buffer<State>(&mut self, qh: &QueueHandle<State>) -> Result<(WlBuffer, &mut [u32])>
+    where
+        State: Dispatch<WlBuffer, Arc<BufferHandle>> + 'static,
+    {
+        let idx = match self
+            .buffers
+            .iter()
+            .position(|(_, handle)| !handle.in_use.load(Ordering::Relaxed))
+        {
+            Some(idx) => idx,
+            None => self.add_buffer(qh)?,
+        };
+
+        let (buffer, handle) = &self.buffers[idx];
+        handle.acquire();
+
+        let size = buffer_size(self.width, self.height);
+        let offset = self.base_offset + idx * size;
+        let bytes = &mut self.mmap[offset..offset + size];
+
+        // The mapping is page-aligned and buffer sizes are multiples of 4.
+        let (_, pixels, _) = unsafe { bytes.align_to_mut::<u32>() };
+
+        Ok((buffer.clone(), pixels))
+    }
+
+    /// Switches the pool over to buffers of a new size, reusing its memory.
+    ///
+    /// Buffers of the old size that are still loaned to the compositor are destroyed when they are
+    /// released instead of being recycled, and their memory is left untouched until then.
+    pub fn reconfigure(&mut self, width: u32, height: u32) {
+        self.retired
+            .retain(|(_, handle)| handle.in_use.load(Ordering::Relaxed));
+
+        for (buffer, handle) in self.buffers.drain(..) {
+            if handle.in_use.load(Ordering::Relaxed) {
+                handle.retired.store(true, Ordering::Relaxed);
+                self.retired.push((buffer, handle));
+            } else {
+                buffer.destroy();
+            }
+        }
+
+        // `wl_shm_pool` can only grow, so new buffers go after everything the compositor might
+        // still be reading and the space is reclaimed on the next reconfigure once it's free.
+        self.base_offset = if self.retired.is_empty() {
+            0
+        } else {
+            self.mmap.len()
+        };
+
+        self.width = width;
+        self.height = height;
+    }
+
+    fn add_buffer<State>(&mut self, qh: &QueueHandle<State>) -> Result<usize>
+    where
+        State: Dispatch<WlBuffer, Arc<BufferHandle>> + 'static,
+    {
+        let idx = self.buffers.len();
+        let size = buffer_size(self.width, self.height);
+        let offset = self.base_offset + idx * size;
+        let new_len = offset + size;
+
+        if new_len > self.mmap.len() {
+            if !self.owns_memory {
+                return Err(Error::ShmPoolTooSmall);
+            }
+
+            let pool_len = pool_len(new_len)?;
+            ftruncate(&self.memfd, new_len as u64)?;
+            self.mmap = unsafe { MmapMut::map_mut(&self.memfd)? };
+            self.pool.resize(pool_len);
+        }
+
+        let handle = Arc::new(BufferHandle {
+            in_use: AtomicBool::new(false),
+            retired: AtomicBool::new(false),
+            loaned: Arc::clone(&self.loaned),
+        });
+
+        let buffer = self.pool.create_buffer(
+            offset as i32,
+            self.width as i32,
+            self.height as i32,
+            (self.width * 4) as i32,
+            wl_shm::Format::Argb8888,
+            qh,
+            Arc::clone(&handle),
+        );
+
+        self.buffers.push((buffer, handle));
+        Ok(idx)
+    }
+}
+
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        // The last buffer attached to a surface is never released if the surface is destroyed
+        // first, so a single outstanding buffer is normal. Anything beyond that hints at a leak.
+        let loaned = self.loaned.load(Ordering::Relaxed);
+        let total = self.buffers.len() + self.retired.len();
+        if loaned > 1 {
+            warn!(
+                "dropping buffer pool with {loaned} of {total} buffers still loaned to the compositor"
+            );
+        } else if loaned == 1 {
+            debug!(
+                "dropping buffer pool with its last attached buffer still loaned to the compositor"
+            );
+        }
+
+        for (buffer, _) in &self.buffers {
+            buffer.destroy();
+        }
+        // Released retired buffers have already been destroyed.
+        for (buffer, handle) in &self.retired {
+            if handle.in_use.load(Ordering::Relaxed) {
+                buffer.destroy();
+            }
+        }
+        self.pool.destroy();
+    }
+}
+
+impl<State> Dispatch<WlBuffer, Arc<BufferHandle>, State> for BufferPool
+where
+    State: Dispatch<WlBuffer, Arc<BufferHandle>>,
+{
+    fn event(
+        _state: &mut State,
+        buffer: &WlBuffer,
+        event: wl_buffer::Event,
+        handle: &Arc<BufferHandle>,
+        _conn: &Connection,
+        _qh: &QueueHandle<State>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            handle.release();
+            if handle.retired.load(Ordering::Relaxed) {
+                buffer.destroy();
+            }
+        }
+    }
+}
+
+/// Rounds a window scale up to an integer buffer scale, leaving any further downscaling to the
+/// compositor.
+pub fn to_buffer_scale(scale: f64) -> u32 {
+    (scale.ceil() as u32).max(1)
+}
+
+fn buffer_size(width: u32, height: u32) -> usize {
+    width as usize * height as usize * 4
+}
+
+/// Converts a pool size for `wl_shm`, which only takes sizes that fit in an `i32`.
+fn pool_len(len: usize) -> Result<i32> {
+    i32::try_from(len).map_err(|_| Error::ShmPoolTooLarge)
+}
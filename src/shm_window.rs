@@ -0,0 +1,334 @@
+use std::sync::Arc;
+
+use log::{debug, info};
+use wayland_client::{
+    Connection, Dispatch, EventQueue, QueueHandle, delegate_dispatch, delegate_noop,
+    globals::{GlobalList, GlobalListContents},
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_callback::{self, WlCallback},
+        wl_compositor::WlCompositor,
+        wl_registry::{self, WlRegistry},
+        wl_shm::WlShm,
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+};
+use wayland_protocols::{
+    wp::viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+    xdg::shell::client::{
+        xdg_surface::{self, XdgSurface},
+        xdg_toplevel::{self, XdgToplevel},
+        xdg_wm_base::{self, XdgWmBase},
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    headless::{self, HeadlessRenderer},
+    options::Options,
+    shm::{BufferHandle, BufferPool},
+    vulkan,
+    window::bind_global,
+};
+
+/// The size to open at, the same as a regular window's initial size.
+const WIDTH: u32 = 500;
+const HEIGHT: u32 = 500;
+
+/// Shows a window drawn into shm buffers instead of a swapchain, attaching a new one on every frame
+/// callback.
+///
+/// With `--shm-present`, frames are rendered offscreen with Vulkan and read back into the buffers.
+/// Otherwise Vulkan isn't touched at all, for running on machines without a usable GPU, and the
+/// window is filled with the clear color by stretching a single-pixel buffer over it with the
+/// viewport.
+pub fn run(
+    globals: &GlobalList,
+    mut queue: EventQueue<ShmWindow>,
+    options: &Options,
+) -> Result<()> {
+    let qh = queue.handle();
+
+    let compositor: WlCompositor = bind_global(globals, &qh, 4..=6)?;
+    let shm: WlShm = bind_global(globals, &qh, 1..=2)?;
+    let xdg_wm_base: XdgWmBase = bind_global(globals, &qh, 1..=6)?;
+    let viewporter: WpViewporter = bind_global(globals, &qh, 1..=1)?;
+
+    let surface = compositor.create_surface(&qh, ());
+    let viewport = viewporter.get_viewport(&surface, &qh, ());
+    let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, &qh, ());
+    let xdg_toplevel = xdg_surface.get_toplevel(&qh, ());
+    xdg_toplevel.set_title("Wayland Thing (shm)".to_owned());
+    xdg_toplevel.set_app_id("wayland-thing".to_owned());
+
+    // Nothing may be attached before the first configure.
+    surface.commit();
+
+    let (content, pool) = if options.shm_present {
+        let device = headless::create_device(options)?;
+        let renderer = HeadlessRenderer::for_demo(&device, options, WIDTH, HEIGHT)?;
+        info!("rendering offscreen, presenting through shm buffers");
+        (
+            Content::Rendered(Box::new(Offscreen {
+                device,
+                renderer,
+                options: options.clone(),
+            })),
+            BufferPool::new(&shm, &qh, WIDTH, HEIGHT)?,
+        )
+    } else {
+        info!("running without a GPU, drawing into shm buffers");
+        viewport.set_destination(WIDTH as i32, HEIGHT as i32);
+        (
+            Content::Color(argb(options.clear_color)),
+            BufferPool::new(&shm, &qh, 1, 1)?,
+        )
+    };
+
+    let mut window = ShmWindow {
+        surface,
+        viewport,
+        xdg_surface,
+        xdg_toplevel,
+        pool,
+        content,
+        width: WIDTH,
+        height: HEIGHT,
+        pending_size: None,
+        configured: false,
+        closed: false,
+        frames: 0,
+        fatal_error: None,
+    };
+
+    while !window.closed {
+        queue.blocking_dispatch(&mut window)?;
+        if let Some(err) = window.fatal_error.take() {
+            return Err(err);
+        }
+    }
+
+    debug!("presented {} shm frames", window.frames);
+    Ok(())
+}
+
+/// A toplevel showing shm buffers, with all of its state in one place since there is nothing to
+/// share it with.
+pub struct ShmWindow {
+    surface: WlSurface,
+    viewport: WpViewport,
+    xdg_surface: XdgSurface,
+    xdg_toplevel: XdgToplevel,
+    /// Holds buffers of the window's size for rendered content, or of a single pixel otherwise.
+    pool: BufferPool,
+    content: Content,
+    width: u32,
+    height: u32,
+    /// The size from the last toplevel configure, applied once the surface configure arrives.
+    pending_size: Option<(u32, u32)>,
+    configured: bool,
+    closed: bool,
+    frames: u64,
+    fatal_error: Option<Error>,
+}
+
+/// What the window shows.
+enum Content {
+    /// A single pixel, as premultiplied ARGB, stretched over the window.
+    Color(u32),
+    /// Frames rendered offscreen at the window's size and read back into shm buffers.
+    Rendered(Box<Offscreen>),
+}
+
+/// Renders the demo offscreen as `--headless` does, at the window's size.
+struct Offscreen {
+    /// Kept around to recreate the renderer when the window is resized.
+    device: Arc<vulkan::Device>,
+    renderer: HeadlessRenderer,
+    options: Options,
+}
+
+impl ShmWindow {
+    fn configure(&mut self, qh: &QueueHandle<Self>) -> Result<()> {
+        if let Some((width, height)) = self.pending_size.take()
+            && (width, height) != (self.width, self.height)
+        {
+            self.resize(width, height)?;
+        }
+
+        if self.configured {
+            // The next frame picks up the new size.
+            return Ok(());
+        }
+
+        // Frame callbacks keep things going from here on.
+        self.configured = true;
+        self.draw(qh)
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        self.width = width;
+        self.height = height;
+
+        match &mut self.content {
+            Content::Color(_) => self.viewport.set_destination(width as i32, height as i32),
+            Content::Rendered(offscreen) => {
+                // Buffers still held by the compositor keep their old size until released.
+                self.pool.reconfigure(width, height);
+                offscreen.renderer = HeadlessRenderer::for_demo(
+                    &offscreen.device,
+                    &offscreen.options,
+                    width,
+                    height,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attaches a fresh buffer and asks for a frame callback to draw the next one.
+    fn draw(&mut self, qh: &QueueHandle<Self>) -> Result<()> {
+        let (width, height) = match &mut self.content {
+            Content::Color(color) => {
+                let (buffer, pixels) = self.pool.get_buffer(qh)?;
+                pixels[0] = *color;
+                self.surface.attach(Some(&buffer), 0, 0);
+                (1, 1)
+            }
+            Content::Rendered(offscreen) => {
+                // This waits for the GPU to finish copying the frame out, so the pixels are
+                // complete by the time they are copied into a buffer the compositor released.
+                let rgba = offscreen.renderer.render_demo(&offscreen.options)?;
+                let (buffer, pixels) = self.pool.get_buffer(qh)?;
+                for (pixel, rgba) in pixels.iter_mut().zip(rgba.chunks_exact(4)) {
+                    *pixel = u32::from_be_bytes([rgba[3], rgba[0], rgba[1], rgba[2]]);
+                }
+                self.surface.attach(Some(&buffer), 0, 0);
+                (self.width, self.height)
+            }
+        };
+
+        self.surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.surface.frame(qh, ());
+        self.surface.commit();
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn record_result(&mut self, result: Result<()>) {
+        if let Err(err) = result
+            && self.fatal_error.is_none()
+        {
+            self.fatal_error = Some(err);
+        }
+    }
+}
+
+impl Drop for ShmWindow {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+        self.xdg_toplevel.destroy();
+        self.xdg_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+/// Converts an sRGB color to an opaque ARGB pixel, which shm buffers store without any further
+/// encoding.
+fn argb(color: [f32; 3]) -> u32 {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u32);
+    0xff00_0000 | r << 16 | g << 8 | b
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for ShmWindow {
+    fn event(
+        _window: &mut Self,
+        _registry: &WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Globals are only bound at startup.
+    }
+}
+
+delegate_noop!(ShmWindow: WlCompositor);
+delegate_noop!(ShmWindow: ignore WlSurface);
+delegate_noop!(ShmWindow: ignore WlShm);
+delegate_noop!(ShmWindow: WlShmPool);
+delegate_noop!(ShmWindow: WpViewporter);
+delegate_noop!(ShmWindow: WpViewport);
+
+delegate_dispatch!(ShmWindow: [WlBuffer: Arc<BufferHandle>] => BufferPool);
+
+impl Dispatch<XdgWmBase, ()> for ShmWindow {
+    fn event(
+        _window: &mut Self,
+        xdg_wm_base: &XdgWmBase,
+        event: xdg_wm_base::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            xdg_wm_base.pong(serial)
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, ()> for ShmWindow {
+    fn event(
+        window: &mut Self,
+        xdg_surface: &XdgSurface,
+        event: xdg_surface::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+            let result = window.configure(qh);
+            window.record_result(result);
+        }
+    }
+}
+
+impl Dispatch<XdgToplevel, ()> for ShmWindow {
+    fn event(
+        window: &mut Self,
+        _xdg_toplevel: &XdgToplevel,
+        event: xdg_toplevel::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            xdg_toplevel::Event::Close => window.closed = true,
+            // Zero leaves the size up to us.
+            xdg_toplevel::Event::Configure { width, height, .. } if width > 0 && height > 0 => {
+                window.pending_size = Some((width as u32, height as u32));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlCallback, ()> for ShmWindow {
+    fn event(
+        window: &mut Self,
+        _callback: &WlCallback,
+        event: wl_callback::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            let result = window.draw(qh);
+            window.record_result(result);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_shm::WlShm,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
+        wl_surface::{self, WlSurface},
+    },
+};
+use wayland_protocols::wp::viewporter::client::{
+    wp_viewport::WpViewport, wp_viewporter::WpViewporter,
+};
+
+use crate::{app::App, draw::Canvas, error::Result, shm::BufferPool};
+
+// 75% SMPTE color bars, as ARGB.
+const COLOR_BARS: [u32; 7] = [
+    0xffbfbfbf, 0xffbfbf00, 0xff00bfbf, 0xff00bf00, 0xffbf00bf, 0xffbf0000, 0xff0000bf,
+];
+const BLACK: u32 = 0xff000000;
+const WHITE: u32 = 0xffffffff;
+const CHECKER_TILE: [u32; 4] = [WHITE, BLACK, BLACK, WHITE];
+
+/// Marks the test pattern's surface, so that its events aren't mistaken for the main surface's.
+pub struct TestPatternSurface;
+
+/// Color bars over a one-pixel checkerboard, covering the whole window as a subsurface.
+///
+/// The pattern is always drawn at the exact physical size of the window and mapped back to its
+/// logical size with a viewport, so any blur or misalignment introduced by scaling shows up in the
+/// checkerboard.
+pub struct TestPattern {
+    surface: WlSurface,
+    subsurface: WlSubsurface,
+    viewport: WpViewport,
+    buffers: BufferPool,
+    physical_size: (u32, u32),
+}
+
+impl TestPattern {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        compositor: &WlCompositor,
+        subcompositor: &WlSubcompositor,
+        viewporter: &WpViewporter,
+        shm: &WlShm,
+        parent: &WlSurface,
+        logical_size: (u32, u32),
+        physical_size: (u32, u32),
+        qh: &QueueHandle<App>,
+    ) -> Result<Self> {
+        let surface = compositor.create_surface(qh, TestPatternSurface);
+        let subsurface = subcompositor.get_subsurface(&surface, parent, qh, ());
+        let viewport = viewporter.get_viewport(&surface, qh, ());
+
+        let (logical_width, logical_height) = logical_size;
+        viewport.set_destination(logical_width as i32, logical_height as i32);
+
+        let (physical_width, physical_height) = physical_size;
+        let buffers = BufferPool::new(shm, qh, physical_width, physical_height)?;
+
+        let mut test_pattern = Self {
+            surface,
+            subsurface,
+            viewport,
+            buffers,
+            physical_size,
+        };
+        test_pattern.redraw(qh)?;
+
+        Ok(test_pattern)
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Stretches the pattern over a new logical size, without re-rendering it.
+    pub fn set_logical_size(&mut self, logical_size: (u32, u32)) {
+        let (logical_width, logical_height) = logical_size;
+        self.viewport
+            .set_destination(logical_width as i32, logical_height as i32);
+    }
+
+    /// Re-renders the pattern at a new physical size, keeping its logical size.
+    pub fn set_physical_size(
+        &mut self,
+        physical_size: (u32, u32),
+        qh: &QueueHandle<App>,
+    ) -> Result<()> {
+        if physical_size != self.physical_size {
+            self.physical_size = physical_size;
+
+            let (width, height) = physical_size;
+            self.buffers.reconfigure(width, height);
+            self.redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Draws into a fresh buffer and damages all of it, e.g. in case the compositor dropped the old
+    /// contents.
+    pub fn redraw(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        let (width, height) = self.physical_size;
+
+        let (buffer, pixels) = self.buffers.get_buffer(qh)?;
+        draw(pixels, width, height);
+
+        // The subsurface is synchronized, so this only takes effect with the parent's next commit.
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.surface.commit();
+
+        Ok(())
+    }
+}
+
+impl Drop for TestPattern {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+        self.subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+/// Draws the pattern into `width`x`height` ARGB pixels.
+pub fn draw(pixels: &mut [u32], width: u32, height: u32) {
+    let mut canvas = Canvas::new(pixels, width, height);
+
+    // Tile the checkerboard over everything and cover the top with the bars, so that the tiles
+    // stay aligned to even coordinates.
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            canvas.blit(x as i32, y as i32, &CHECKER_TILE, 2, 2);
+        }
+    }
+
+    let bars_height = height * 2 / 3;
+    let bar_count = COLOR_BARS.len() as u32;
+    for (i, color) in (0..).zip(COLOR_BARS) {
+        let start = (i * width).div_ceil(bar_count);
+        let end = ((i + 1) * width).div_ceil(bar_count);
+        canvas.fill_rect(start as i32, 0, end - start, bars_height, color);
+    }
+}
+
+impl Dispatch<WlSurface, TestPatternSurface> for App {
+    fn event(
+        _app: &mut Self,
+        _surface: &WlSurface,
+        _event: wl_surface::Event,
+        _data: &TestPatternSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
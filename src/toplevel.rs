@@ -0,0 +1,88 @@
+use bitflags::bitflags;
+use wayland_protocols::xdg::shell::client::xdg_toplevel::{State, WmCapabilities as Capability};
+
+use crate::window::decode_u32_array;
+
+bitflags! {
+    /// The states a toplevel was last configured with.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct ToplevelStates: u32 {
+        const MAXIMIZED = 1 << 0;
+        const FULLSCREEN = 1 << 1;
+        /// An interactive resize is in progress.
+        const RESIZING = 1 << 2;
+        /// The window has focus, and should look like it.
+        const ACTIVATED = 1 << 3;
+        const TILED_LEFT = 1 << 4;
+        const TILED_RIGHT = 1 << 5;
+        const TILED_TOP = 1 << 6;
+        const TILED_BOTTOM = 1 << 7;
+        /// The window isn't visible at all, so there is no point in drawing it.
+        const SUSPENDED = 1 << 8;
+    }
+}
+
+bitflags! {
+    /// The window management features the compositor offers for a toplevel.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct WmCapabilities: u32 {
+        const WINDOW_MENU = 1 << 0;
+        const MAXIMIZE = 1 << 1;
+        const FULLSCREEN = 1 << 2;
+        const MINIMIZE = 1 << 3;
+    }
+}
+
+impl ToplevelStates {
+    /// Decodes the `states` array of an `xdg_toplevel.configure` event.
+    ///
+    /// States we don't know about are ignored, as newer compositors may send them.
+    pub fn from_array(states: &[u8]) -> Self {
+        decode_u32_array(states)
+            .filter_map(|state| State::try_from(state).ok())
+            .map(|state| match state {
+                State::Maximized => Self::MAXIMIZED,
+                State::Fullscreen => Self::FULLSCREEN,
+                State::Resizing => Self::RESIZING,
+                State::Activated => Self::ACTIVATED,
+                State::TiledLeft => Self::TILED_LEFT,
+                State::TiledRight => Self::TILED_RIGHT,
+                State::TiledTop => Self::TILED_TOP,
+                State::TiledBottom => Self::TILED_BOTTOM,
+                State::Suspended => Self::SUSPENDED,
+                _ => Self::empty(),
+            })
+            .collect()
+    }
+
+    /// Returns whether the window is free to be any size, rather than having it dictated by the
+    /// compositor.
+    pub fn is_floating(self) -> bool {
+        !self.intersects(
+            Self::MAXIMIZED
+                | Self::FULLSCREEN
+                | Self::TILED_LEFT
+                | Self::TILED_RIGHT
+                | Self::TILED_TOP
+                | Self::TILED_BOTTOM,
+        )
+    }
+}
+
+impl WmCapabilities {
+    /// Decodes the `capabilities` array of an `xdg_toplevel.wm_capabilities` event.
+    ///
+    /// As with states, unknown capabilities are ignored.
+    pub fn from_array(capabilities: &[u8]) -> Self {
+        decode_u32_array(capabilities)
+            .filter_map(|capability| Capability::try_from(capability).ok())
+            .map(|capability| match capability {
+                Capability::WindowMenu => Self::WINDOW_MENU,
+                Capability::Maximize => Self::MAXIMIZE,
+                Capability::Fullscreen => Self::FULLSCREEN,
+                Capability::Minimize => Self::MINIMIZE,
+                _ => Self::empty(),
+            })
+            .collect()
+    }
+}
@@ -1,117 +1,681 @@
-use std::{ffi::CStr, sync::Arc};
+use std::{
+    borrow::Cow,
+    env,
+    ffi::{CStr, c_void},
+    iter, mem,
+    ops::Range,
+    ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use anyhow::{Result, anyhow};
-use ash::{khr, vk};
-use log::info;
+use ash::{ext, khr, vk};
+use log::{Level, debug, info, log, trace, warn};
+
+use crate::error::{Error, Result};
+
+mod counting_allocator;
+pub mod pipeline_cache;
+pub mod shaders;
+pub mod upload;
+
+/// Host memory allocation callbacks, used for every Vulkan object created through an [`Instance`]
+/// and the devices created from it.
+pub struct AllocationCallbacks(vk::AllocationCallbacks<'static>);
+
+impl AllocationCallbacks {
+    /// Returns callbacks allocating through the global allocator, which count what they hand out
+    /// and log the totals once the instance using them is destroyed.
+    pub fn counting() -> Self {
+        Self(counting_allocator::callbacks())
+    }
+}
+
+/// Environment variable picking the device to render with, by index (as logged at debug level),
+/// UUID or name. Without it, discrete GPUs are preferred over integrated ones, and those over
+/// software renderers.
+const DEVICE_SELECTOR_VAR: &str = "WAYLAND_THING_DEVICE";
+
+const VALIDATION_LAYER_NAME: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+/// The newest Vulkan version anything here is written against.
+const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
+
+/// Core features to enable on a device, on top of whatever the renderer enables for itself.
+#[derive(Clone, Copy, Default)]
+pub struct DeviceFeatures {
+    /// Features a device must support to be picked at all.
+    pub required: vk::PhysicalDeviceFeatures,
+    /// Features enabled where the picked device supports them.
+    pub optional: vk::PhysicalDeviceFeatures,
+}
 
 pub struct Instance {
     entry: ash::Entry,
     instance: ash::Instance,
     khr_wayland_instance: khr::wayland_surface::Instance,
+    /// The Vulkan version the instance was created for.
+    api_version: u32,
+    /// Set if `VK_EXT_surface_maintenance1` is enabled, which `VK_EXT_swapchain_maintenance1`
+    /// depends on.
+    surface_maintenance1: bool,
+    /// Set if `VK_EXT_swapchain_colorspace` is enabled, so that swapchains can use color spaces
+    /// other than sRGB.
+    swapchain_colorspace: bool,
+    /// Forwards validation messages to the log, when validation is enabled.
+    debug_messenger: Option<(ext::debug_utils::Instance, vk::DebugUtilsMessengerEXT)>,
+    /// Owned by the instance so that they outlive every object allocated with them: devices keep
+    /// the instance alive, and everything else is destroyed before its device.
+    allocation_callbacks: Option<AllocationCallbacks>,
 }
 
 impl Drop for Instance {
     fn drop(&mut self) {
         unsafe {
-            self.instance.destroy_instance(None);
+            if let Some((ext_debug_utils_instance, messenger)) = &self.debug_messenger {
+                ext_debug_utils_instance
+                    .destroy_debug_utils_messenger(*messenger, self.allocation_callbacks());
+            }
+            self.instance.destroy_instance(self.allocation_callbacks());
+        }
+        if self.allocation_callbacks.is_some() {
+            counting_allocator::log_totals();
         }
     }
 }
 
 impl Instance {
-    pub fn new() -> Result<Arc<Self>> {
+    /// Creates an instance, with the Khronos validation layer enabled if `validation` is set.
+    ///
+    /// Validation is best-effort: if the layer isn't installed, the instance is created without it.
+    pub fn new(validation: bool) -> Result<Arc<Self>> {
+        Self::create(None, validation)
+    }
+
+    /// Creates an instance whose objects are all allocated through `allocation_callbacks`, e.g. to
+    /// track host memory usage.
+    pub fn with_allocation_callbacks(
+        allocation_callbacks: AllocationCallbacks,
+        validation: bool,
+    ) -> Result<Arc<Self>> {
+        Self::create(Some(allocation_callbacks), validation)
+    }
+
+    fn create(
+        allocation_callbacks: Option<AllocationCallbacks>,
+        validation: bool,
+    ) -> Result<Arc<Self>> {
         let entry = unsafe { ash::Entry::load()? };
 
-        let extension_names = [khr::wayland_surface::NAME.as_ptr()];
+        let validation = validation && validation_supported(&entry);
+
+        // Ask for the newest version both the loader and we know about. Each device then gets
+        // used at the lower of this and its own version.
+        let loader_version =
+            unsafe { entry.try_enumerate_instance_version()? }.unwrap_or(vk::API_VERSION_1_0);
+        let api_version = loader_version.min(MAX_API_VERSION);
+        debug!(
+            "using vulkan {}.{} (loader supports {}.{})",
+            vk::api_version_major(api_version),
+            vk::api_version_minor(api_version),
+            vk::api_version_major(loader_version),
+            vk::api_version_minor(loader_version),
+        );
+
+        let mut extension_names = vec![khr::wayland_surface::NAME.as_ptr()];
+        let mut layer_names = Vec::new();
+
+        // These are only needed for optional features, which go unused without them.
+        let available_extensions =
+            unsafe { entry.enumerate_instance_extension_properties(None) }.unwrap_or_default();
+        let has_extension = |name| {
+            available_extensions
+                .iter()
+                .any(|extension| extension.extension_name_as_c_str() == Ok(name))
+        };
+        let surface_maintenance1 = has_extension(ext::surface_maintenance1::NAME)
+            && has_extension(khr::get_surface_capabilities2::NAME);
+        if surface_maintenance1 {
+            extension_names.push(khr::get_surface_capabilities2::NAME.as_ptr());
+            extension_names.push(ext::surface_maintenance1::NAME.as_ptr());
+        }
+        let swapchain_colorspace = has_extension(ext::swapchain_colorspace::NAME);
+        if swapchain_colorspace {
+            extension_names.push(ext::swapchain_colorspace::NAME.as_ptr());
+        }
+
+        if validation {
+            extension_names.push(ext::debug_utils::NAME.as_ptr());
+            layer_names.push(VALIDATION_LAYER_NAME.as_ptr());
+        }
+
+        let messenger_create_info = vk::DebugUtilsMessengerCreateInfoEXT {
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            pfn_user_callback: Some(log_debug_message),
+            ..Default::default()
+        };
 
         let instance_create_info = vk::InstanceCreateInfo {
+            // Chaining the messenger info covers instance creation and destruction themselves,
+            // which the messenger object can't.
+            p_next: if validation {
+                (&raw const messenger_create_info).cast()
+            } else {
+                ptr::null()
+            },
             p_application_info: &vk::ApplicationInfo {
-                api_version: vk::make_api_version(0, 1, 0, 0),
+                api_version,
                 ..Default::default()
             },
+            enabled_layer_count: layer_names.len() as u32,
+            pp_enabled_layer_names: layer_names.as_ptr(),
             enabled_extension_count: extension_names.len() as u32,
             pp_enabled_extension_names: extension_names.as_ptr(),
             ..Default::default()
         };
 
-        let instance = unsafe { entry.create_instance(&instance_create_info, None)? };
+        let instance = unsafe {
+            entry.create_instance(
+                &instance_create_info,
+                allocation_callbacks.as_ref().map(|callbacks| &callbacks.0),
+            )?
+        };
         let khr_wayland_instance = khr::wayland_surface::Instance::new(&entry, &instance);
 
+        let debug_messenger = validation
+            .then(|| {
+                let ext_debug_utils_instance = ext::debug_utils::Instance::new(&entry, &instance);
+                let messenger = unsafe {
+                    ext_debug_utils_instance.create_debug_utils_messenger(
+                        &messenger_create_info,
+                        allocation_callbacks.as_ref().map(|callbacks| &callbacks.0),
+                    )
+                };
+                // Without the messenger, validation still happens but its output goes nowhere
+                // useful, which is no reason to fail.
+                messenger
+                    .inspect_err(|err| warn!("failed to create debug messenger: {err}"))
+                    .ok()
+                    .map(|messenger| (ext_debug_utils_instance, messenger))
+            })
+            .flatten();
+
         Ok(Arc::new(Self {
             entry,
             instance,
             khr_wayland_instance,
+            api_version,
+            surface_maintenance1,
+            swapchain_colorspace,
+            debug_messenger,
+            allocation_callbacks,
         }))
     }
 
     pub fn create_device(
         self: &Arc<Self>,
-        mut match_dev: impl FnMut(vk::PhysicalDevice, u32, &vk::QueueFamilyProperties) -> bool,
+        features: &DeviceFeatures,
+        mut match_graphics: impl FnMut(vk::PhysicalDevice, u32, &vk::QueueFamilyProperties) -> bool,
+        mut match_present: impl FnMut(vk::PhysicalDevice, u32, &vk::QueueFamilyProperties) -> bool,
     ) -> Result<Arc<Device>> {
         let available_devices = unsafe { self.instance.enumerate_physical_devices()? };
-        let (physical_device, queue_family_index) = available_devices
+        let candidates: Vec<_> = available_devices
             .iter()
-            .find_map(|&physical_device| {
-                let queue_families = unsafe {
-                    self.instance
-                        .get_physical_device_queue_family_properties(physical_device)
+            .zip(0..)
+            .filter_map(|(&physical_device, index)| {
+                let candidate = DeviceCandidate::new(self, physical_device, index);
+                debug!(
+                    "found device {index}: {} ({:?})",
+                    candidate.name(),
+                    candidate.properties.device_type
+                );
+
+                let (graphics_idx, present_idx) = self.find_queue_families(
+                    physical_device,
+                    &mut match_graphics,
+                    &mut match_present,
+                )?;
+
+                let Some(compute_idx) =
+                    self.find_compute_queue_family(physical_device, graphics_idx)
+                else {
+                    debug!("skipping device {index}: no compute queue family");
+                    return None;
                 };
-                let (_, queue_family_index) = queue_families
-                    .iter()
-                    .zip(0..)
-                    .find(|&(properties, idx)| match_dev(physical_device, idx, properties))?;
 
-                Some((physical_device, queue_family_index))
+                // Frame pacing is built around timeline semaphores, so there's no doing without.
+                let Some(timeline_semaphores) =
+                    self.timeline_semaphore_support(physical_device, &candidate.properties)
+                else {
+                    debug!("skipping device {index}: timeline semaphores not supported");
+                    return None;
+                };
+
+                let supported_features =
+                    unsafe { self.instance.get_physical_device_features(physical_device) };
+                if !contains_features(&supported_features, &features.required) {
+                    debug!("skipping device {index}: required features not supported");
+                    return None;
+                }
+
+                Some((
+                    candidate,
+                    graphics_idx,
+                    present_idx,
+                    compute_idx,
+                    timeline_semaphores,
+                ))
             })
-            .ok_or_else(|| anyhow!("no usable vulkan devices available"))?;
+            .collect();
 
-        let device_properties = unsafe {
-            self.instance
-                .get_physical_device_properties(physical_device)
+        // Among equally preferred devices, keep the driver's order.
+        let preferred = candidates
+            .iter()
+            .min_by_key(|(candidate, ..)| device_type_rank(candidate.properties.device_type));
+        let selected = match env::var(DEVICE_SELECTOR_VAR) {
+            Ok(selector) => candidates
+                .iter()
+                .find(|(candidate, ..)| candidate.matches(&selector))
+                .or_else(|| {
+                    warn!("no usable device matches `{selector}`, using the default one");
+                    preferred
+                }),
+            Err(_) => preferred,
         };
-        let device_name = unsafe { CStr::from_ptr(device_properties.device_name.as_ptr()) };
+        let &(
+            candidate,
+            graphics_queue_family_index,
+            present_queue_family_index,
+            compute_queue_family_index,
+            timeline_semaphores,
+        ) = selected.ok_or(Error::NoVulkanDevice)?;
+        let physical_device = candidate.physical_device;
+        let device_properties = candidate.properties;
+        let api_version = device_properties.api_version.min(self.api_version);
         info!(
-            "selected device: {} ({:?})",
-            device_name.to_string_lossy(),
-            device_properties.device_type
+            "selected device: {} ({:?}, vulkan {}.{})",
+            candidate.name(),
+            device_properties.device_type,
+            vk::api_version_major(api_version),
+            vk::api_version_minor(api_version),
+        );
+
+        if graphics_queue_family_index != present_queue_family_index {
+            info!(
+                "using separate graphics ({graphics_queue_family_index}) and present \
+                ({present_queue_family_index}) queue families"
+            );
+        }
+        if compute_queue_family_index != graphics_queue_family_index {
+            info!("using dedicated compute queue family {compute_queue_family_index}");
+        }
+        let transfer_queue_family_index = self.find_transfer_queue_family(physical_device);
+        if let Some(idx) = transfer_queue_family_index {
+            info!("using dedicated transfer queue family {idx}");
+        }
+
+        let dynamic_rendering = self.dynamic_rendering_support(physical_device, &device_properties);
+        match dynamic_rendering {
+            Some(support) => debug!("dynamic rendering supported ({support:?})"),
+            None => info!("dynamic rendering not supported, using render passes"),
+        }
+
+        debug!("timeline semaphores supported ({timeline_semaphores:?})");
+
+        let present_fences = self.present_fence_support(physical_device);
+        if !present_fences {
+            debug!("present fences not supported");
+        }
+
+        let mut extension_names = vec![khr::swapchain::NAME.as_ptr()];
+        if dynamic_rendering == Some(DynamicRenderingSupport::Extension) {
+            extension_names.push(khr::dynamic_rendering::NAME.as_ptr());
+        }
+        if timeline_semaphores == TimelineSemaphoreSupport::Extension {
+            extension_names.push(khr::timeline_semaphore::NAME.as_ptr());
+        }
+        if present_fences {
+            extension_names.push(ext::swapchain_maintenance1::NAME.as_ptr());
+        }
+
+        let supported_features =
+            unsafe { self.instance.get_physical_device_features(physical_device) };
+        let enabled_features = merge_features(
+            &features.required,
+            &intersect_features(&features.optional, &supported_features),
         );
 
-        let extension_names = [khr::swapchain::NAME.as_ptr()];
+        // Timeline semaphores need 1.1 either way, so features can always go through the chain.
+        // The extension features have to be enabled even where they are core.
+        let mut features2 = vk::PhysicalDeviceFeatures2 {
+            features: enabled_features,
+            ..Default::default()
+        };
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures {
+            dynamic_rendering: vk::TRUE,
+            ..Default::default()
+        };
+        let mut timeline_semaphore_features = vk::PhysicalDeviceTimelineSemaphoreFeatures {
+            timeline_semaphore: vk::TRUE,
+            ..Default::default()
+        };
+        let mut swapchain_maintenance1_features =
+            vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT {
+                swapchain_maintenance1: vk::TRUE,
+                ..Default::default()
+            };
+
+        // The same family may not be requested twice, so queues in a shared family are shared too.
+        let mut queue_family_indices = vec![
+            graphics_queue_family_index,
+            present_queue_family_index,
+            compute_queue_family_index,
+        ];
+        queue_family_indices.extend(transfer_queue_family_index);
+        queue_family_indices.sort_unstable();
+        queue_family_indices.dedup();
 
-        let device_create_info = vk::DeviceCreateInfo {
-            queue_create_info_count: 1,
-            p_queue_create_infos: &vk::DeviceQueueCreateInfo {
+        let queue_priorities = [1f32];
+        let queue_create_infos: Vec<_> = queue_family_indices
+            .iter()
+            .map(|&queue_family_index| vk::DeviceQueueCreateInfo {
                 queue_family_index,
                 queue_count: 1,
-                p_queue_priorities: [1f32].as_ptr(),
+                p_queue_priorities: queue_priorities.as_ptr(),
                 ..Default::default()
-            },
+            })
+            .collect();
+
+        let mut device_create_info = vk::DeviceCreateInfo {
+            queue_create_info_count: queue_create_infos.len() as u32,
+            p_queue_create_infos: queue_create_infos.as_ptr(),
             enabled_extension_count: extension_names.len() as u32,
             pp_enabled_extension_names: extension_names.as_ptr(),
             ..Default::default()
-        };
+        }
+        .push_next(&mut features2)
+        .push_next(&mut timeline_semaphore_features);
+        if dynamic_rendering.is_some() {
+            device_create_info = device_create_info.push_next(&mut dynamic_rendering_features);
+        }
+        if present_fences {
+            device_create_info = device_create_info.push_next(&mut swapchain_maintenance1_features);
+        }
 
         // NOTE: Don't exit this block early, because `device` will be leaked if so.
         {
             let device = unsafe {
-                self.instance
-                    .create_device(physical_device, &device_create_info, None)?
+                self.instance.create_device(
+                    physical_device,
+                    &device_create_info,
+                    self.allocation_callbacks(),
+                )?
             };
 
             let khr_swapchain_device = khr::swapchain::Device::new(&self.instance, &device);
+            let dynamic_rendering = dynamic_rendering.map(|support| match support {
+                DynamicRenderingSupport::Core => DynamicRendering::Core,
+                DynamicRenderingSupport::Extension => DynamicRendering::Extension(
+                    khr::dynamic_rendering::Device::new(&self.instance, &device),
+                ),
+            });
+            let timeline_semaphores = match timeline_semaphores {
+                TimelineSemaphoreSupport::Core => TimelineSemaphores::Core,
+                TimelineSemaphoreSupport::Extension => TimelineSemaphores::Extension(
+                    khr::timeline_semaphore::Device::new(&self.instance, &device),
+                ),
+            };
+
+            let timestamp_valid_bits = unsafe {
+                self.instance
+                    .get_physical_device_queue_family_properties(physical_device)
+                    [graphics_queue_family_index as usize]
+                    .timestamp_valid_bits
+            };
+
+            // Timestamps only count up within their valid bits, wrapping around beyond that.
+            let timestamp_mask = match timestamp_valid_bits {
+                0 => {
+                    info!("graphics queue does not support timestamps, GPU timing disabled");
+                    None
+                }
+                64.. => Some(u64::MAX),
+                bits => Some((1 << bits) - 1),
+            };
 
-            let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+            // Roles sharing a family share its one queue, along with the lock guarding it.
+            let queues: Vec<_> = queue_family_indices
+                .iter()
+                .map(|&queue_family_index| {
+                    Arc::new(Queue {
+                        device: device.clone(),
+                        khr_swapchain_device: khr_swapchain_device.clone(),
+                        queue: unsafe { device.get_device_queue(queue_family_index, 0) },
+                        family_index: queue_family_index,
+                        submissions: Mutex::new(0),
+                    })
+                })
+                .collect();
+            let queue = |queue_family_index| {
+                let queue = queues
+                    .iter()
+                    .find(|queue| queue.family_index == queue_family_index)
+                    .unwrap();
+                Arc::clone(queue)
+            };
+            let graphics_queue = queue(graphics_queue_family_index);
+            let present_queue = queue(present_queue_family_index);
+            let compute_queue = queue(compute_queue_family_index);
+            let transfer_queue = transfer_queue_family_index.map(queue);
 
             Ok(Arc::new(Device {
                 instance: Arc::clone(self),
+                physical_device,
                 device,
                 khr_swapchain_device,
-                queue_family_index,
-                queue,
+                dynamic_rendering,
+                timeline_semaphores,
+                present_fences,
+                graphics_queue_family_index,
+                present_queue_family_index,
+                compute_queue_family_index,
+                graphics_queue,
+                present_queue,
+                compute_queue,
+                transfer_queue,
+                properties: device_properties,
+                enabled_features,
+                timestamp_period: device_properties.limits.timestamp_period,
+                timestamp_mask,
             }))
         }
     }
 
+    /// Checks how `physical_device` supports dynamic rendering, if at all.
+    fn dynamic_rendering_support(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> Option<DynamicRenderingSupport> {
+        // The device can't use anything newer than the instance.
+        let api_version = properties.api_version.min(self.api_version);
+        if api_version >= vk::API_VERSION_1_3 {
+            // Dynamic rendering is required in 1.3.
+            return Some(DynamicRenderingSupport::Core);
+        }
+
+        // Before 1.2, the extension depends on a chain of others we'd have to enable as well, which
+        // isn't worth it for drivers that old.
+        if api_version < vk::API_VERSION_1_2 {
+            return None;
+        }
+
+        if !self.has_device_extension(physical_device, khr::dynamic_rendering::NAME) {
+            return None;
+        }
+
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut features2 =
+            vk::PhysicalDeviceFeatures2::default().push_next(&mut dynamic_rendering_features);
+        unsafe {
+            self.instance
+                .get_physical_device_features2(physical_device, &mut features2);
+        }
+        (dynamic_rendering_features.dynamic_rendering == vk::TRUE)
+            .then_some(DynamicRenderingSupport::Extension)
+    }
+
+    /// Checks how `physical_device` supports timeline semaphores, if at all.
+    fn timeline_semaphore_support(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> Option<TimelineSemaphoreSupport> {
+        let api_version = properties.api_version.min(self.api_version);
+        if api_version >= vk::API_VERSION_1_2 {
+            // Timeline semaphores are required in 1.2.
+            return Some(TimelineSemaphoreSupport::Core);
+        }
+
+        // Querying the feature needs 1.1.
+        if api_version < vk::API_VERSION_1_1
+            || !self.has_device_extension(physical_device, khr::timeline_semaphore::NAME)
+        {
+            return None;
+        }
+
+        let mut timeline_semaphore_features =
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut features2 =
+            vk::PhysicalDeviceFeatures2::default().push_next(&mut timeline_semaphore_features);
+        unsafe {
+            self.instance
+                .get_physical_device_features2(physical_device, &mut features2);
+        }
+        (timeline_semaphore_features.timeline_semaphore == vk::TRUE)
+            .then_some(TimelineSemaphoreSupport::Extension)
+    }
+
+    /// Checks whether `physical_device` can signal a fence once a present is done with its
+    /// swapchain image, through `VK_EXT_swapchain_maintenance1`.
+    fn present_fence_support(&self, physical_device: vk::PhysicalDevice) -> bool {
+        if !self.surface_maintenance1
+            || !self.has_device_extension(physical_device, ext::swapchain_maintenance1::NAME)
+        {
+            return false;
+        }
+
+        // Timeline semaphores already made sure the device is at least 1.1, so this query works.
+        let mut swapchain_maintenance1_features =
+            vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT::default();
+        let mut features2 =
+            vk::PhysicalDeviceFeatures2::default().push_next(&mut swapchain_maintenance1_features);
+        unsafe {
+            self.instance
+                .get_physical_device_features2(physical_device, &mut features2);
+        }
+        swapchain_maintenance1_features.swapchain_maintenance1 == vk::TRUE
+    }
+
+    /// Finds a queue family on `physical_device` for compute work, preferring one without graphics
+    /// support so that compute can run alongside rendering, and falling back to sharing the
+    /// graphics family.
+    fn find_compute_queue_family(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        graphics_queue_family_index: u32,
+    ) -> Option<u32> {
+        let queue_families = unsafe {
+            self.instance
+                .get_physical_device_queue_family_properties(physical_device)
+        };
+        let supports_compute = |idx: u32| {
+            queue_families[idx as usize]
+                .queue_flags
+                .contains(vk::QueueFlags::COMPUTE)
+        };
+
+        (0..)
+            .zip(&queue_families)
+            .find(|(_, properties)| {
+                properties.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !properties.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|(idx, _)| idx)
+            .or_else(|| {
+                supports_compute(graphics_queue_family_index).then_some(graphics_queue_family_index)
+            })
+            .or_else(|| (0..queue_families.len() as u32).find(|&idx| supports_compute(idx)))
+    }
+
+    /// Finds a queue family on `physical_device` that only supports transfers, if it has one.
+    /// These usually map to copy engines that can run alongside rendering.
+    fn find_transfer_queue_family(&self, physical_device: vk::PhysicalDevice) -> Option<u32> {
+        let queue_families = unsafe {
+            self.instance
+                .get_physical_device_queue_family_properties(physical_device)
+        };
+
+        (0..)
+            .zip(&queue_families)
+            .find(|(_, properties)| {
+                properties.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !properties
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|(idx, _)| idx)
+    }
+
+    fn has_device_extension(&self, physical_device: vk::PhysicalDevice, name: &CStr) -> bool {
+        unsafe {
+            self.instance
+                .enumerate_device_extension_properties(physical_device)
+        }
+        .unwrap_or_default()
+        .iter()
+        .any(|extension| extension.extension_name_as_c_str() == Ok(name))
+    }
+
+    /// Finds queue families on `physical_device` for rendering and presenting, preferring a single
+    /// family that can do both, so that the swapchain images don't need to be shared between
+    /// families.
+    fn find_queue_families(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        match_graphics: &mut impl FnMut(vk::PhysicalDevice, u32, &vk::QueueFamilyProperties) -> bool,
+        match_present: &mut impl FnMut(vk::PhysicalDevice, u32, &vk::QueueFamilyProperties) -> bool,
+    ) -> Option<(u32, u32)> {
+        let queue_families = unsafe {
+            self.instance
+                .get_physical_device_queue_family_properties(physical_device)
+        };
+
+        if let Some((_, idx)) = queue_families.iter().zip(0..).find(|&(properties, idx)| {
+            match_graphics(physical_device, idx, properties)
+                && match_present(physical_device, idx, properties)
+        }) {
+            return Some((idx, idx));
+        }
+
+        let (_, graphics_idx) = queue_families
+            .iter()
+            .zip(0..)
+            .find(|&(properties, idx)| match_graphics(physical_device, idx, properties))?;
+        let (_, present_idx) = queue_families
+            .iter()
+            .zip(0..)
+            .find(|&(properties, idx)| match_present(physical_device, idx, properties))?;
+
+        Some((graphics_idx, present_idx))
+    }
+
     pub fn entry(&self) -> &ash::Entry {
         &self.entry
     }
@@ -123,14 +687,271 @@ impl Instance {
     pub fn khr_wayland_instance(&self) -> &khr::wayland_surface::Instance {
         &self.khr_wayland_instance
     }
+
+    /// Returns whether swapchains can be created in the extended color spaces, such as Display P3
+    /// and HDR10, where surfaces support them.
+    pub fn supports_swapchain_colorspace(&self) -> bool {
+        self.swapchain_colorspace
+    }
+
+    /// Returns the callbacks to pass when creating or destroying any object belonging to this
+    /// instance.
+    pub fn allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks<'static>> {
+        self.allocation_callbacks
+            .as_ref()
+            .map(|callbacks| &callbacks.0)
+    }
+}
+
+/// A physical device that could be picked to render with.
+#[derive(Clone, Copy)]
+struct DeviceCandidate {
+    physical_device: vk::PhysicalDevice,
+    /// The position among all devices the driver enumerated, for picking a device by index.
+    index: u32,
+    properties: vk::PhysicalDeviceProperties,
+    /// The device UUID, if Vulkan 1.1 is available to query it.
+    uuid: Option<[u8; vk::UUID_SIZE]>,
+}
+
+impl DeviceCandidate {
+    fn new(instance: &Instance, physical_device: vk::PhysicalDevice, index: u32) -> Self {
+        let properties = unsafe {
+            instance
+                .instance
+                .get_physical_device_properties(physical_device)
+        };
+
+        let uuid = (instance.api_version >= vk::API_VERSION_1_1
+            && properties.api_version >= vk::API_VERSION_1_1)
+            .then(|| {
+                let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+                let mut properties2 =
+                    vk::PhysicalDeviceProperties2::default().push_next(&mut id_properties);
+                unsafe {
+                    instance
+                        .instance
+                        .get_physical_device_properties2(physical_device, &mut properties2);
+                }
+                id_properties.device_uuid
+            });
+
+        Self {
+            physical_device,
+            index,
+            properties,
+            uuid,
+        }
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        unsafe { CStr::from_ptr(self.properties.device_name.as_ptr()) }.to_string_lossy()
+    }
+
+    /// Checks whether `selector` picks this device, by index, UUID (with or without dashes) or a
+    /// case-insensitive part of the name.
+    fn matches(&self, selector: &str) -> bool {
+        if let Ok(index) = selector.parse::<u32>() {
+            return index == self.index;
+        }
+
+        if let Some(uuid) = self.uuid {
+            let hex: String = uuid.iter().map(|byte| format!("{byte:02x}")).collect();
+            if selector.replace('-', "").eq_ignore_ascii_case(&hex) {
+                return true;
+            }
+        }
+
+        self.name()
+            .to_lowercase()
+            .contains(&selector.to_lowercase())
+    }
+}
+
+/// Views `features` as the array of flags it really is.
+fn feature_flags(features: &vk::PhysicalDeviceFeatures) -> &[vk::Bool32] {
+    // SAFETY: `VkPhysicalDeviceFeatures` consists of nothing but `VkBool32` members.
+    unsafe {
+        std::slice::from_raw_parts(
+            ptr::from_ref(features).cast(),
+            mem::size_of::<vk::PhysicalDeviceFeatures>() / mem::size_of::<vk::Bool32>(),
+        )
+    }
+}
+
+/// Combines two sets of features flag by flag with `op`.
+fn combine_features(
+    a: &vk::PhysicalDeviceFeatures,
+    b: &vk::PhysicalDeviceFeatures,
+    op: impl Fn(bool, bool) -> bool,
+) -> vk::PhysicalDeviceFeatures {
+    let mut combined = vk::PhysicalDeviceFeatures::default();
+    // SAFETY: As in `feature_flags`.
+    let combined_flags = unsafe {
+        std::slice::from_raw_parts_mut(
+            ptr::from_mut(&mut combined).cast::<vk::Bool32>(),
+            mem::size_of::<vk::PhysicalDeviceFeatures>() / mem::size_of::<vk::Bool32>(),
+        )
+    };
+    for ((combined, &a), &b) in combined_flags
+        .iter_mut()
+        .zip(feature_flags(a))
+        .zip(feature_flags(b))
+    {
+        *combined = op(a == vk::TRUE, b == vk::TRUE).into();
+    }
+    combined
+}
+
+fn merge_features(
+    a: &vk::PhysicalDeviceFeatures,
+    b: &vk::PhysicalDeviceFeatures,
+) -> vk::PhysicalDeviceFeatures {
+    combine_features(a, b, |a, b| a || b)
+}
+
+fn intersect_features(
+    a: &vk::PhysicalDeviceFeatures,
+    b: &vk::PhysicalDeviceFeatures,
+) -> vk::PhysicalDeviceFeatures {
+    combine_features(a, b, |a, b| a && b)
+}
+
+/// Checks whether every feature in `required` is also in `supported`.
+fn contains_features(
+    supported: &vk::PhysicalDeviceFeatures,
+    required: &vk::PhysicalDeviceFeatures,
+) -> bool {
+    feature_flags(supported)
+        .iter()
+        .zip(feature_flags(required))
+        .all(|(&supported, &required)| supported == vk::TRUE || required == vk::FALSE)
+}
+
+/// Ranks device types by how well they are expected to perform, lowest first.
+fn device_type_rank(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 4,
+        _ => 3,
+    }
+}
+
+/// Checks that both the validation layer and the debug utils extension are available, so that
+/// enabling them can't fail instance creation.
+fn validation_supported(entry: &ash::Entry) -> bool {
+    let layer_supported = unsafe { entry.enumerate_instance_layer_properties() }
+        .unwrap_or_default()
+        .iter()
+        .any(|layer| layer.layer_name_as_c_str() == Ok(VALIDATION_LAYER_NAME));
+    if !layer_supported {
+        warn!("validation requested, but `{VALIDATION_LAYER_NAME:?}` is not installed");
+        return false;
+    }
+
+    // The extension may come from the layer itself rather than the loader.
+    let extension_supported = [None, Some(VALIDATION_LAYER_NAME)]
+        .into_iter()
+        .flat_map(|layer_name| {
+            unsafe { entry.enumerate_instance_extension_properties(layer_name) }.unwrap_or_default()
+        })
+        .any(|extension| extension.extension_name_as_c_str() == Ok(ext::debug_utils::NAME));
+    if !extension_supported {
+        warn!(
+            "validation requested, but `{:?}` is not available",
+            ext::debug_utils::NAME
+        );
+        return false;
+    }
+
+    info!("enabling vulkan validation");
+    true
+}
+
+/// Forwards messages from the validation layer (and the driver) to the log, at the matching level.
+unsafe extern "system" fn log_debug_message(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    let level = if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        Level::Warn
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        Level::Debug
+    } else {
+        Level::Trace
+    };
+
+    let message = unsafe {
+        callback_data
+            .as_ref()
+            .and_then(|data| data.message_as_c_str())
+    };
+    if let Some(message) = message {
+        log!(target: "vulkan", level, "{types:?}: {}", message.to_string_lossy());
+    }
+
+    // Returning true would make the call that triggered the message fail, which is only meant for
+    // testing the layers themselves.
+    vk::FALSE
+}
+
+/// How a device offers dynamic rendering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DynamicRenderingSupport {
+    Core,
+    Extension,
+}
+
+/// The entry points for dynamic rendering, which depend on whether it is core or an extension.
+enum DynamicRendering {
+    Core,
+    Extension(khr::dynamic_rendering::Device),
+}
+
+/// How a device offers timeline semaphores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TimelineSemaphoreSupport {
+    Core,
+    Extension,
+}
+
+/// The entry points for timeline semaphores, which depend on whether they are core or an
+/// extension.
+enum TimelineSemaphores {
+    Core,
+    Extension(khr::timeline_semaphore::Device),
 }
 
 pub struct Device {
     device: ash::Device,
     khr_swapchain_device: khr::swapchain::Device,
+    /// Set if dynamic rendering is available and enabled.
+    dynamic_rendering: Option<DynamicRendering>,
+    timeline_semaphores: TimelineSemaphores,
+    /// Set if `VK_EXT_swapchain_maintenance1` is enabled, so that presents can signal fences.
+    present_fences: bool,
     instance: Arc<Instance>,
-    queue_family_index: u32,
-    queue: vk::Queue,
+    physical_device: vk::PhysicalDevice,
+    graphics_queue_family_index: u32,
+    present_queue_family_index: u32,
+    compute_queue_family_index: u32,
+    graphics_queue: Arc<Queue>,
+    present_queue: Arc<Queue>,
+    compute_queue: Arc<Queue>,
+    /// Set if the device has a transfer-only queue family.
+    transfer_queue: Option<Arc<Queue>>,
+    properties: vk::PhysicalDeviceProperties,
+    enabled_features: vk::PhysicalDeviceFeatures,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// The valid bits of timestamps on the graphics queue, or `None` if it doesn't support them.
+    timestamp_mask: Option<u64>,
 }
 
 impl Device {
@@ -138,28 +959,1563 @@ impl Device {
         &self.instance
     }
 
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    pub fn properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.properties
+    }
+
+    /// Returns the core features enabled on the device: the required ones passed to
+    /// [`Instance::create_device`], along with the optional ones the device supports.
+    pub fn enabled_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.enabled_features
+    }
+
     pub fn device(&self) -> &ash::Device {
         &self.device
     }
 
-    pub fn queue_family_index(&self) -> u32 {
-        self.queue_family_index
+    pub fn graphics_queue_family_index(&self) -> u32 {
+        self.graphics_queue_family_index
+    }
+
+    pub fn present_queue_family_index(&self) -> u32 {
+        self.present_queue_family_index
     }
 
-    pub fn queue(&self) -> vk::Queue {
-        self.queue
+    /// Returns the family of the compute queue, which may be the graphics queue's.
+    pub fn compute_queue_family_index(&self) -> u32 {
+        self.compute_queue_family_index
+    }
+
+    pub fn graphics_queue(&self) -> &Arc<Queue> {
+        &self.graphics_queue
+    }
+
+    /// Returns the queue to present on, which may be the same as the graphics queue.
+    pub fn present_queue(&self) -> &Arc<Queue> {
+        &self.present_queue
+    }
+
+    /// Returns the queue to run compute work on, which may be the same as the graphics queue.
+    pub fn compute_queue(&self) -> &Arc<Queue> {
+        &self.compute_queue
+    }
+
+    /// Returns a queue that only does transfers, if the device has one.
+    pub fn transfer_queue(&self) -> Option<&Arc<Queue>> {
+        self.transfer_queue.as_ref()
     }
 
     pub fn khr_swapchain_device(&self) -> &khr::swapchain::Device {
         &self.khr_swapchain_device
     }
+
+    /// Returns whether rendering can be done without render pass and framebuffer objects, using
+    /// [`Self::cmd_begin_rendering`] and [`Self::cmd_end_rendering`].
+    pub fn supports_dynamic_rendering(&self) -> bool {
+        self.dynamic_rendering.is_some()
+    }
+
+    /// Returns whether [`Queue::present`] can signal a fence once the present is done with its
+    /// swapchain image.
+    pub fn supports_present_fences(&self) -> bool {
+        self.present_fences
+    }
+
+    /// Begins dynamic rendering, through the core or extension entry point as available.
+    ///
+    /// # Safety
+    ///
+    /// Dynamic rendering must be supported, and the usual rules for `vkCmdBeginRendering` apply.
+    pub unsafe fn cmd_begin_rendering(
+        &self,
+        cmd: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo<'_>,
+    ) {
+        match self
+            .dynamic_rendering
+            .as_ref()
+            .expect("dynamic rendering not supported")
+        {
+            DynamicRendering::Core => unsafe {
+                self.device.cmd_begin_rendering(cmd, rendering_info)
+            },
+            DynamicRendering::Extension(ext) => unsafe {
+                ext.cmd_begin_rendering(cmd, rendering_info)
+            },
+        }
+    }
+
+    /// Ends dynamic rendering begun with [`Self::cmd_begin_rendering`].
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be inside dynamic rendering.
+    pub unsafe fn cmd_end_rendering(&self, cmd: vk::CommandBuffer) {
+        match self
+            .dynamic_rendering
+            .as_ref()
+            .expect("dynamic rendering not supported")
+        {
+            DynamicRendering::Core => unsafe { self.device.cmd_end_rendering(cmd) },
+            DynamicRendering::Extension(ext) => unsafe { ext.cmd_end_rendering(cmd) },
+        }
+    }
+
+    /// Creates a timeline semaphore whose counter starts at `initial_value`.
+    pub fn create_timeline_semaphore(&self, initial_value: u64) -> Result<vk::Semaphore> {
+        let type_create_info = vk::SemaphoreTypeCreateInfo {
+            semaphore_type: vk::SemaphoreType::TIMELINE,
+            initial_value,
+            ..Default::default()
+        };
+        let semaphore = unsafe {
+            self.device.create_semaphore(
+                &vk::SemaphoreCreateInfo {
+                    p_next: (&raw const type_create_info).cast(),
+                    ..Default::default()
+                },
+                self.allocation_callbacks(),
+            )?
+        };
+        Ok(semaphore)
+    }
+
+    /// Returns the current counter value of timeline semaphore `semaphore`.
+    pub fn timeline_semaphore_value(&self, semaphore: vk::Semaphore) -> Result<u64> {
+        let value = unsafe {
+            match &self.timeline_semaphores {
+                TimelineSemaphores::Core => self.device.get_semaphore_counter_value(semaphore)?,
+                TimelineSemaphores::Extension(ext) => ext.get_semaphore_counter_value(semaphore)?,
+            }
+        };
+        Ok(value)
+    }
+
+    /// Waits for the counter of timeline semaphore `semaphore` to reach `value`, for at most
+    /// `timeout` if given.
+    ///
+    /// Returns whether the value was reached, i.e. `false` if the wait timed out.
+    pub fn wait_timeline_semaphore(
+        &self,
+        semaphore: vk::Semaphore,
+        value: u64,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let wait_info = vk::SemaphoreWaitInfo {
+            semaphore_count: 1,
+            p_semaphores: &semaphore,
+            p_values: &value,
+            ..Default::default()
+        };
+        let timeout = timeout.map_or(u64::MAX, |timeout| {
+            timeout.as_nanos().try_into().unwrap_or(u64::MAX)
+        });
+
+        let result = unsafe {
+            match &self.timeline_semaphores {
+                TimelineSemaphores::Core => self.device.wait_semaphores(&wait_info, timeout),
+                TimelineSemaphores::Extension(ext) => ext.wait_semaphores(&wait_info, timeout),
+            }
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Converts the difference between two timestamps taken on the graphics queue to a duration.
+    fn timestamp_duration(&self, start: u64, end: u64, mask: u64) -> Duration {
+        let ticks = end.wrapping_sub(start) & mask;
+        Duration::from_nanos((ticks as f64 * self.timestamp_period as f64) as u64)
+    }
+
+    /// Returns the instance's allocation callbacks, for creating and destroying objects.
+    pub fn allocation_callbacks(&self) -> Option<&vk::AllocationCallbacks<'static>> {
+        self.instance.allocation_callbacks()
+    }
 }
 
 impl Drop for Device {
     fn drop(&mut self) {
+        // Roles sharing a family share its queue, which should only be counted once.
+        let mut queues = vec![
+            &self.graphics_queue,
+            &self.present_queue,
+            &self.compute_queue,
+        ];
+        queues.extend(&self.transfer_queue);
+        queues.sort_unstable_by_key(|queue| queue.family_index);
+        queues.dedup_by_key(|queue| queue.family_index);
+        for queue in queues {
+            debug!(
+                "queue family {} saw {} submissions",
+                queue.family_index,
+                queue.submissions()
+            );
+        }
+
         unsafe {
             let _ = self.device.device_wait_idle();
-            self.device.destroy_device(None);
+            self.device
+                .destroy_device(self.instance.allocation_callbacks());
         }
     }
 }
+
+/// What became of a present, as far as the swapchain is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentStatus {
+    /// The image was presented, and the swapchain still matches the surface.
+    Optimal,
+    /// The image was presented, but the swapchain no longer matches the surface exactly and
+    /// should be rebuilt.
+    Suboptimal,
+    /// Nothing was presented, as the swapchain no longer matches the surface and has to be
+    /// rebuilt.
+    OutOfDate,
+}
+
+/// A device queue, which all submissions and presents go through.
+///
+/// Vulkan requires access to a queue to be externally synchronized, which is done by locking
+/// around every call. Roles sharing a queue family (e.g. graphics and present) get the same
+/// `Queue`, so that the lock covers them all.
+///
+/// Losing the device comes out as [`Error::DeviceLost`], and presenting to an outdated swapchain
+/// as [`PresentStatus::OutOfDate`] rather than an error.
+pub struct Queue {
+    device: ash::Device,
+    khr_swapchain_device: khr::swapchain::Device,
+    queue: vk::Queue,
+    family_index: u32,
+    /// The number of submissions so far, serving as the lock too.
+    submissions: Mutex<u64>,
+}
+
+impl Queue {
+    pub fn family_index(&self) -> u32 {
+        self.family_index
+    }
+
+    /// Returns how many submissions were made to the queue so far.
+    pub fn submissions(&self) -> u64 {
+        *self.submissions.lock().unwrap()
+    }
+
+    /// Submits `submits` to the queue, signaling `fence` (which may be null) once they are all
+    /// done, and returns the serial of the submission, counting from 1.
+    ///
+    /// # Safety
+    ///
+    /// The usual rules for `vkQueueSubmit` apply.
+    pub unsafe fn submit(&self, submits: &[vk::SubmitInfo<'_>], fence: vk::Fence) -> Result<u64> {
+        let mut submissions = self.submissions.lock().unwrap();
+        unsafe { self.device.queue_submit(self.queue, submits, fence)? };
+        *submissions += 1;
+        Ok(*submissions)
+    }
+
+    /// Presents image `image_index` of `swapchain` once `wait_semaphores` are signaled.
+    ///
+    /// Unless it is null, `fence` is signaled once the present is done with the image and the
+    /// wait semaphores, which requires [`Device::supports_present_fences`].
+    pub fn present(
+        &self,
+        wait_semaphores: &[vk::Semaphore],
+        swapchain: vk::SwapchainKHR,
+        image_index: u32,
+        fence: vk::Fence,
+    ) -> Result<PresentStatus> {
+        let mut fence_info = vk::SwapchainPresentFenceInfoEXT {
+            swapchain_count: 1,
+            p_fences: &fence,
+            ..Default::default()
+        };
+        let mut present_info = vk::PresentInfoKHR {
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            swapchain_count: 1,
+            p_swapchains: &swapchain,
+            p_image_indices: &image_index,
+            ..Default::default()
+        };
+        if fence != vk::Fence::null() {
+            present_info = present_info.push_next(&mut fence_info);
+        }
+
+        let _guard = self.submissions.lock().unwrap();
+        let result = unsafe {
+            self.khr_swapchain_device
+                .queue_present(self.queue, &present_info)
+        };
+
+        match result {
+            Ok(false) => Ok(PresentStatus::Optimal),
+            Ok(true) => Ok(PresentStatus::Suboptimal),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(PresentStatus::OutOfDate),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Waits for everything submitted to the queue to finish.
+    pub fn wait_idle(&self) -> Result<()> {
+        let _guard = self.submissions.lock().unwrap();
+        unsafe { self.device.queue_wait_idle(self.queue)? };
+        Ok(())
+    }
+}
+
+/// A command pool with a fixed set of primary command buffers, which are reset and re-recorded
+/// for every use, e.g. one per frame in flight.
+pub struct CommandRecorder {
+    device: Arc<Device>,
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl CommandRecorder {
+    /// Creates a recorder with `count` command buffers for submission to queues of family
+    /// `queue_family_index`.
+    pub fn new(device: &Arc<Device>, queue_family_index: u32, count: u32) -> Result<Self> {
+        let allocation_callbacks = device.allocation_callbacks();
+
+        let command_pool = unsafe {
+            device.device().create_command_pool(
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                    queue_family_index,
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?
+        };
+
+        let command_buffers = unsafe {
+            device
+                .device()
+                .allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                    command_pool,
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_buffer_count: count,
+                    ..Default::default()
+                })
+        };
+        let command_buffers = match command_buffers {
+            Ok(command_buffers) => command_buffers,
+            Err(err) => {
+                unsafe {
+                    device
+                        .device()
+                        .destroy_command_pool(command_pool, allocation_callbacks)
+                };
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            device: Arc::clone(device),
+            command_pool,
+            command_buffers,
+        })
+    }
+
+    /// Returns command buffer `index`, for submitting it once recorded.
+    pub fn command_buffer(&self, index: usize) -> vk::CommandBuffer {
+        self.command_buffers[index]
+    }
+
+    /// Resets command buffer `index` and records it for a single submission, with `record` filling
+    /// in the commands.
+    ///
+    /// If recording fails, the command buffer is left unusable until it is recorded again.
+    ///
+    /// # Safety
+    ///
+    /// The command buffer must not be pending execution, i.e. any submission of it must have
+    /// finished.
+    pub unsafe fn record<T>(
+        &self,
+        index: usize,
+        record: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+    ) -> Result<T> {
+        let device = self.device.device();
+        let cmd = self.command_buffers[index];
+
+        unsafe {
+            device.reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty())?;
+            device.begin_command_buffer(
+                cmd,
+                &vk::CommandBufferBeginInfo {
+                    flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        let ret = record(cmd)?;
+
+        unsafe { device.end_command_buffer(cmd)? };
+        Ok(ret)
+    }
+}
+
+impl Drop for CommandRecorder {
+    fn drop(&mut self) {
+        // Freeing the pool frees its command buffers along with it. The owner is responsible for
+        // waiting for them to finish first.
+        unsafe {
+            self.device
+                .device()
+                .destroy_command_pool(self.command_pool, self.device.allocation_callbacks());
+        }
+    }
+}
+
+/// The semaphores of one frame in flight, which can only be reused once the frame's submission has
+/// finished.
+pub struct FrameContext {
+    /// Signaled once the swapchain image the frame renders into has been acquired.
+    acquire_image_sem: vk::Semaphore,
+    /// Signaled once rendering is done, for presenting to wait on.
+    render_sem: vk::Semaphore,
+    /// The number of the last frame submitted with this context, counting from 1, or 0 if none
+    /// was yet.
+    last_frame: u64,
+}
+
+impl FrameContext {
+    fn new(device: &Device) -> Result<Self> {
+        let allocation_callbacks = device.allocation_callbacks();
+
+        // Start with null handles so that `Drop` can clean up after a partial failure.
+        let mut frame = Self {
+            acquire_image_sem: vk::Semaphore::null(),
+            render_sem: vk::Semaphore::null(),
+            last_frame: 0,
+        };
+
+        let result = (|| unsafe {
+            frame.acquire_image_sem = device
+                .device
+                .create_semaphore(&Default::default(), allocation_callbacks)?;
+            frame.render_sem = device
+                .device
+                .create_semaphore(&Default::default(), allocation_callbacks)?;
+            Ok::<_, Error>(())
+        })();
+        if let Err(err) = result {
+            frame.destroy(device);
+            return Err(err);
+        }
+
+        Ok(frame)
+    }
+
+    pub fn acquire_image_sem(&self) -> vk::Semaphore {
+        self.acquire_image_sem
+    }
+
+    pub fn render_sem(&self) -> vk::Semaphore {
+        self.render_sem
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        let allocation_callbacks = device.allocation_callbacks();
+        unsafe {
+            device
+                .device
+                .destroy_semaphore(self.render_sem, allocation_callbacks);
+            device
+                .device
+                .destroy_semaphore(self.acquire_image_sem, allocation_callbacks);
+        }
+    }
+}
+
+/// Weight of each new sample in the smoothed GPU frame time. Lower values are steadier but slower
+/// to follow real changes.
+const GPU_TIME_SMOOTHING: f64 = 0.1;
+
+/// Measures how long the GPU spends on frames, with a timestamp query at the start and end of
+/// each.
+///
+/// The queries come in a pair of slots per frame in flight, each of which can be read back once
+/// its frame is done. Besides the time of each frame, the timer keeps an exponentially smoothed
+/// average, which is steadier for displaying.
+pub struct GpuTimer {
+    device: Arc<Device>,
+    query_pool: vk::QueryPool,
+    /// The valid bits of timestamps on the graphics queue.
+    mask: u64,
+    /// The number of the frame last read back from each slot, so that no frame is counted twice.
+    last_read: Vec<u64>,
+    smoothed: Option<Duration>,
+}
+
+impl GpuTimer {
+    /// Creates a timer with `slots` slots, or returns `None` if the graphics queue doesn't support
+    /// timestamps.
+    ///
+    /// GPU timings are only informational, so failing to set them up only logs a warning.
+    pub fn new(device: &Arc<Device>, slots: u32) -> Option<Self> {
+        let mask = device.timestamp_mask?;
+        let create_info = vk::QueryPoolCreateInfo {
+            query_type: vk::QueryType::TIMESTAMP,
+            query_count: 2 * slots,
+            ..Default::default()
+        };
+        let query_pool = unsafe {
+            device
+                .device
+                .create_query_pool(&create_info, device.allocation_callbacks())
+        }
+        .inspect_err(|err| warn!("failed to create timestamp query pool: {err}"))
+        .ok()?;
+
+        Some(Self {
+            device: Arc::clone(device),
+            query_pool,
+            mask,
+            last_read: vec![0; slots as usize],
+            smoothed: None,
+        })
+    }
+
+    /// Records the timestamp marking the start of a frame into `cmd`, in `slot`.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be a graphics command buffer in the recording state, outside of any render pass,
+    /// and the slot's last frame must have finished.
+    pub unsafe fn cmd_begin(&self, cmd: vk::CommandBuffer, slot: usize) {
+        let first_query = 2 * slot as u32;
+        unsafe {
+            // Queries have to be reset before every reuse.
+            self.device
+                .device
+                .cmd_reset_query_pool(cmd, self.query_pool, first_query, 2);
+            self.device.device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                first_query,
+            );
+        }
+    }
+
+    /// Records the timestamp marking the end of a frame into `cmd`, in `slot`.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be in the recording state, and have begun the frame in the same slot with
+    /// [`Self::cmd_begin`].
+    pub unsafe fn cmd_end(&self, cmd: vk::CommandBuffer, slot: usize) {
+        unsafe {
+            self.device.device.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                2 * slot as u32 + 1,
+            );
+        }
+    }
+
+    /// Reads back how long the GPU spent on frame `frame` (counting from 1), which was timed in
+    /// `slot`, and folds it into the smoothed time.
+    ///
+    /// Returns `None` if the frame was already read back or its results aren't available yet.
+    pub fn read(&mut self, slot: usize, frame: u64) -> Result<Option<Duration>> {
+        if frame == 0 || self.last_read[slot] == frame {
+            return Ok(None);
+        }
+
+        let mut timestamps = [0u64; 2];
+        match unsafe {
+            self.device.device.get_query_pool_results(
+                self.query_pool,
+                2 * slot as u32,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        } {
+            Ok(()) => {}
+            Err(vk::Result::NOT_READY) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        self.last_read[slot] = frame;
+
+        let [start, end] = timestamps;
+        let time = self.device.timestamp_duration(start, end, self.mask);
+        self.smoothed = Some(match self.smoothed {
+            Some(smoothed) => Duration::from_secs_f64(
+                smoothed.as_secs_f64()
+                    + (time.as_secs_f64() - smoothed.as_secs_f64()) * GPU_TIME_SMOOTHING,
+            ),
+            None => time,
+        });
+        Ok(Some(time))
+    }
+
+    /// Returns the smoothed GPU time per frame, once any frame has been read back.
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.smoothed
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        // The owner is responsible for waiting for the timed frames to finish first.
+        unsafe {
+            self.device
+                .device
+                .destroy_query_pool(self.query_pool, self.device.allocation_callbacks());
+        }
+    }
+}
+
+/// A ring of [`FrameContext`]s with a command buffer each, letting the CPU get ahead of the GPU
+/// by up to as many frames as there are contexts.
+///
+/// A frame is rendered with the current context, which must first be waited for with
+/// [`Self::wait_current`]. Submitting it moves on to the next context.
+///
+/// Every submission signals a timeline semaphore with the number of the frame, so that the
+/// semaphore's value is always the number of frames finished so far.
+pub struct FrameRing {
+    device: Arc<Device>,
+    frames: Vec<FrameContext>,
+    commands: CommandRecorder,
+    /// Times each frame, with a slot per context.
+    timer: Option<GpuTimer>,
+    timeline: vk::Semaphore,
+    /// Timeline semaphore values the next submission waits for, with the stages that wait.
+    timeline_waits: Vec<(vk::Semaphore, u64, vk::PipelineStageFlags)>,
+    current: usize,
+    submitted_frames: u64,
+}
+
+impl FrameRing {
+    /// Creates a ring of `depth` frame contexts, for submission to the graphics queue.
+    pub fn new(device: &Arc<Device>, depth: u32) -> Result<Self> {
+        let commands = CommandRecorder::new(device, device.graphics_queue_family_index(), depth)?;
+
+        let mut ring = Self {
+            device: Arc::clone(device),
+            frames: Vec::with_capacity(depth as usize),
+            commands,
+            timer: GpuTimer::new(device, depth),
+            timeline: device.create_timeline_semaphore(0)?,
+            timeline_waits: Vec::new(),
+            current: 0,
+            submitted_frames: 0,
+        };
+        for _ in 0..depth {
+            ring.frames.push(FrameContext::new(device)?);
+        }
+
+        Ok(ring)
+    }
+
+    /// Returns the context the next frame is rendered with.
+    pub fn current(&self) -> &FrameContext {
+        &self.frames[self.current]
+    }
+
+    /// Returns the context the last frame was submitted with.
+    pub fn previous(&self) -> &FrameContext {
+        &self.frames[(self.current + self.frames.len() - 1) % self.frames.len()]
+    }
+
+    /// Returns the timeline semaphore that each submission signals with its frame number, for
+    /// other queues to wait on.
+    pub fn timeline(&self) -> vk::Semaphore {
+        self.timeline
+    }
+
+    /// Returns how many frames have been submitted so far.
+    pub fn submitted_frames(&self) -> u64 {
+        self.submitted_frames
+    }
+
+    /// Returns how many of the submitted frames are known to have finished, without waiting.
+    pub fn finished_frames(&self) -> Result<u64> {
+        self.device.timeline_semaphore_value(self.timeline)
+    }
+
+    /// Waits for the last frame submitted with the current context to finish, so that it can be
+    /// reused, and returns how long the GPU spent on it if known.
+    pub fn wait_current(&mut self) -> Result<Option<Duration>> {
+        let last_frame = self.current().last_frame;
+        self.device
+            .wait_timeline_semaphore(self.timeline, last_frame, None)?;
+        match &mut self.timer {
+            Some(timer) => timer.read(self.current, last_frame),
+            None => Ok(None),
+        }
+    }
+
+    /// Waits for every frame submitted so far to finish, leaving other work on the device alone.
+    pub fn wait_idle(&self) -> Result<()> {
+        self.device
+            .wait_timeline_semaphore(self.timeline, self.submitted_frames, None)?;
+        Ok(())
+    }
+
+    /// Returns the smoothed GPU time per frame, if GPU timing is available.
+    pub fn smoothed_gpu_time(&self) -> Option<Duration> {
+        self.timer.as_ref()?.smoothed()
+    }
+
+    /// Makes the next submission wait at `stage` until timeline semaphore `semaphore` reaches
+    /// `value`, e.g. for compute work the frame uses to finish.
+    pub fn wait_timeline(
+        &mut self,
+        semaphore: vk::Semaphore,
+        value: u64,
+        stage: vk::PipelineStageFlags,
+    ) {
+        self.timeline_waits.push((semaphore, value, stage));
+    }
+
+    /// Records the current context's command buffer, timing everything `record` adds.
+    ///
+    /// # Safety
+    ///
+    /// The current context must have been waited for with [`Self::wait_current`] since it was
+    /// last submitted.
+    pub unsafe fn record<T>(
+        &self,
+        record: impl FnOnce(vk::CommandBuffer) -> Result<T>,
+    ) -> Result<T> {
+        let timer = self.timer.as_ref();
+        unsafe {
+            self.commands.record(self.current, |cmd| {
+                if let Some(timer) = timer {
+                    timer.cmd_begin(cmd, self.current);
+                }
+                let ret = record(cmd)?;
+                if let Some(timer) = timer {
+                    timer.cmd_end(cmd, self.current);
+                }
+                Ok(ret)
+            })
+        }
+    }
+
+    /// Submits the current context's command buffer to the graphics queue and moves on to the
+    /// next context.
+    ///
+    /// The submission waits for the context's acquire semaphore at `wait_stage`, along with
+    /// anything passed to [`Self::wait_timeline`] since the last submission, and signals its
+    /// render semaphore and the timeline once done.
+    ///
+    /// # Safety
+    ///
+    /// The command buffer must have been recorded with [`Self::record`], and the acquire semaphore
+    /// have a signal operation pending.
+    pub unsafe fn submit(&mut self, wait_stage: vk::PipelineStageFlags) -> Result<()> {
+        let frame = &self.frames[self.current];
+        let frame_number = self.submitted_frames + 1;
+
+        // Values are ignored for the binary semaphores, but there has to be one for each.
+        let (wait_semaphores, (wait_values, wait_stages)): (Vec<_>, (Vec<_>, Vec<_>)) =
+            iter::once((frame.acquire_image_sem, 0, wait_stage))
+                .chain(self.timeline_waits.drain(..))
+                .map(|(semaphore, value, stage)| (semaphore, (value, stage)))
+                .unzip();
+        let signal_values = [0, frame_number];
+        let timeline_submit_info = vk::TimelineSemaphoreSubmitInfo {
+            wait_semaphore_value_count: wait_values.len() as u32,
+            p_wait_semaphore_values: wait_values.as_ptr(),
+            signal_semaphore_value_count: signal_values.len() as u32,
+            p_signal_semaphore_values: signal_values.as_ptr(),
+            ..Default::default()
+        };
+        let signal_semaphores = [frame.render_sem, self.timeline];
+
+        unsafe {
+            self.device.graphics_queue().submit(
+                &[vk::SubmitInfo {
+                    p_next: (&raw const timeline_submit_info).cast(),
+                    wait_semaphore_count: wait_semaphores.len() as u32,
+                    p_wait_semaphores: wait_semaphores.as_ptr(),
+                    p_wait_dst_stage_mask: wait_stages.as_ptr(),
+                    command_buffer_count: 1,
+                    p_command_buffers: [self.commands.command_buffer(self.current)].as_ptr(),
+                    signal_semaphore_count: signal_semaphores.len() as u32,
+                    p_signal_semaphores: signal_semaphores.as_ptr(),
+                    ..Default::default()
+                }],
+                vk::Fence::null(),
+            )?;
+        }
+
+        self.submitted_frames = frame_number;
+        self.frames[self.current].last_frame = frame_number;
+        self.current = (self.current + 1) % self.frames.len();
+
+        Ok(())
+    }
+}
+
+impl Drop for FrameRing {
+    fn drop(&mut self) {
+        // The owner is responsible for waiting for the frames to finish first.
+        for frame in &mut self.frames {
+            frame.destroy(&self.device);
+        }
+        unsafe {
+            self.device
+                .device
+                .destroy_semaphore(self.timeline, self.device.allocation_callbacks());
+        }
+    }
+}
+
+/// A compute shader along with the layout it binds resources through.
+pub struct ComputePipeline {
+    device: Arc<Device>,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    /// The size of the push constant range, which covers the whole of it.
+    push_constant_size: u32,
+}
+
+impl ComputePipeline {
+    /// Creates a pipeline running the `main` entry point of `shader`, with descriptor sets laid
+    /// out as in `set_layouts` and `push_constant_size` bytes of push constants.
+    ///
+    /// The shader module may be destroyed once this returns. `pipeline_cache` may be null.
+    pub fn new(
+        device: &Arc<Device>,
+        shader: vk::ShaderModule,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_size: u32,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<Self> {
+        let allocation_callbacks = device.allocation_callbacks();
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: push_constant_size,
+        };
+        let layout = unsafe {
+            device.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo {
+                    set_layout_count: set_layouts.len() as u32,
+                    p_set_layouts: set_layouts.as_ptr(),
+                    push_constant_range_count: (push_constant_size > 0).into(),
+                    p_push_constant_ranges: &push_constant_range,
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?
+        };
+
+        let create_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::COMPUTE,
+                module: shader,
+                p_name: c"main".as_ptr(),
+                ..Default::default()
+            },
+            layout,
+            ..Default::default()
+        };
+        let pipeline = unsafe {
+            device.device.create_compute_pipelines(
+                pipeline_cache,
+                &[create_info],
+                allocation_callbacks,
+            )
+        };
+        let pipeline = match pipeline {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                unsafe {
+                    device
+                        .device
+                        .destroy_pipeline_layout(layout, allocation_callbacks)
+                };
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            device: Arc::clone(device),
+            layout,
+            pipeline,
+            push_constant_size,
+        })
+    }
+
+    /// Records a dispatch of `group_counts` workgroups into `cmd`, with `descriptor_sets` bound
+    /// from set 0 and `push_constants` as the push constants.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be a compute-capable command buffer in the recording state, outside of any
+    /// render pass, and the descriptor sets must match the pipeline's layout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `push_constants` isn't exactly the size the pipeline was created with.
+    pub unsafe fn cmd_dispatch(
+        &self,
+        cmd: vk::CommandBuffer,
+        descriptor_sets: &[vk::DescriptorSet],
+        push_constants: &[u8],
+        group_counts: [u32; 3],
+    ) {
+        assert_eq!(
+            push_constants.len(),
+            self.push_constant_size as usize,
+            "wrong push constant size"
+        );
+
+        let device = &self.device.device;
+        let [x, y, z] = group_counts;
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            if !descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.layout,
+                    0,
+                    descriptor_sets,
+                    &[],
+                );
+            }
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(
+                    cmd,
+                    self.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants,
+                );
+            }
+            device.cmd_dispatch(cmd, x, y, z);
+        }
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        let allocation_callbacks = self.device.allocation_callbacks();
+        unsafe {
+            self.device
+                .device
+                .destroy_pipeline(self.pipeline, allocation_callbacks);
+            self.device
+                .device
+                .destroy_pipeline_layout(self.layout, allocation_callbacks);
+        }
+    }
+}
+
+/// Returns how many workgroups of `local_size` invocations it takes to cover `size` invocations
+/// in each dimension.
+pub fn workgroup_count(size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    [0, 1, 2].map(|i| size[i].div_ceil(local_size[i]))
+}
+
+/// Size of the memory blocks that allocations are carved out of. Anything bigger gets a block of
+/// its own.
+const MEMORY_BLOCK_SIZE: vk::DeviceSize = 64 << 20;
+
+/// Where an allocation should live, which decides the memory type it comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// Device-local memory, for anything only the GPU touches.
+    GpuOnly,
+    /// Host-visible memory, preferably device-local as well, for data the CPU writes for the GPU
+    /// to read, like uniforms and staging buffers.
+    CpuToGpu,
+    /// Host-visible memory, preferably cached, for reading results back on the CPU.
+    GpuToCpu,
+}
+
+impl MemoryLocation {
+    fn required_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly => vk::MemoryPropertyFlags::empty(),
+            // Coherent memory spares us from flushing and invalidating mapped ranges.
+            Self::CpuToGpu | Self::GpuToCpu => {
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            }
+        }
+    }
+
+    fn preferred_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            Self::GpuOnly | Self::CpuToGpu => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            Self::GpuToCpu => vk::MemoryPropertyFlags::HOST_CACHED,
+        }
+    }
+}
+
+/// Suballocates device memory for [`Buffer`]s and [`Image`]s out of larger blocks, as devices only
+/// allow a limited number of allocations.
+///
+/// Blocks are never shared between buffers and images, so that linear and optimal resources can't
+/// end up within `bufferImageGranularity` of each other.
+pub struct Allocator {
+    device: Arc<Device>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Indexed by the block IDs handed out in [`Allocation`]s, with freed blocks left as `None`.
+    blocks: Mutex<Vec<Option<MemoryBlock>>>,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type_index: u32,
+    /// Whether the block holds buffers rather than images.
+    linear: bool,
+    /// Whether the block was allocated for a single allocation too big for a shared block.
+    dedicated: bool,
+    /// Where the block is mapped, if it is host-visible.
+    mapped: *mut u8,
+    /// The unallocated ranges of the block, sorted and never adjacent.
+    free_ranges: Vec<Range<vk::DeviceSize>>,
+}
+
+// SAFETY: The mapping is only a pointer into memory owned by the block, which can be written from
+// any thread.
+unsafe impl Send for MemoryBlock {}
+
+struct Allocation {
+    block: usize,
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    mapped: *mut u8,
+}
+
+// SAFETY: See `MemoryBlock`.
+unsafe impl Send for Allocation {}
+
+impl Allocator {
+    pub fn new(device: &Arc<Device>) -> Arc<Self> {
+        let memory_properties = unsafe {
+            device
+                .instance
+                .instance
+                .get_physical_device_memory_properties(device.physical_device)
+        };
+
+        Arc::new(Self {
+            device: Arc::clone(device),
+            memory_properties,
+            blocks: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// Finds the memory type for `location` among `memory_type_bits`, preferring one with all of
+    /// the location's preferred properties.
+    fn find_memory_type(&self, memory_type_bits: u32, location: MemoryLocation) -> Option<u32> {
+        let required = location.required_flags();
+        let memory_types = &self.memory_properties.memory_types
+            [..self.memory_properties.memory_type_count as usize];
+
+        let find = |flags: vk::MemoryPropertyFlags| {
+            memory_types.iter().zip(0..).find_map(|(memory_type, idx)| {
+                (memory_type_bits & (1 << idx) != 0 && memory_type.property_flags.contains(flags))
+                    .then_some(idx)
+            })
+        };
+        find(required | location.preferred_flags()).or_else(|| find(required))
+    }
+
+    fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        location: MemoryLocation,
+        linear: bool,
+    ) -> Result<Allocation> {
+        let memory_type_index = self
+            .find_memory_type(requirements.memory_type_bits, location)
+            .ok_or(Error::NoSuitableMemoryType)?;
+
+        let mut blocks = self.blocks.lock().unwrap();
+
+        if requirements.size <= MEMORY_BLOCK_SIZE {
+            for (id, block) in blocks.iter_mut().enumerate() {
+                let Some(block) = block else {
+                    continue;
+                };
+                if block.memory_type_index != memory_type_index
+                    || block.linear != linear
+                    || block.dedicated
+                {
+                    continue;
+                }
+                if let Some(offset) = block.carve(requirements.size, requirements.alignment) {
+                    return Ok(block.allocation(id, offset, requirements.size));
+                }
+            }
+        }
+
+        let dedicated = requirements.size > MEMORY_BLOCK_SIZE;
+        let size = if dedicated {
+            requirements.size
+        } else {
+            MEMORY_BLOCK_SIZE
+        };
+        let mut block = self.allocate_block(memory_type_index, size, linear, dedicated)?;
+        let offset = block
+            .carve(requirements.size, requirements.alignment)
+            .expect("fresh memory block too small");
+
+        // Reuse the slot of a freed block if there is one, to keep the list from growing.
+        let id = match blocks.iter().position(Option::is_none) {
+            Some(id) => id,
+            None => {
+                blocks.push(None);
+                blocks.len() - 1
+            }
+        };
+        let allocation = block.allocation(id, offset, requirements.size);
+        blocks[id] = Some(block);
+
+        Ok(allocation)
+    }
+
+    fn allocate_block(
+        &self,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+        linear: bool,
+        dedicated: bool,
+    ) -> Result<MemoryBlock> {
+        let device = &self.device.device;
+        let allocation_callbacks = self.device.allocation_callbacks();
+
+        trace!("allocating {size} byte memory block of type {memory_type_index}");
+        let memory = unsafe {
+            device.allocate_memory(
+                &vk::MemoryAllocateInfo {
+                    allocation_size: size,
+                    memory_type_index,
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?
+        };
+
+        // Host-visible blocks stay mapped for as long as they live, which Vulkan is fine with.
+        let mut mapped = ptr::null_mut();
+        if self.memory_properties.memory_types[memory_type_index as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+        {
+            match unsafe { device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty()) } {
+                Ok(ptr) => mapped = ptr.cast(),
+                Err(err) => {
+                    unsafe { device.free_memory(memory, allocation_callbacks) };
+                    return Err(err.into());
+                }
+            }
+        }
+
+        Ok(MemoryBlock {
+            memory,
+            memory_type_index,
+            linear,
+            dedicated,
+            mapped,
+            free_ranges: iter::once(0..size).collect(),
+        })
+    }
+
+    fn free(&self, allocation: &Allocation) {
+        let mut blocks = self.blocks.lock().unwrap();
+        let slot = &mut blocks[allocation.block];
+        let block = slot.as_mut().expect("allocation from freed memory block");
+
+        if block.dedicated {
+            unsafe {
+                self.device
+                    .device
+                    .free_memory(block.memory, self.device.allocation_callbacks());
+            }
+            *slot = None;
+        } else {
+            block.release(allocation.offset..allocation.offset + allocation.size);
+        }
+    }
+}
+
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        // Buffers and images keep the allocator alive, so everything has been freed by now.
+        for block in self.blocks.get_mut().unwrap().drain(..).flatten() {
+            unsafe {
+                self.device
+                    .device
+                    .free_memory(block.memory, self.device.allocation_callbacks());
+            }
+        }
+    }
+}
+
+impl MemoryBlock {
+    /// Takes `size` bytes aligned to `alignment` from the first free range that fits them,
+    /// returning their offset.
+    fn carve(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let (idx, offset) = self
+            .free_ranges
+            .iter()
+            .enumerate()
+            .find_map(|(idx, range)| {
+                let offset = range.start.next_multiple_of(alignment.max(1));
+                (offset + size <= range.end).then_some((idx, offset))
+            })?;
+
+        let range = self.free_ranges.remove(idx);
+        let remaining = [range.start..offset, offset + size..range.end];
+        for (i, remaining) in remaining
+            .into_iter()
+            .filter(|range| !range.is_empty())
+            .enumerate()
+        {
+            self.free_ranges.insert(idx + i, remaining);
+        }
+
+        Some(offset)
+    }
+
+    /// Returns `range` to the free ranges, merging it with its neighbors.
+    fn release(&mut self, range: Range<vk::DeviceSize>) {
+        let idx = self
+            .free_ranges
+            .partition_point(|free| free.start < range.start);
+        self.free_ranges.insert(idx, range);
+
+        if idx + 1 < self.free_ranges.len()
+            && self.free_ranges[idx].end == self.free_ranges[idx + 1].start
+        {
+            let next = self.free_ranges.remove(idx + 1);
+            self.free_ranges[idx].end = next.end;
+        }
+        if idx > 0 && self.free_ranges[idx - 1].end == self.free_ranges[idx].start {
+            let this = self.free_ranges.remove(idx);
+            self.free_ranges[idx - 1].end = this.end;
+        }
+    }
+
+    fn allocation(&self, block: usize, offset: vk::DeviceSize, size: vk::DeviceSize) -> Allocation {
+        Allocation {
+            block,
+            memory: self.memory,
+            offset,
+            size,
+            mapped: if self.mapped.is_null() {
+                ptr::null_mut()
+            } else {
+                unsafe { self.mapped.add(offset as usize) }
+            },
+        }
+    }
+}
+
+/// A buffer along with the memory backing it.
+pub struct Buffer {
+    allocator: Arc<Allocator>,
+    buffer: vk::Buffer,
+    size: vk::DeviceSize,
+    allocation: Allocation,
+}
+
+impl Buffer {
+    /// Creates a buffer of `size` bytes for `usage`, backed by memory from `location`.
+    pub fn new(
+        allocator: &Arc<Allocator>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Result<Self> {
+        let device = &allocator.device.device;
+        let allocation_callbacks = allocator.device.allocation_callbacks();
+
+        let buffer = unsafe {
+            device.create_buffer(
+                &vk::BufferCreateInfo {
+                    size,
+                    usage,
+                    sharing_mode: vk::SharingMode::EXCLUSIVE,
+                    ..Default::default()
+                },
+                allocation_callbacks,
+            )?
+        };
+
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let allocation = allocator
+            .allocate(requirements, location, true)
+            .and_then(|allocation| {
+                match unsafe {
+                    device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+                } {
+                    Ok(()) => Ok(allocation),
+                    Err(err) => {
+                        allocator.free(&allocation);
+                        Err(err.into())
+                    }
+                }
+            });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { device.destroy_buffer(buffer, allocation_callbacks) };
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            allocator: Arc::clone(allocator),
+            buffer,
+            size,
+            allocation,
+        })
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Copies `data` into the buffer at byte `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer isn't host-visible or `data` doesn't fit.
+    pub fn write<T: Copy>(&mut self, offset: vk::DeviceSize, data: &[T]) {
+        assert!(
+            !self.allocation.mapped.is_null(),
+            "writing to buffer in memory the host can't see"
+        );
+        let len = mem::size_of_val(data);
+        assert!(
+            offset + len as vk::DeviceSize <= self.size,
+            "write past end of buffer"
+        );
+
+        // SAFETY: The mapping covers the whole buffer, and `&mut self` keeps other writes on the
+        // CPU out. Making sure the GPU isn't using this part of the buffer is up to the caller, as
+        // with any other memory the GPU reads.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr().cast::<u8>(),
+                self.allocation.mapped.add(offset as usize),
+                len,
+            );
+        }
+    }
+
+    /// Copies bytes from the buffer at byte `offset` into `data`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer isn't host-visible or `data` reaches past its end.
+    pub fn read(&self, offset: vk::DeviceSize, data: &mut [u8]) {
+        assert!(
+            !self.allocation.mapped.is_null(),
+            "reading from buffer in memory the host can't see"
+        );
+        assert!(
+            offset + data.len() as vk::DeviceSize <= self.size,
+            "read past end of buffer"
+        );
+
+        // SAFETY: The mapping covers the whole buffer. Making sure the GPU is done writing is up to
+        // the caller.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.allocation.mapped.add(offset as usize),
+                data.as_mut_ptr(),
+                data.len(),
+            );
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.allocator
+                .device
+                .device
+                .destroy_buffer(self.buffer, self.allocator.device.allocation_callbacks());
+        }
+        self.allocator.free(&self.allocation);
+    }
+}
+
+/// An image along with the memory backing it.
+pub struct Image {
+    allocator: Arc<Allocator>,
+    image: vk::Image,
+    format: vk::Format,
+    extent: vk::Extent3D,
+    allocation: Allocation,
+}
+
+impl Image {
+    /// Creates an image as described by `create_info`, backed by memory from `location`.
+    ///
+    /// Images are expected to use optimal tiling, as linear images would need blocks of their own.
+    pub fn new(
+        allocator: &Arc<Allocator>,
+        create_info: &vk::ImageCreateInfo<'_>,
+        location: MemoryLocation,
+    ) -> Result<Self> {
+        debug_assert_eq!(create_info.tiling, vk::ImageTiling::OPTIMAL);
+
+        let device = &allocator.device.device;
+        let allocation_callbacks = allocator.device.allocation_callbacks();
+
+        let image = unsafe { device.create_image(create_info, allocation_callbacks)? };
+
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+        let allocation = allocator
+            .allocate(requirements, location, false)
+            .and_then(|allocation| {
+                match unsafe {
+                    device.bind_image_memory(image, allocation.memory, allocation.offset)
+                } {
+                    Ok(()) => Ok(allocation),
+                    Err(err) => {
+                        allocator.free(&allocation);
+                        Err(err.into())
+                    }
+                }
+            });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { device.destroy_image(image, allocation_callbacks) };
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            allocator: Arc::clone(allocator),
+            image,
+            format: create_info.format,
+            extent: create_info.extent,
+            allocation,
+        })
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.extent
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.allocator
+                .device
+                .device
+                .destroy_image(self.image, self.allocator.device.allocation_callbacks());
+        }
+        self.allocator.free(&self.allocation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(set: impl FnOnce(&mut vk::PhysicalDeviceFeatures)) -> vk::PhysicalDeviceFeatures {
+        let mut features = vk::PhysicalDeviceFeatures::default();
+        set(&mut features);
+        features
+    }
+
+    #[test]
+    fn merged_features_include_both() {
+        let a = features(|f| f.fill_mode_non_solid = vk::TRUE);
+        let b = features(|f| f.sampler_anisotropy = vk::TRUE);
+        let expected = features(|f| {
+            f.fill_mode_non_solid = vk::TRUE;
+            f.sampler_anisotropy = vk::TRUE;
+        });
+        assert_eq!(
+            feature_flags(&merge_features(&a, &b)),
+            feature_flags(&expected)
+        );
+    }
+
+    #[test]
+    fn intersected_features_keep_shared_ones() {
+        let requested = features(|f| {
+            f.fill_mode_non_solid = vk::TRUE;
+            f.wide_lines = vk::TRUE;
+        });
+        let supported = features(|f| {
+            f.fill_mode_non_solid = vk::TRUE;
+            f.sampler_anisotropy = vk::TRUE;
+        });
+        let expected = features(|f| f.fill_mode_non_solid = vk::TRUE);
+        assert_eq!(
+            feature_flags(&intersect_features(&requested, &supported)),
+            feature_flags(&expected)
+        );
+    }
+
+    #[test]
+    fn contained_features() {
+        let supported = features(|f| {
+            f.fill_mode_non_solid = vk::TRUE;
+            f.sampler_anisotropy = vk::TRUE;
+        });
+        assert!(contains_features(&supported, &Default::default()));
+        assert!(contains_features(
+            &supported,
+            &features(|f| f.fill_mode_non_solid = vk::TRUE)
+        ));
+        assert!(!contains_features(
+            &supported,
+            &features(|f| {
+                f.fill_mode_non_solid = vk::TRUE;
+                f.wide_lines = vk::TRUE;
+            })
+        ));
+    }
+
+    #[test]
+    fn last_feature_is_covered() {
+        // Catches the flag count going wrong, which would skip the features at the end.
+        let last = features(|f| f.inherited_queries = vk::TRUE);
+        assert!(!contains_features(&Default::default(), &last));
+        assert_eq!(
+            feature_flags(&merge_features(&Default::default(), &last)),
+            feature_flags(&last)
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    alloc::{self, Layout},
+    ffi::c_void,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ash::vk;
+use log::info;
+
+/// Every allocation is preceded by a header recording its size and alignment, which the free and
+/// reallocation callbacks aren't given. The header is at least this large, and a multiple of the
+/// allocation's alignment so that the memory after it stays aligned.
+const HEADER_SIZE: usize = 2 * size_of::<usize>();
+
+/// Totals for the whole process, which only ever creates one instance.
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns callbacks allocating through the global allocator, counting what they hand out.
+pub fn callbacks() -> vk::AllocationCallbacks<'static> {
+    vk::AllocationCallbacks {
+        pfn_allocation: Some(allocate),
+        pfn_reallocation: Some(reallocate),
+        pfn_free: Some(free),
+        ..Default::default()
+    }
+}
+
+/// Logs how much was allocated through the callbacks, and how much of it is still live.
+pub fn log_totals() {
+    info!(
+        "vulkan host allocations: {} total, peak {} bytes, {} still live ({} bytes)",
+        ALLOCATIONS.load(Ordering::Relaxed),
+        PEAK_BYTES.load(Ordering::Relaxed),
+        LIVE_ALLOCATIONS.load(Ordering::Relaxed),
+        LIVE_BYTES.load(Ordering::Relaxed),
+    );
+}
+
+/// Returns the layout of an allocation of `size` bytes including its header, and the offset of
+/// the memory handed out.
+fn layout(size: usize, alignment: usize) -> Option<(Layout, usize)> {
+    let offset = alignment.max(HEADER_SIZE);
+    let layout = Layout::from_size_align(size.checked_add(offset)?, offset).ok()?;
+    Some((layout, offset))
+}
+
+unsafe extern "system" fn allocate(
+    _user_data: *mut c_void,
+    size: usize,
+    alignment: usize,
+    _scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    let Some((layout, offset)) = layout(size, alignment) else {
+        return ptr::null_mut();
+    };
+
+    unsafe {
+        let base = alloc::alloc(layout);
+        if base.is_null() {
+            return ptr::null_mut();
+        }
+        let memory = base.add(offset);
+        memory.cast::<usize>().sub(2).write(size);
+        memory.cast::<usize>().sub(1).write(alignment);
+
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        LIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let live_bytes = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_BYTES.fetch_max(live_bytes, Ordering::Relaxed);
+
+        memory.cast()
+    }
+}
+
+unsafe extern "system" fn reallocate(
+    user_data: *mut c_void,
+    original: *mut c_void,
+    size: usize,
+    alignment: usize,
+    scope: vk::SystemAllocationScope,
+) -> *mut c_void {
+    if original.is_null() {
+        return unsafe { allocate(user_data, size, alignment, scope) };
+    }
+    if size == 0 {
+        unsafe { free(user_data, original) };
+        return ptr::null_mut();
+    }
+
+    unsafe {
+        // On failure, the original allocation must be left untouched.
+        let memory = allocate(user_data, size, alignment, scope);
+        if !memory.is_null() {
+            let original_size = original.cast::<usize>().sub(2).read();
+            ptr::copy_nonoverlapping(
+                original.cast::<u8>(),
+                memory.cast::<u8>(),
+                original_size.min(size),
+            );
+            free(user_data, original);
+        }
+        memory
+    }
+}
+
+unsafe extern "system" fn free(_user_data: *mut c_void, memory: *mut c_void) {
+    if memory.is_null() {
+        return;
+    }
+
+    let size = unsafe {
+        let size = memory.cast::<usize>().sub(2).read();
+        let alignment = memory.cast::<usize>().sub(1).read();
+        // This succeeded when the memory was allocated.
+        let (layout, offset) = layout(size, alignment).unwrap_unchecked();
+        alloc::dealloc(memory.cast::<u8>().sub(offset), layout);
+        size
+    };
+
+    LIVE_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
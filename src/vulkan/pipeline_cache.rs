@@ -0,0 +1,151 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ash::vk;
+use log::{debug, warn};
+
+use super::Device;
+use crate::error::{Error, Result};
+
+/// Where the cache goes, relative to the cache directory.
+const CACHE_FILE_PATH: &str = "wayland-thing/pipeline_cache.bin";
+
+/// Identifies our cache files, ahead of the key.
+const MAGIC: &[u8; 4] = b"WTPC";
+
+/// Length of the header before the cache data: the magic, vendor ID, device ID, driver version
+/// and pipeline cache UUID.
+const HEADER_LEN: usize = MAGIC.len() + 3 * 4 + vk::UUID_SIZE;
+
+/// A pipeline cache that persists across runs, so that pipelines don't have to be compiled from
+/// scratch every time.
+///
+/// The cache is stored in `$XDG_CACHE_HOME/wayland-thing/pipeline_cache.bin`, behind a header
+/// naming the device and driver it came from. Caches from any other device or driver version are
+/// discarded, as drivers aren't required to make sense of them.
+pub struct PipelineCache {
+    device: Arc<Device>,
+    cache: vk::PipelineCache,
+}
+
+impl PipelineCache {
+    /// Creates a pipeline cache, seeded with the one saved by a previous run on the same device
+    /// and driver, if any.
+    pub fn load(device: &Arc<Device>) -> Result<Self> {
+        let data = path()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|contents| {
+                let data = contents
+                    .strip_prefix(&header(device)[..])
+                    .map(<[u8]>::to_vec);
+                if data.is_none() {
+                    debug!("discarding pipeline cache from another device or driver");
+                }
+                data
+            })
+            .unwrap_or_default();
+
+        let create = |data: &[u8]| unsafe {
+            device.device().create_pipeline_cache(
+                &vk::PipelineCacheCreateInfo {
+                    initial_data_size: data.len(),
+                    p_initial_data: data.as_ptr().cast(),
+                    ..Default::default()
+                },
+                device.allocation_callbacks(),
+            )
+        };
+
+        let cache = match create(&data) {
+            Ok(cache) => cache,
+            // Drivers are supposed to ignore data they can't use, but don't let a broken cache
+            // file keep us from starting either way.
+            Err(err) if !data.is_empty() => {
+                warn!("failed to load pipeline cache, starting from scratch: {err}");
+                create(&[])?
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if !data.is_empty() {
+            debug!("loaded {} byte pipeline cache", data.len());
+        }
+
+        Ok(Self {
+            device: Arc::clone(device),
+            cache,
+        })
+    }
+
+    pub fn cache(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    /// Writes the cache out for the next run.
+    pub fn save(&self) -> Result<()> {
+        let path = path().ok_or(Error::NoCacheDir)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let data = unsafe { self.device.device().get_pipeline_cache_data(self.cache)? };
+        let mut contents = header(&self.device).to_vec();
+        contents.extend_from_slice(&data);
+
+        // Write to a temporary file first, so that other instances never see a partial cache.
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, contents)?;
+        fs::rename(temp_path, path)?;
+
+        debug!("saved {} byte pipeline cache", data.len());
+        Ok(())
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        // The cache is only an optimization, so failing to save it is no reason to complain loudly.
+        if let Err(err) = self.save() {
+            warn!("failed to save pipeline cache: {err}");
+        }
+
+        unsafe {
+            self.device
+                .device()
+                .destroy_pipeline_cache(self.cache, self.device.allocation_callbacks());
+        }
+    }
+}
+
+/// Returns the header identifying caches usable with `device`.
+fn header(device: &Device) -> [u8; HEADER_LEN] {
+    let properties = device.properties();
+
+    let mut header = [0; HEADER_LEN];
+    let fields = [
+        &MAGIC[..],
+        &properties.vendor_id.to_le_bytes(),
+        &properties.device_id.to_le_bytes(),
+        &properties.driver_version.to_le_bytes(),
+        &properties.pipeline_cache_uuid,
+    ];
+    let mut offset = 0;
+    for field in fields {
+        header[offset..offset + field.len()].copy_from_slice(field);
+        offset += field.len();
+    }
+
+    header
+}
+
+/// Returns the file the cache is stored in, following the XDG base directory spec.
+fn path() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        // Relative paths are to be ignored, as per the spec.
+        .filter(|path| path.is_absolute())
+        .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".cache")))?;
+    Some(cache_home.join(CACHE_FILE_PATH))
+}
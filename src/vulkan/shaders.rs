@@ -0,0 +1,84 @@
+use std::{collections::HashMap, fs, io::Cursor, path::PathBuf, sync::Arc};
+
+use ash::{util::read_spv, vk};
+use log::debug;
+
+use super::Device;
+use crate::error::Result;
+
+/// Where a shader's SPIR-V comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderSource {
+    /// SPIR-V embedded in the binary, usually with `include_bytes!`.
+    Embedded(&'static [u8]),
+    /// A SPIR-V file, read when the shader is first needed, for trying out changes without
+    /// rebuilding.
+    Path(PathBuf),
+}
+
+impl ShaderSource {
+    /// Reads the SPIR-V words, checking the magic number along the way.
+    fn load(&self) -> Result<Vec<u32>> {
+        // `read_spv` copies the code, which also takes care of aligning it to words.
+        match self {
+            Self::Embedded(spv) => Ok(read_spv(&mut Cursor::new(spv))?),
+            Self::Path(path) => {
+                debug!("loading shader from {}", path.display());
+                Ok(read_spv(&mut Cursor::new(fs::read(path)?))?)
+            }
+        }
+    }
+}
+
+/// Creates shader modules on first use and keeps them around, so that pipelines rebuilt from the
+/// same shaders don't load them all over again.
+pub struct ShaderCache {
+    device: Arc<Device>,
+    modules: HashMap<ShaderSource, vk::ShaderModule>,
+}
+
+impl ShaderCache {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            device: Arc::clone(device),
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Returns the shader module for `source`, creating it if it isn't cached yet.
+    ///
+    /// The module stays valid for as long as the cache.
+    pub fn get(&mut self, source: &ShaderSource) -> Result<vk::ShaderModule> {
+        if let Some(&module) = self.modules.get(source) {
+            return Ok(module);
+        }
+
+        let code = source.load()?;
+        let module = unsafe {
+            self.device.device().create_shader_module(
+                &vk::ShaderModuleCreateInfo {
+                    code_size: code.len() * 4,
+                    p_code: code.as_ptr(),
+                    ..Default::default()
+                },
+                self.device.allocation_callbacks(),
+            )?
+        };
+
+        self.modules.insert(source.clone(), module);
+        Ok(module)
+    }
+}
+
+impl Drop for ShaderCache {
+    fn drop(&mut self) {
+        // Pipelines don't need their shader modules once created, so there is nothing to wait for.
+        for &module in self.modules.values() {
+            unsafe {
+                self.device
+                    .device()
+                    .destroy_shader_module(module, self.device.allocation_callbacks());
+            }
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{Allocator, Buffer, Device, Image, MemoryLocation, Queue};
+use crate::error::Result;
+
+/// Uploads data into images through staging buffers, on the dedicated transfer queue if the
+/// device has one and the graphics queue otherwise, without blocking on the copies.
+///
+/// Copies are batched up until [`Self::flush`] submits them, which signals a timeline semaphore
+/// that submissions using the data have to wait for.
+/// Whenever the copies run on another queue family, the graphics queue also has to take ownership
+/// of the uploaded resources with [`Self::cmd_acquire`] before using them.
+///
+/// Only whole resources can be written, which keeps clear of the transfer queue's granularity
+/// restrictions.
+pub struct Uploader {
+    device: Arc<Device>,
+    allocator: Arc<Allocator>,
+    queue: Arc<Queue>,
+    command_pool: vk::CommandPool,
+    /// Command buffers whose submissions have finished, for reuse.
+    free_command_buffers: Vec<vk::CommandBuffer>,
+    /// The batch collecting copies for the next flush, if anything was uploaded since the last.
+    batch: Option<Batch>,
+    /// Flushed batches with the timeline values they signal, until they finish.
+    in_flight: Vec<(u64, Batch)>,
+    timeline: vk::Semaphore,
+    flushed_batches: u64,
+    /// Acquire barriers for the graphics queue family, from batches flushed since the last
+    /// [`Self::cmd_acquire`].
+    image_acquires: Vec<vk::ImageMemoryBarrier<'static>>,
+}
+
+/// The copies recorded into one command buffer, along with what they need kept alive.
+struct Batch {
+    cmd: vk::CommandBuffer,
+    staging_buffers: Vec<Buffer>,
+    image_acquires: Vec<vk::ImageMemoryBarrier<'static>>,
+}
+
+impl Uploader {
+    /// Creates an uploader staging data through memory from `allocator`.
+    pub fn new(allocator: &Arc<Allocator>) -> Result<Self> {
+        let device = allocator.device();
+        let queue = Arc::clone(
+            device
+                .transfer_queue()
+                .unwrap_or_else(|| device.graphics_queue()),
+        );
+        let queue_family_index = queue.family_index();
+
+        let command_pool = unsafe {
+            device.device().create_command_pool(
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::TRANSIENT
+                        | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                    queue_family_index,
+                    ..Default::default()
+                },
+                device.allocation_callbacks(),
+            )?
+        };
+
+        // Anything created so far is cleaned up by `Drop` if this fails.
+        let mut uploader = Self {
+            device: Arc::clone(device),
+            allocator: Arc::clone(allocator),
+            queue,
+            command_pool,
+            free_command_buffers: Vec::new(),
+            batch: None,
+            in_flight: Vec::new(),
+            timeline: vk::Semaphore::null(),
+            flushed_batches: 0,
+            image_acquires: Vec::new(),
+        };
+        uploader.timeline = device.create_timeline_semaphore(0)?;
+
+        Ok(uploader)
+    }
+
+    /// Returns the timeline semaphore that [`Self::flush`] signals.
+    pub fn timeline(&self) -> vk::Semaphore {
+        self.timeline
+    }
+
+    /// Returns whether uploads run on a queue family other than the graphics queue's, making
+    /// [`Self::cmd_acquire`] necessary.
+    pub fn transfers_ownership(&self) -> bool {
+        self.queue.family_index() != self.device.graphics_queue_family_index()
+    }
+
+    /// Queues a copy of `data` into the first mip level and layer of color image `dst`, which ends
+    /// up in `layout` once the upload is done (and acquired).
+    ///
+    /// The data must be tightly packed, with rows and texel blocks as the image's format lays them
+    /// out. The image's previous contents are discarded.
+    pub fn upload_image(
+        &mut self,
+        dst: &Image,
+        data: &[u8],
+        layout: vk::ImageLayout,
+    ) -> Result<()> {
+        let staging = self.stage(data)?;
+        let (src_queue_family_index, dst_queue_family_index) = self.ownership_transfer();
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer_dst = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: dst.image(),
+            subresource_range,
+            ..Default::default()
+        };
+        // The acquire has to repeat the same layout transition.
+        let release = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::empty(),
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: layout,
+            src_queue_family_index,
+            dst_queue_family_index,
+            image: dst.image(),
+            subresource_range,
+            ..Default::default()
+        };
+
+        let cmd = self.batch()?.cmd;
+        let device = self.device.device();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer_dst],
+            );
+            device.cmd_copy_buffer_to_image(
+                cmd,
+                staging.buffer(),
+                dst.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: dst.extent(),
+                }],
+            );
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[release],
+            );
+        }
+
+        let batch = self.batch.as_mut().unwrap();
+        if src_queue_family_index != dst_queue_family_index {
+            batch.image_acquires.push(release);
+        }
+        batch.staging_buffers.push(staging);
+        Ok(())
+    }
+
+    /// Submits the uploads queued since the last flush, returning the value the timeline
+    /// semaphore reaches once they are done, or `None` if there was nothing to submit.
+    ///
+    /// This also cleans up after earlier uploads that have finished.
+    pub fn flush(&mut self) -> Result<Option<u64>> {
+        self.collect()?;
+
+        let Some(mut batch) = self.batch.take() else {
+            return Ok(None);
+        };
+
+        let device = self.device.device();
+        let value = self.flushed_batches + 1;
+        let timeline_submit_info = vk::TimelineSemaphoreSubmitInfo {
+            signal_semaphore_value_count: 1,
+            p_signal_semaphore_values: &value,
+            ..Default::default()
+        };
+        let result = unsafe {
+            device
+                .end_command_buffer(batch.cmd)
+                .map_err(Into::into)
+                .and_then(|()| {
+                    self.queue.submit(
+                        &[vk::SubmitInfo {
+                            p_next: (&raw const timeline_submit_info).cast(),
+                            command_buffer_count: 1,
+                            p_command_buffers: &batch.cmd,
+                            signal_semaphore_count: 1,
+                            p_signal_semaphores: &self.timeline,
+                            ..Default::default()
+                        }],
+                        vk::Fence::null(),
+                    )
+                })
+        };
+        if let Err(err) = result {
+            // Nothing was submitted, so the command buffer can be reused right away.
+            self.free_command_buffers.push(batch.cmd);
+            return Err(err);
+        }
+
+        self.flushed_batches = value;
+        self.image_acquires.append(&mut batch.image_acquires);
+        self.in_flight.push((value, batch));
+        Ok(Some(value))
+    }
+
+    /// Records the graphics queue's side of the ownership transfers for everything flushed since
+    /// the last call into `cmd`, making the uploaded data available to all later commands.
+    ///
+    /// This does nothing if uploads stay on the graphics queue family.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` must be a graphics command buffer in the recording state, outside of any render pass,
+    /// and its submission must wait for the flushes on the timeline semaphore.
+    pub unsafe fn cmd_acquire(&mut self, cmd: vk::CommandBuffer) {
+        if self.image_acquires.is_empty() {
+            return;
+        }
+
+        for barrier in &mut self.image_acquires {
+            // The release already made the writes available.
+            barrier.src_access_mask = vk::AccessFlags::empty();
+            barrier.dst_access_mask = vk::AccessFlags::MEMORY_READ;
+        }
+
+        unsafe {
+            self.device.device().cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &self.image_acquires,
+            );
+        }
+        self.image_acquires.clear();
+    }
+
+    /// Copies `data` into a new staging buffer.
+    fn stage(&self, data: &[u8]) -> Result<Buffer> {
+        let mut staging = Buffer::new(
+            &self.allocator,
+            data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        staging.write(0, data);
+        Ok(staging)
+    }
+
+    /// Returns the queue families to transfer ownership between, which are both ignored when
+    /// uploads stay on the graphics queue family.
+    fn ownership_transfer(&self) -> (u32, u32) {
+        if self.transfers_ownership() {
+            (
+                self.queue.family_index(),
+                self.device.graphics_queue_family_index(),
+            )
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        }
+    }
+
+    /// Returns the batch to record copies into, starting one if needed.
+    fn batch(&mut self) -> Result<&mut Batch> {
+        if self.batch.is_none() {
+            let device = self.device.device();
+            let cmd = match self.free_command_buffers.pop() {
+                Some(cmd) => cmd,
+                None => unsafe {
+                    device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                        command_pool: self.command_pool,
+                        level: vk::CommandBufferLevel::PRIMARY,
+                        command_buffer_count: 1,
+                        ..Default::default()
+                    })?[0]
+                },
+            };
+
+            // Beginning implicitly resets the command buffer.
+            let result = unsafe {
+                device.begin_command_buffer(
+                    cmd,
+                    &vk::CommandBufferBeginInfo {
+                        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                        ..Default::default()
+                    },
+                )
+            };
+            if let Err(err) = result {
+                self.free_command_buffers.push(cmd);
+                return Err(err.into());
+            }
+
+            self.batch = Some(Batch {
+                cmd,
+                staging_buffers: Vec::new(),
+                image_acquires: Vec::new(),
+            });
+        }
+
+        Ok(self.batch.as_mut().unwrap())
+    }
+
+    /// Frees the staging buffers of finished batches and keeps their command buffers for reuse.
+    fn collect(&mut self) -> Result<()> {
+        let finished = self.device.timeline_semaphore_value(self.timeline)?;
+        let (done, pending) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition::<Vec<_>, _>(|&(value, _)| value <= finished);
+        self.in_flight = pending;
+        self.free_command_buffers
+            .extend(done.into_iter().map(|(_, batch)| batch.cmd));
+        Ok(())
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        // Staging buffers have to outlive the copies reading from them.
+        let _ = self
+            .device
+            .wait_timeline_semaphore(self.timeline, self.flushed_batches, None);
+        self.in_flight.clear();
+        self.batch = None;
+
+        // Destroying the pool frees its command buffers along with it.
+        let allocation_callbacks = self.device.allocation_callbacks();
+        unsafe {
+            self.device
+                .device()
+                .destroy_semaphore(self.timeline, allocation_callbacks);
+            self.device
+                .device()
+                .destroy_command_pool(self.command_pool, allocation_callbacks);
+        }
+    }
+}
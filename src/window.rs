@@ -1,99 +1,370 @@
-use std::{ptr, sync::Arc, time::Duration};
+use std::{
+    ffi::c_void,
+    mem,
+    ops::RangeInclusive,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use ash::vk;
-use log::{debug, trace};
+use ash::{khr, vk};
+use log::{debug, info, trace, warn};
 use wayland_client::{
-    Connection, Dispatch, Proxy, QueueHandle, delegate_noop,
-    globals::{GlobalList, GlobalListContents},
+    Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_noop,
+    globals::{BindError, GlobalList},
     protocol::{
         wl_callback::{self, WlCallback},
         wl_compositor::WlCompositor,
-        wl_registry::{self, WlRegistry},
-        wl_shm::WlShm,
+        wl_output::WlOutput,
+        wl_seat::WlSeat,
+        wl_shm::{self, WlShm},
         wl_shm_pool::WlShmPool,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
         wl_surface::{self, WlSurface},
     },
 };
 use wayland_protocols::{
     wp::{
+        commit_timing::v1::client::{
+            wp_commit_timer_v1::WpCommitTimerV1,
+            wp_commit_timing_manager_v1::WpCommitTimingManagerV1,
+        },
+        fifo::v1::client::{wp_fifo_manager_v1::WpFifoManagerV1, wp_fifo_v1::WpFifoV1},
         fractional_scale::v1::client::{
             wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
             wp_fractional_scale_v1::{self, WpFractionalScaleV1},
         },
+        presentation_time::client::{
+            wp_presentation::WpPresentation,
+            wp_presentation_feedback::{self, WpPresentationFeedback},
+        },
         viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
     },
+    xdg::decoration::zv1::client::{
+        zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+        zxdg_toplevel_decoration_v1::{self, ZxdgToplevelDecorationV1},
+    },
+    xdg::dialog::v1::client::{xdg_dialog_v1::XdgDialogV1, xdg_wm_dialog_v1::XdgWmDialogV1},
     xdg::shell::client::{
-        xdg_surface::XdgSurface,
+        xdg_surface::{self, XdgSurface},
         xdg_toplevel::{self, XdgToplevel},
         xdg_wm_base::{self, XdgWmBase},
     },
+    xdg::toplevel_icon::v1::client::{
+        xdg_toplevel_icon_manager_v1::XdgToplevelIconManagerV1,
+        xdg_toplevel_icon_v1::XdgToplevelIconV1,
+    },
+};
+
+use crate::{
+    app::{App, WindowId},
+    caps::CompositorCaps,
+    decoration::{self, TITLEBAR_HEIGHT, Titlebar, TitlebarPart},
+    error::{Error, Result},
+    gradient::Gradient,
+    icon::{Icon, ToplevelIcon},
+    input_log::InputEvent,
+    options::{ColorSpace, Demo, Options, PresentPreference, RenderMode, Vsync},
+    overlay::QuitOverlay,
+    popup::Popup,
+    presentation::{self, PresentationHistory},
+    render::{Framebuffers, Renderer},
+    saved_geometry::SavedGeometry,
+    session::{Session, protocol::xdg_toplevel_session_v1::XdgToplevelSessionV1},
+    test_pattern::TestPattern,
+    toplevel::{ToplevelStates, WmCapabilities},
+    vulkan::{self, PresentStatus, pipeline_cache::PipelineCache},
 };
 
-use crate::vulkan;
+// From `linux/input-event-codes.h`.
+const KEY_ESC: u32 = 1;
+const KEY_1: u32 = 2;
+const KEY_9: u32 = 10;
+const KEY_Q: u32 = 16;
+const KEY_W: u32 = 17;
+const KEY_R: u32 = 19;
+const KEY_Y: u32 = 21;
+const KEY_LEFTCTRL: u32 = 29;
+const KEY_V: u32 = 47;
+const KEY_N: u32 = 49;
+const KEY_M: u32 = 50;
+const KEY_F10: u32 = 68;
+const KEY_F11: u32 = 87;
+const KEY_RIGHTCTRL: u32 = 97;
+
+/// Number of swapchain rebuilds timed by `--bench-resize`.
+const BENCH_RESIZE_ITERATIONS: u32 = 100;
 
+/// Clear colors selectable with the number keys 1-9.
+const CLEAR_COLOR_PRESETS: [[f32; 3]; 9] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 1.0, 1.0],
+    [0.8, 0.1, 0.1],
+    [0.1, 0.7, 0.2],
+    [0.1, 0.3, 0.9],
+    [0.9, 0.8, 0.1],
+    [0.1, 0.8, 0.8],
+    [0.7, 0.2, 0.8],
+    [0.3, 0.3, 0.3],
+];
+
+/// A toplevel window, rendered to with its own swapchain.
+///
+/// Windows don't receive events directly; the [`App`] owning them routes events to them by
+/// [`WindowId`].
 pub struct Window {
     pub closed: bool,
+    id: WindowId,
+    conn: Connection,
     width: u32,
     height: u32,
+    compositor: WlCompositor,
+    subcompositor: WlSubcompositor,
+    shm: WlShm,
     surface: WlSurface,
+    xdg_wm_base: XdgWmBase,
+    xdg_surface: XdgSurface,
+    xdg_toplevel: XdgToplevel,
+    /// The title the window was created with, which the modes switched from the keyboard get
+    /// appended to.
+    title: String,
+    decoration: Option<ZxdgToplevelDecorationV1>,
+    /// Our own titlebar, drawn when the compositor wants client-side decorations or has no say in
+    /// the matter.
+    titlebar: Option<Titlebar>,
+    icon_manager: Option<XdgToplevelIconManagerV1>,
+    icon: Option<ToplevelIcon>,
+    wm_dialog: Option<XdgWmDialogV1>,
+    /// Created on first use, as a toplevel may only ever get one.
+    dialog: Option<XdgDialogV1>,
+    modal: bool,
+    /// Our handle on the window's state in the compositor session, if there is one.
+    toplevel_session: Option<XdgToplevelSessionV1>,
+    viewporter: WpViewporter,
     viewport: WpViewport,
-    fractional_scale_supported: bool,
+    fifo: Option<WpFifoV1>,
+    presentation: Option<WpPresentation>,
+    presentation_history: PresentationHistory,
+    commit_timer: Option<WpCommitTimerV1>,
+    /// The surface the pointer is currently over, which may be one of our subsurfaces.
+    pointer_surface: Option<WlSurface>,
+    pointer_pos: (f64, f64),
+    ctrl_held: bool,
+    quit_overlay: Option<QuitOverlay>,
+    demo: Demo,
+    test_pattern: Option<TestPattern>,
+    /// Generates the gradient demo's content, which is blitted onto swapchain images instead of
+    /// drawing into them.
+    gradient: Option<Gradient>,
+    /// Our own menu, shown on right click when the compositor has no window menu to offer.
+    popup: Option<Popup>,
+    wm_capabilities: WmCapabilities,
+    fractional_scale: Option<WpFractionalScaleV1>,
     scale: f64,
+    /// Scale reported before the first configure, applied once the surface is configured.
+    pending_scale: Option<f64>,
+    /// The window geometry size from the last toplevel configure, applied along with the
+    /// surface configure that follows it.
+    pending_size: Option<(u32, u32)>,
+    /// The window this one is a dialog or other child of, if any.
+    parent: Option<WindowId>,
+    /// Limits on the content size; zero means unlimited, as in the protocol.
+    min_size: (u32, u32),
+    max_size: (u32, u32),
+    states: ToplevelStates,
+    /// The window geometry size to go back to when leaving fullscreen, maximized or tiled, in case
+    /// the compositor leaves the choice to us.
+    windowed_size: Option<(u32, u32)>,
+    /// Whether the scale is applied with `wl_surface.set_buffer_scale` rather than the viewport.
+    native_resolution: bool,
+    /// The largest size the compositor suggests for the window, with 0 meaning unbounded.
+    configure_bounds: Option<(u32, u32)>,
+    configured: bool,
+    suspended: bool,
+    /// Outputs the surface is currently shown on.
+    surface_outputs: Vec<WlOutput>,
+    /// Set when the surface has left every output, e.g. because they were powered off.
+    offscreen: bool,
+    frame_callback_pending: bool,
+    /// Whether a frame has been presented, after which the compositor sends frame callbacks.
+    mapped: bool,
+    render_mode: RenderMode,
+    needs_redraw: bool,
+    /// Handle to the queue for high-frequency objects (frame callbacks and input devices).
+    input_qh: QueueHandle<App>,
+    clear_color: [f32; 3],
+    /// Whether to outline the triangle rather than fill it, where the device can.
+    wireframe: bool,
+    vk_swapchain_format: vk::Format,
+    vk_color_space: vk::ColorSpaceKHR,
+    vk_present_mode: vk::PresentModeKHR,
+    /// The present mode last asked for, which the surface may not support.
+    present_preference: PresentPreference,
+    /// Set when the swapchain has to be rebuilt before the next frame, e.g. for a new present
+    /// mode.
+    swapchain_outdated: bool,
     vk_device: Arc<vulkan::Device>,
     vk_surface: vk::SurfaceKHR,
     vk_swapchain: vk::SwapchainKHR,
     vk_swapchain_images: Vec<vk::Image>,
-    acquire_image_sem: vk::Semaphore,
+    /// Framebuffers for the current swapchain's images, once there is a swapchain.
+    framebuffers: Option<Framebuffers>,
+    renderer: Renderer,
+    frames: vulkan::FrameRing,
+    /// Swapchains replaced by newer ones, destroyed once the frames using them have finished.
+    retired_swapchains: Vec<RetiredSwapchain>,
+    /// Fences signaled by presents from the current swapchain that haven't been seen signaled
+    /// yet, when the device supports present fences.
+    present_fences: Vec<vk::Fence>,
+    /// Present fences that are free for reuse, whether signaled or not.
+    free_present_fences: Vec<vk::Fence>,
+    gpu_frame_time: Option<Duration>,
 }
 
 impl Window {
-    pub fn new(
+    /// Creates a window on top of `surface`, which it takes ownership of.
+    ///
+    /// The surface is given the toplevel role, so it must not have a role yet. It must have been
+    /// created on `conn`, through `qh` with `id` as its user data, so that the window sees its
+    /// events and can follow outputs and the preferred buffer scale through it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
         conn: &Connection,
-        qh: &QueueHandle<Self>,
+        qh: &QueueHandle<App>,
+        input_qh: &QueueHandle<App>,
         globals: &GlobalList,
+        id: WindowId,
+        surface: WlSurface,
+        allocator: &Arc<vulkan::Allocator>,
+        pipeline_cache: &Arc<PipelineCache>,
         width: u32,
         height: u32,
         title: String,
+        app_id: String,
+        options: &Options,
+        caps: &CompositorCaps,
+        session: Option<&Session>,
+        saved_geometry: Option<SavedGeometry>,
     ) -> Result<Self> {
-        let vk_instance = vulkan::Instance::new()?;
+        // Objects from another connection would make every request on them a protocol error (or
+        // worse, silently refer to unrelated objects).
+        check_connection(conn, globals.registry())?;
+        check_connection(conn, &surface)?;
 
-        let compositor: WlCompositor = globals.bind(qh, 4..=6, ())?;
-        let xdg_wm_base: XdgWmBase = globals.bind(qh, 1..=1, ())?;
-        let viewporter: WpViewporter = globals.bind(qh, 1..=1, ())?;
+        let compositor: WlCompositor = bind_global(globals, qh, 4..=6)?;
+        let subcompositor: WlSubcompositor = bind_global(globals, qh, 1..=1)?;
+        let shm: WlShm = bind_global(globals, qh, 1..=2)?;
+        // This is supposed to be mandatory, but the overlay can't be drawn without it.
+        if !caps.shm_formats.contains(&wl_shm::Format::Argb8888) {
+            warn!("compositor does not advertise ARGB8888 shm buffers");
+        }
+        let xdg_wm_base: XdgWmBase = bind_global(globals, qh, 1..=6)?;
+        let viewporter: WpViewporter = bind_global(globals, qh, 1..=1)?;
         let fractional_scale_manager: Option<WpFractionalScaleManagerV1> =
-            globals.bind(qh, 1..=1, ()).ok();
+            bind_optional_global(globals, qh, 1..=1);
 
-        let surface = compositor.create_surface(qh, ());
         let viewport = viewporter.get_viewport(&surface, qh, ());
 
-        if let Some(fractional_scale_manager) = &fractional_scale_manager {
-            fractional_scale_manager.get_fractional_scale(&surface, qh, ());
-        }
+        let fractional_scale = fractional_scale_manager.map(|fractional_scale_manager| {
+            let fractional_scale = fractional_scale_manager.get_fractional_scale(&surface, qh, id);
+            // The fractional scale object outlives its manager.
+            fractional_scale_manager.destroy();
+            fractional_scale
+        });
+
+        // Only one fifo object may exist per surface, so this must stay opt-in as long as the WSI
+        // might want to create its own (as it may for FIFO presentation).
+        let fifo = if options.fifo {
+            let fifo_manager: Option<WpFifoManagerV1> = bind_optional_global(globals, qh, 1..=1);
+            if fifo_manager.is_none() {
+                warn!("compositor does not support fifo, falling back to frame callbacks only");
+            }
+            fifo_manager.map(|fifo_manager| {
+                let fifo = fifo_manager.get_fifo(&surface, qh, ());
+                // The fifo object outlives its manager.
+                fifo_manager.destroy();
+                fifo
+            })
+        } else {
+            None
+        };
+
+        // Commit timing needs presentation feedback to know when refresh cycles start.
+        let (presentation, commit_timer) = if options.commit_timing {
+            let presentation: Option<WpPresentation> = bind_optional_global(globals, qh, 1..=2);
+            let commit_timing_manager: Option<WpCommitTimingManagerV1> =
+                bind_optional_global(globals, qh, 1..=1);
 
-        let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, qh, ());
-        let xdg_toplevel = xdg_surface.get_toplevel(qh, ());
+            match (presentation, commit_timing_manager) {
+                (Some(presentation), Some(commit_timing_manager)) => {
+                    let commit_timer = commit_timing_manager.get_timer(&surface, qh, ());
+                    // The timer outlives its manager.
+                    commit_timing_manager.destroy();
+                    (Some(presentation), Some(commit_timer))
+                }
+                _ => {
+                    warn!("compositor does not support commit timing");
+                    (None, None)
+                }
+            }
+        } else {
+            (None, None)
+        };
 
-        xdg_toplevel.set_title(title);
+        let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, qh, id);
+        let xdg_toplevel = xdg_surface.get_toplevel(qh, id);
 
-        let display_ptr = conn.display().id().as_ptr().cast();
-        let surface_ptr = surface.id().as_ptr().cast();
+        // Prefer having the compositor draw decorations; it tells us which mode we actually get,
+        // and may change its mind later.
+        let decoration = if caps.server_side_decorations {
+            let decoration_manager: Option<ZxdgDecorationManagerV1> =
+                bind_optional_global(globals, qh, 1..=1);
+            decoration_manager.map(|decoration_manager| {
+                let decoration = decoration_manager.get_toplevel_decoration(&xdg_toplevel, qh, id);
+                decoration.set_mode(zxdg_toplevel_decoration_v1::Mode::ServerSide);
+                // The decoration object outlives its manager.
+                decoration_manager.destroy();
+                decoration
+            })
+        } else {
+            info!("compositor does not offer server-side decorations, drawing our own");
+            None
+        };
 
-        let vk_device = vk_instance.create_device(|physical_device, idx, properties| {
-            properties
-                .queue_flags
-                .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::TRANSFER)
-                && unsafe {
-                    vk_instance
-                        .khr_wayland_instance()
-                        .get_physical_device_wayland_presentation_support(
-                            physical_device,
-                            idx,
-                            &mut *display_ptr,
-                        )
+        let icon_manager: Option<XdgToplevelIconManagerV1> =
+            bind_optional_global(globals, qh, 1..=1);
+        // Desktop entries usually name their icon after the app ID.
+        let icon = Icon::builtin(Some(options.icon_name.clone().unwrap_or(app_id.clone())));
+        let wm_dialog: Option<XdgWmDialogV1> = bind_optional_global(globals, qh, 1..=1);
+
+        xdg_toplevel.set_title(title.clone());
+        // Lets the compositor match us up with our desktop entry.
+        xdg_toplevel.set_app_id(app_id);
+        let toplevel_session = session.map(|session| session.add_toplevel(&xdg_toplevel, id, qh));
+
+        // States requested before the initial commit are applied with the initial configure, so
+        // the window never shows up floating first. Capabilities aren't known yet, but asking for
+        // an unsupported state is harmless.
+        let (width, height) = match saved_geometry {
+            Some(saved) => {
+                debug!("restoring saved geometry {saved:?}");
+                if saved.maximized {
+                    xdg_toplevel.set_maximized();
                 }
-        })?;
+                if saved.fullscreen {
+                    xdg_toplevel.set_fullscreen(None);
+                }
+                (saved.width, saved.height)
+            }
+            None => (width, height),
+        };
 
+        let display_ptr = raw_display_ptr(conn);
+        let surface_ptr = raw_surface_ptr(&surface);
+
+        // The device is shared by all windows; only the surface and swapchain are our own.
+        let vk_device = Arc::clone(allocator.device());
+        let vk_instance = vk_device.instance();
         let vk_surface = unsafe {
             vk_instance.khr_wayland_instance().create_wayland_surface(
                 &vk::WaylandSurfaceCreateInfoKHR {
@@ -101,254 +372,2390 @@ impl Window {
                     surface: surface_ptr,
                     ..Default::default()
                 },
-                None,
+                vk_instance.allocation_callbacks(),
             )?
         };
 
-        let acquire_image_sem = unsafe {
-            vk_device
-                .device()
-                .create_semaphore(&Default::default(), None)?
+        let frames = vulkan::FrameRing::new(&vk_device, options.frames_in_flight)?;
+
+        let vk::SurfaceFormatKHR {
+            format: vk_swapchain_format,
+            color_space: vk_color_space,
+        } = match options.forced_format {
+            Some(format) => {
+                check_surface_format(&vk_device, vk_surface, format)?;
+                debug!("forcing swapchain format {format:?}");
+                vk::SurfaceFormatKHR {
+                    format,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                }
+            }
+            None => {
+                select_surface_format(&vk_device, vk_surface, options.srgb, options.color_space)?
+            }
+        };
+
+        let scale = if options.native_resolution {
+            to_integer_scale(options.scale)
+        } else {
+            options.scale
         };
 
-        let (vk_swapchain, vk_swapchain_images) = create_vk_swapchain(
+        let vk_present_mode = select_present_mode(
             &vk_device,
             vk_surface,
-            vk::SwapchainKHR::null(),
-            width,
-            height,
+            options.vsync.map(Vsync::present_mode),
+        )?;
+        let renderer = Renderer::new(
+            allocator,
+            pipeline_cache,
+            vk_swapchain_format,
+            options.depth,
+            options.samples,
+            options.dynamic_rendering,
+            vk::ImageLayout::PRESENT_SRC_KHR,
         )?;
 
+        let mut demo = options.demo;
+        let gradient = if demo == Demo::Gradient {
+            if Gradient::supports_target(&vk_device, vk_swapchain_format)
+                && supports_swapchain_usage(
+                    &vk_device,
+                    vk_surface,
+                    vk::ImageUsageFlags::TRANSFER_DST,
+                )?
+            {
+                Some(Gradient::new(allocator, pipeline_cache)?)
+            } else {
+                warn!("can't blit the gradient to {vk_swapchain_format:?} swapchain images");
+                // Show the clear color instead.
+                demo = Demo::Clear;
+                None
+            }
+        } else {
+            None
+        };
+
+        // Knowing the clock up front lets the very first presentation feedback be used, and
+        // spares us from routing the presentation object's own events to the window.
+        let mut presentation_history = PresentationHistory::default();
+        if presentation.is_some()
+            && let Some(clock) = caps.presentation_clock
+        {
+            presentation_history.set_clock(clock);
+        }
+
         let mut window = Self {
             closed: false,
+            id,
+            conn: conn.clone(),
             width,
             height,
+            compositor,
+            subcompositor,
+            shm,
             surface,
+            viewporter,
             viewport,
-            fractional_scale_supported: fractional_scale_manager.is_some(),
-            scale: 1.0,
+            fifo,
+            presentation,
+            presentation_history,
+            commit_timer,
+            pointer_surface: None,
+            pointer_pos: (0.0, 0.0),
+            ctrl_held: false,
+            quit_overlay: None,
+            demo,
+            test_pattern: None,
+            gradient,
+            popup: None,
+            // Older compositors never advertise capabilities and are assumed to support
+            // everything.
+            wm_capabilities: if supports(&xdg_toplevel, xdg_toplevel::EVT_WM_CAPABILITIES_SINCE) {
+                WmCapabilities::empty()
+            } else {
+                WmCapabilities::all()
+            },
+            xdg_wm_base,
+            xdg_surface,
+            xdg_toplevel,
+            title,
+            decoration,
+            titlebar: None,
+            icon_manager,
+            icon: None,
+            wm_dialog,
+            dialog: None,
+            modal: false,
+            toplevel_session,
+            fractional_scale,
+            scale,
+            pending_scale: None,
+            pending_size: None,
+            parent: None,
+            min_size: (0, 0),
+            max_size: (0, 0),
+            states: ToplevelStates::empty(),
+            windowed_size: None,
+            native_resolution: options.native_resolution,
+            configure_bounds: None,
+            configured: false,
+            suspended: false,
+            surface_outputs: Vec::new(),
+            offscreen: false,
+            frame_callback_pending: false,
+            mapped: false,
+            render_mode: options.render_mode,
+            needs_redraw: true,
+            input_qh: input_qh.clone(),
+            clear_color: options.clear_color,
+            wireframe: false,
+            vk_swapchain_format,
+            vk_color_space,
+            vk_present_mode,
+            present_preference: match vk_present_mode {
+                vk::PresentModeKHR::MAILBOX => PresentPreference::Mailbox,
+                vk::PresentModeKHR::IMMEDIATE => PresentPreference::Immediate,
+                _ => PresentPreference::Fifo,
+            },
+            swapchain_outdated: false,
             vk_device,
             vk_surface,
-            vk_swapchain,
-            vk_swapchain_images,
-            acquire_image_sem,
+            // The swapchain is only created with the first configure, once the compositor has had
+            // its say on the size and scale.
+            vk_swapchain: vk::SwapchainKHR::null(),
+            vk_swapchain_images: Vec::new(),
+            framebuffers: None,
+            renderer,
+            frames,
+            retired_swapchains: Vec::new(),
+            present_fences: Vec::new(),
+            free_present_fences: Vec::new(),
+            gpu_frame_time: None,
         };
 
-        // Kick off the frame timer by drawing our first frame.
-        window.handle_frame(qh, Duration::from_millis(0))?;
+        window.set_icon(qh, &icon)?;
+        let (min_width, min_height) = options.min_size;
+        window.set_min_size(min_width, min_height);
+        let (max_width, max_height) = options.max_size;
+        window.set_max_size(max_width, max_height);
+
+        // Without a decoration object, nobody is going to tell us to draw a titlebar, so just do
+        // it.
+        if window.decoration.is_none() {
+            window.set_decoration_mode(qh, zxdg_toplevel_decoration_v1::Mode::ClientSide)?;
+        }
+
+        // Ask for the initial configure; the first frame is drawn once it arrives.
+        window.surface.commit();
 
         Ok(window)
     }
 
-    fn handle_frame(&mut self, qh: &QueueHandle<Self>, timestamp: Duration) -> Result<()> {
-        trace!("frame at {timestamp:?}");
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
 
-        // TODO: Recreate if suboptimal.
-        let (image_idx, _) = unsafe {
-            self.vk_device.khr_swapchain_device().acquire_next_image(
-                self.vk_swapchain,
-                0,
-                self.acquire_image_sem,
-                vk::Fence::null(),
-            )?
-        };
+    /// Returns the window's main surface.
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
 
-        let _image = self.vk_swapchain_images[image_idx as usize];
+    /// Returns the raw `wl_display` pointer, for creating surfaces in other GPU contexts.
+    ///
+    /// The pointer remains valid for as long as the window's connection is alive.
+    pub fn display_ptr(&self) -> *mut c_void {
+        raw_display_ptr(&self.conn)
+    }
 
-        // TODO: bind and draw into image...
+    /// Returns the raw `wl_surface` pointer, for creating surfaces in other GPU contexts.
+    ///
+    /// The pointer remains valid for as long as the window is alive.
+    pub fn surface_ptr(&self) -> *mut c_void {
+        raw_surface_ptr(&self.surface)
+    }
 
-        let (width, height) = (
-            (self.width as f64 * self.scale).round() as u32,
-            (self.height as f64 * self.scale).round() as u32,
-        );
+    /// Returns the format of the swapchain images, which pipelines rendering into them must be
+    /// built for. It stays the same for the lifetime of the window.
+    pub fn swapchain_format(&self) -> vk::Format {
+        self.vk_swapchain_format
+    }
 
-        self.viewport
-            .set_source(0.0, 0.0, width as f64, height as f64);
-        self.viewport
-            .set_destination(self.width as i32, self.height as i32);
+    /// Returns the color space the swapchain images are presented in, which is what anything
+    /// rendering into them has to encode its colors for. Like the format, it never changes.
+    pub fn swapchain_color_space(&self) -> vk::ColorSpaceKHR {
+        self.vk_color_space
+    }
 
-        self.surface.frame(qh, FrameCallbackToken);
+    /// Checks whether `surface` is the window's main surface or one of its subsurfaces.
+    pub fn contains_surface(&self, surface: &WlSurface) -> bool {
+        *surface == self.surface
+            || self
+                .titlebar
+                .as_ref()
+                .is_some_and(|titlebar| titlebar.surface() == surface)
+            || self
+                .quit_overlay
+                .as_ref()
+                .is_some_and(|quit_overlay| quit_overlay.surface() == surface)
+            || self
+                .test_pattern
+                .as_ref()
+                .is_some_and(|test_pattern| test_pattern.surface() == surface)
+            || self
+                .popup
+                .as_ref()
+                .is_some_and(|popup| popup.surface() == surface)
+    }
 
-        // This present call will also commit the surface.
-        unsafe {
-            self.vk_device.khr_swapchain_device().queue_present(
-                self.vk_device.queue(),
-                &vk::PresentInfoKHR {
-                    wait_semaphore_count: 1,
-                    p_wait_semaphores: [self.acquire_image_sem].as_ptr(),
-                    swapchain_count: 1,
-                    p_swapchains: [self.vk_swapchain].as_ptr(),
-                    p_image_indices: [image_idx].as_ptr(),
-                    p_results: ptr::null_mut(),
-                    ..Default::default()
-                },
-            )?;
+    /// Returns whether the window has received its first configure, and can thus receive input.
+    pub fn is_configured(&self) -> bool {
+        self.configured
+    }
+
+    /// Asks the compositor to make the window fullscreen on whatever output it sees fit, or to
+    /// restore it.
+    ///
+    /// The window only actually changes state once the compositor configures it accordingly. This
+    /// does nothing if the compositor doesn't support fullscreen windows.
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        if !self.has_capability(WmCapabilities::FULLSCREEN) {
+            return;
+        }
+
+        if fullscreen {
+            self.xdg_toplevel.set_fullscreen(None);
+        } else {
+            self.xdg_toplevel.unset_fullscreen();
+        }
+    }
+
+    /// Starts an interactive move, in response to the pointer button press on `seat` with
+    /// `serial`.
+    ///
+    /// The compositor takes over from here, until the button is released.
+    pub fn begin_move(&self, seat: &WlSeat, serial: u32) {
+        self.xdg_toplevel._move(seat, serial);
+    }
+
+    /// Starts an interactive resize from `edge`, in response to the pointer button press on `seat`
+    /// with `serial`.
+    ///
+    /// The new sizes arrive as configure events while the resize is going on.
+    pub fn begin_resize(&self, seat: &WlSeat, serial: u32, edge: xdg_toplevel::ResizeEdge) {
+        self.xdg_toplevel.resize(seat, serial, edge);
+    }
+
+    /// Pops up the compositor's window menu at `(x, y)` in window geometry coordinates, in
+    /// response to the pointer button press on `seat` with `serial`.
+    ///
+    /// Returns whether the compositor has a window menu to show at all.
+    pub fn show_window_menu(&self, seat: &WlSeat, serial: u32, x: i32, y: i32) -> bool {
+        if !self.has_capability(WmCapabilities::WINDOW_MENU) {
+            return false;
+        }
+        self.xdg_toplevel.show_window_menu(seat, serial, x, y);
+        true
+    }
+
+    /// Handles a right click by showing the window menu at the pointer, or our own popup if the
+    /// compositor has none.
+    pub fn handle_right_click(&mut self, qh: &QueueHandle<App>, seat: &WlSeat, serial: u32) {
+        let Some((x, y)) = self.pointer_geometry_pos() else {
+            return;
+        };
+        let (x, y) = (x as i32, y as i32);
+
+        if !self.show_window_menu(seat, serial, x, y)
+            && let Err(err) = self.open_popup(qh, (x, y, 1, 1), Some((seat, serial)))
+        {
+            warn!("failed to open popup: {err}");
         }
+    }
 
+    /// Opens a popup next to `anchor_rect`, given in window geometry coordinates, replacing any
+    /// popup already open.
+    ///
+    /// See [`Popup::new`] for what `grab` does.
+    pub fn open_popup(
+        &mut self,
+        qh: &QueueHandle<App>,
+        anchor_rect: (i32, i32, u32, u32),
+        grab: Option<(&WlSeat, u32)>,
+    ) -> Result<()> {
+        // Only the topmost popup may be destroyed, so the old one has to go first.
+        self.popup = None;
+        self.popup = Some(Popup::new(
+            &self.compositor,
+            &self.shm,
+            &self.xdg_wm_base,
+            &self.xdg_surface,
+            anchor_rect,
+            grab,
+            self.id,
+            self.scale,
+            qh,
+        )?);
         Ok(())
     }
 
-    fn set_scale(&mut self, _qh: &QueueHandle<Self>, scale: f64) {
-        if scale != self.scale {
-            debug!("buffer scale: {} -> {}", self.scale, scale);
+    pub fn popup_mut(&mut self) -> Option<&mut Popup> {
+        self.popup.as_mut()
+    }
 
-            let new_width = (self.width as f64 * scale).round() as u32;
-            let new_height = (self.height as f64 * scale).round() as u32;
+    pub fn close_popup(&mut self) {
+        self.popup = None;
+    }
 
-            self.scale = scale;
+    pub fn parent(&self) -> Option<WindowId> {
+        self.parent
+    }
 
-            let (new_swapchain, new_images) = create_vk_swapchain(
-                &self.vk_device,
-                self.vk_surface,
-                self.vk_swapchain,
-                new_width,
-                new_height,
-            )
-            .expect("failed to create new swapchain");
+    /// Makes the window a child of `parent`, as for dialogs, or a standalone window again.
+    ///
+    /// Compositors usually keep children above their parent. The [`App`] closes children along with
+    /// their parent.
+    pub fn set_parent(&mut self, parent: Option<&Window>) {
+        self.xdg_toplevel
+            .set_parent(parent.map(|parent| &parent.xdg_toplevel));
+        self.parent = parent.map(Window::id);
+    }
 
-            // TODO: Destroy old stuff
+    /// Marks the window as a modal dialog for its parent, or drops the mark again.
+    ///
+    /// Compositors may dim the parent or keep it from being focused while the dialog is up. Either
+    /// way, the [`App`] stops passing clicks and keys to the parent. This has no effect on windows
+    /// without a parent.
+    pub fn set_modal(&mut self, qh: &QueueHandle<App>, modal: bool) {
+        self.modal = modal;
 
-            self.vk_swapchain = new_swapchain;
-            self.vk_swapchain_images = new_images;
+        let Some(wm_dialog) = &self.wm_dialog else {
+            return;
+        };
+        let dialog = self
+            .dialog
+            .get_or_insert_with(|| wm_dialog.get_xdg_dialog(&self.xdg_toplevel, qh, ()));
+        if modal {
+            dialog.set_modal();
+        } else {
+            dialog.unset_modal();
         }
     }
-}
 
-fn create_vk_swapchain(
-    device: &vulkan::Device,
-    vk_surface: vk::SurfaceKHR,
-    old_swapchain: vk::SwapchainKHR,
-    width: u32,
-    height: u32,
-) -> Result<(vk::SwapchainKHR, Vec<vk::Image>)> {
-    let khr_swapchain_device = device.khr_swapchain_device();
+    pub fn is_modal(&self) -> bool {
+        self.modal
+    }
 
-    let vk_swapchain = unsafe {
-        khr_swapchain_device.create_swapchain(
-            &vk::SwapchainCreateInfoKHR {
-                surface: vk_surface,
-                min_image_count: 2,
-                image_format: vk::Format::R8G8B8_UNORM,
-                image_color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-                image_extent: vk::Extent2D { width, height },
-                image_array_layers: 1,
-                image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                image_sharing_mode: vk::SharingMode::EXCLUSIVE,
-                queue_family_index_count: 1,
-                p_queue_family_indices: [device.queue_family_index()].as_ptr(),
-                pre_transform: vk::SurfaceTransformFlagsKHR::IDENTITY,
-                composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
-                present_mode: vk::PresentModeKHR::MAILBOX,
-                clipped: vk::TRUE,
-                old_swapchain,
-                ..Default::default()
-            },
-            None,
-        )?
-    };
+    /// Drops the window's session handle, which is inert once the session is gone.
+    pub fn leave_session(&mut self) {
+        if let Some(toplevel_session) = self.toplevel_session.take() {
+            toplevel_session.destroy();
+        }
+    }
 
-    let vk_swapchain_images = unsafe { khr_swapchain_device.get_swapchain_images(vk_swapchain)? };
+    /// Changes the title shown in the titlebar and task switchers, e.g. to show the name of an
+    /// open document or progress.
+    pub fn set_title(&self, title: &str) {
+        self.xdg_toplevel.set_title(title.to_owned());
+        // Not all compositors wait for a commit before applying the title, but some do.
+        self.surface.commit();
+    }
 
-    Ok((vk_swapchain, vk_swapchain_images))
-}
+    /// Shows the current render mode and present mode in the title, after either was switched.
+    fn update_title(&self) {
+        let render_mode = match self.render_mode {
+            RenderMode::Continuous => "continuous",
+            RenderMode::OnDemand => "on demand",
+        };
+        self.set_title(&format!(
+            "{} ({:?}, {render_mode})",
+            self.title, self.present_preference
+        ));
+    }
 
-struct FrameCallbackToken;
+    /// Sets the icon docks and taskbars show for the window, if the compositor lets us.
+    ///
+    /// Like most window state, the icon only changes with the next commit.
+    pub fn set_icon(&mut self, qh: &QueueHandle<App>, icon: &Icon) -> Result<()> {
+        let Some(icon_manager) = &self.icon_manager else {
+            return Ok(());
+        };
 
-delegate_noop!(Window: ignore WlCompositor);
-delegate_noop!(Window: ignore WlShm);
-delegate_noop!(Window: ignore WlShmPool);
-delegate_noop!(Window: ignore WpViewporter);
-delegate_noop!(Window: ignore WpViewport);
-delegate_noop!(Window: ignore WpFractionalScaleManagerV1);
-delegate_noop!(Window: ignore XdgSurface);
+        let toplevel_icon = ToplevelIcon::new(icon_manager, &self.shm, icon, qh)?;
+        icon_manager.set_icon(&self.xdg_toplevel, Some(toplevel_icon.proxy()));
+        // The toplevel holds on to the icon now, so the old one can go.
+        self.icon = Some(toplevel_icon);
+        self.request_redraw()
+    }
 
-impl Dispatch<WlRegistry, GlobalListContents> for Window {
-    fn event(
-        _window: &mut Self,
-        _registry: &WlRegistry,
-        _event: wl_registry::Event,
-        _data: &GlobalListContents,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
+    /// Sets the smallest size the window's content may be resized to, with zero meaning no limit.
+    pub fn set_min_size(&mut self, width: u32, height: u32) {
+        self.min_size = (width, height);
+        self.update_size_limits();
     }
-}
 
-impl Dispatch<WlSurface, ()> for Window {
-    fn event(
-        window: &mut Self,
-        _surface: &WlSurface,
-        event: wl_surface::Event,
-        _data: &(),
-        _conn: &Connection,
-        qh: &QueueHandle<Self>,
-    ) {
-        if !window.fractional_scale_supported {
-            if let wl_surface::Event::PreferredBufferScale { factor } = event {
-                window.set_scale(qh, factor as f64);
+    /// Sets the largest size the window's content may be resized to, with zero meaning no limit.
+    pub fn set_max_size(&mut self, width: u32, height: u32) {
+        self.max_size = (width, height);
+        self.update_size_limits();
+    }
+
+    /// Asks the compositor to maximize the window.
+    ///
+    /// As with fullscreen, the new size only arrives with the following configure.
+    fn maximize(&self) {
+        if self.has_capability(WmCapabilities::MAXIMIZE) {
+            self.xdg_toplevel.set_maximized();
+        }
+    }
+
+    fn unmaximize(&self) {
+        if self.has_capability(WmCapabilities::MAXIMIZE) {
+            self.xdg_toplevel.unset_maximized();
+        }
+    }
+
+    /// Asks the compositor to minimize the window.
+    ///
+    /// There is no way to tell whether this worked, as minimized windows have no corresponding
+    /// configure state; the window just stops getting frame callbacks.
+    fn minimize(&self) {
+        if self.has_capability(WmCapabilities::MINIMIZE) {
+            self.xdg_toplevel.set_minimized();
+        }
+    }
+
+    /// Returns the geometry to open the window with on the next run, to pick up where it left off.
+    pub fn saved_geometry(&self) -> SavedGeometry {
+        // Outside of floating, the current size is the compositor's doing, not the user's.
+        let (width, height) = match self.windowed_size {
+            Some((width, height)) if !self.states.is_floating() => {
+                (width, height.saturating_sub(self.titlebar_height()))
             }
+            _ => (self.width, self.height),
+        };
+
+        SavedGeometry {
+            width,
+            height,
+            maximized: self.states.contains(ToplevelStates::MAXIMIZED),
+            fullscreen: self.states.contains(ToplevelStates::FULLSCREEN),
         }
     }
-}
 
-impl Dispatch<WpFractionalScaleV1, ()> for Window {
-    fn event(
-        window: &mut Self,
-        _proxy: &WpFractionalScaleV1,
-        event: wp_fractional_scale_v1::Event,
-        _data: &(),
-        _conn: &Connection,
-        qh: &QueueHandle<Self>,
-    ) {
-        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
-            window.set_scale(qh, (scale as f64) / 120.0);
+    /// Returns the states the compositor last configured the window with.
+    pub fn states(&self) -> ToplevelStates {
+        self.states
+    }
+
+    /// Returns the window management features the compositor offers for this window.
+    pub fn wm_capabilities(&self) -> WmCapabilities {
+        self.wm_capabilities
+    }
+
+    /// Checks for `capability`, noting when a request is skipped for lack of it.
+    fn has_capability(&self, capability: WmCapabilities) -> bool {
+        let supported = self.wm_capabilities.contains(capability);
+        if !supported {
+            debug!("compositor does not support {capability:?}, skipping request");
         }
+        supported
     }
-}
 
-impl Dispatch<XdgWmBase, ()> for Window {
-    fn event(
-        _window: &mut Self,
-        xdg_wm_base: &XdgWmBase,
-        event: xdg_wm_base::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        if let xdg_wm_base::Event::Ping { serial } = event {
-            xdg_wm_base.pong(serial)
+    /// Returns how long the GPU took to render the last finished frame, if known.
+    pub fn gpu_frame_time(&self) -> Option<Duration> {
+        self.gpu_frame_time
+    }
+
+    /// Returns the GPU time per frame averaged over recent frames, which is steadier for display
+    /// than [`Self::gpu_frame_time`].
+    pub fn smoothed_gpu_frame_time(&self) -> Option<Duration> {
+        self.frames.smoothed_gpu_time()
+    }
+
+    /// Returns the scale the window is currently rendered at.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Returns the size of the window's content in surface-local coordinates, excluding
+    /// decorations.
+    pub fn logical_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Returns the size of the window's content in pixels at the current scale.
+    ///
+    /// This is always exactly the size of the swapchain images.
+    pub fn physical_size(&self) -> (u32, u32) {
+        (
+            to_physical(self.width, self.scale),
+            to_physical(self.height, self.scale),
+        )
+    }
+
+    fn handle_frame(&mut self, timestamp: Duration) -> Result<()> {
+        trace!("frame at {timestamp:?}");
+
+        if self.suspended || self.offscreen {
+            // Don't request another frame callback either; rendering is resumed once we become
+            // visible again.
+            trace!("skipping frame while not visible");
+            return Ok(());
+        }
+
+        if self.render_mode == RenderMode::OnDemand && !self.needs_redraw {
+            // Let the frame loop stop until the next `request_redraw`.
+            trace!("nothing to redraw");
+            return Ok(());
+        }
+        self.needs_redraw = false;
+
+        self.wait_for_frame_context()?;
+
+        if self.swapchain_outdated {
+            self.recreate_swapchain()?;
+        }
+
+        let Some((mut image_idx, mut suboptimal)) = self.acquire_image()? else {
+            return Ok(());
+        };
+        self.render_and_submit(image_idx)?;
+
+        if self.native_resolution {
+            // The scale is always integral here, and the logical size is exactly the physical size
+            // divided by it, so the compositor never has to resample the buffer.
+            self.surface.set_buffer_scale(self.scale as i32);
+        } else {
+            let (width, height) = self.physical_size();
+            let (logical_width, logical_height) = self.logical_size();
+
+            self.viewport
+                .set_source(0.0, 0.0, width as f64, height as f64);
+            self.viewport
+                .set_destination(logical_width as i32, logical_height as i32);
         }
+
+        self.surface
+            .frame(&self.input_qh, FrameCallbackToken(self.id));
+        self.frame_callback_pending = true;
+
+        if let Some(presentation) = &self.presentation {
+            presentation.feedback(&self.surface, &self.input_qh, self.id);
+        }
+
+        if let Some(commit_timer) = &self.commit_timer
+            && let Some(refresh) = self.presentation_history.refresh()
+            // Leave ourselves half a refresh cycle of slack for rendering and submission.
+            && let Some(target) = self.presentation_history.next_target(refresh / 2)
+        {
+            trace!("targeting presentation at {target:?}");
+            let (tv_sec_hi, tv_sec_lo, tv_nsec) = presentation::encode_timestamp(target);
+            commit_timer.set_timestamp(tv_sec_hi, tv_sec_lo, tv_nsec);
+        }
+
+        if let Some(fifo) = &self.fifo {
+            // Hold this update back until the previous one has been up for a full refresh cycle,
+            // and make the next one wait for this one in turn.
+            fifo.wait_barrier();
+            fifo.set_barrier();
+        }
+
+        // This present call will also commit the surface, and the WSI flushes the connection
+        // right after, so everything requested for this frame goes out in one batch.
+        let mut retried = false;
+        loop {
+            match self.present(image_idx)? {
+                PresentStatus::Optimal => break,
+                PresentStatus::Suboptimal => {
+                    suboptimal = true;
+                    break;
+                }
+                // Nothing was committed, so the surface requests above are still pending and go
+                // out with the retried present.
+                PresentStatus::OutOfDate if !retried => {
+                    debug!("swapchain out of date on present, rebuilding");
+                    retried = true;
+                    self.recreate_swapchain()?;
+                    // The failed frame is still in flight, so the retry gets the next context.
+                    self.wait_for_frame_context()?;
+                    let Some(acquired) = self.acquire_image()? else {
+                        return Ok(());
+                    };
+                    (image_idx, suboptimal) = acquired;
+                    self.render_and_submit(image_idx)?;
+                }
+                PresentStatus::OutOfDate => {
+                    return Err(vk::Result::ERROR_OUT_OF_DATE_KHR.into());
+                }
+            }
+        }
+
+        if suboptimal {
+            // The frame made it to the screen, just not as efficiently as it could have, so the
+            // new swapchain can wait for the next one.
+            debug!("swapchain suboptimal, rebuilding");
+            self.recreate_swapchain()?;
+        }
+
+        self.mapped = true;
+
+        Ok(())
     }
-}
 
-impl Dispatch<XdgToplevel, ()> for Window {
-    fn event(
-        window: &mut Self,
-        _xdg_toplevel: &XdgToplevel,
-        event: xdg_toplevel::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        if let xdg_toplevel::Event::Close = event {
-            window.closed = true;
+    /// Acquires the next swapchain image, along with whether the swapchain is suboptimal.
+    ///
+    /// If the swapchain is out of date, it is rebuilt and acquisition retried once. If no image is
+    /// available right away, this schedules another attempt for the next frame callback and
+    /// returns `None`.
+    fn acquire_image(&mut self) -> Result<Option<(u32, bool)>> {
+        let mut retried = false;
+        loop {
+            let acquire_result = unsafe {
+                self.vk_device.khr_swapchain_device().acquire_next_image(
+                    self.vk_swapchain,
+                    0,
+                    self.frames.current().acquire_image_sem(),
+                    vk::Fence::null(),
+                )
+            };
+
+            match acquire_result {
+                Ok(acquired) => return Ok(Some(acquired)),
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) if !retried => {
+                    debug!("swapchain out of date on acquire, rebuilding");
+                    retried = true;
+                    self.recreate_swapchain()?;
+                }
+                Err(vk::Result::NOT_READY | vk::Result::TIMEOUT) => {
+                    // The compositor is holding on to every image, e.g. because it stopped showing
+                    // us without telling. Rather than spinning until one frees up, try again on the
+                    // next frame callback.
+                    trace!("no swapchain image available, retrying next frame");
+                    self.needs_redraw = true;
+                    self.surface
+                        .frame(&self.input_qh, FrameCallbackToken(self.id));
+                    self.frame_callback_pending = true;
+                    self.surface.commit();
+                    return Ok(None);
+                }
+                Err(err) => return Err(err.into()),
+            }
         }
     }
-}
 
-impl Dispatch<WlCallback, FrameCallbackToken> for Window {
-    fn event(
-        window: &mut Self,
-        _callback: &WlCallback,
-        event: wl_callback::Event,
-        _token: &FrameCallbackToken,
-        _conn: &Connection,
-        qh: &QueueHandle<Self>,
-    ) {
-        if let wl_callback::Event::Done { callback_data } = event {
-            window
-                .handle_frame(qh, Duration::from_millis(callback_data as u64))
-                .expect("frame callback failed");
+    /// Waits until the current frame context is free for rendering the next frame into.
+    fn wait_for_frame_context(&mut self) -> Result<()> {
+        // Timestamps are only ready once the frame has finished, so this is when to read them back.
+        if let Some(gpu_frame_time) = self.frames.wait_current()? {
+            trace!(
+                "gpu frame time: {gpu_frame_time:?} (smoothed {:?})",
+                self.frames.smoothed_gpu_time().unwrap_or_default()
+            );
+            self.gpu_frame_time = Some(gpu_frame_time);
+        }
+
+        // Some presents and retired swapchains may have finished along the way.
+        self.recycle_present_fences()?;
+        self.destroy_retired_swapchains()
+    }
+
+    /// Records and submits the commands drawing the frame into swapchain image `image_idx`.
+    fn render_and_submit(&mut self, image_idx: u32) -> Result<()> {
+        // Blitting only needs the image from the transfer stage on, which is also when the
+        // generated gradient has to be ready.
+        let wait_stage = match &mut self.gradient {
+            Some(gradient) => {
+                let generated =
+                    gradient.generate(self.frames.timeline(), self.frames.submitted_frames())?;
+                self.frames.wait_timeline(
+                    gradient.timeline(),
+                    generated,
+                    vk::PipelineStageFlags::TRANSFER,
+                );
+                vk::PipelineStageFlags::TRANSFER
+            }
+            None => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        };
+
+        unsafe {
+            self.record_frame(image_idx as usize)?;
+            self.frames.submit(wait_stage)
+        }
+    }
+
+    /// Presents swapchain image `image_idx`.
+    fn present(&mut self, image_idx: u32) -> Result<PresentStatus> {
+        let fence = if self.vk_device.supports_present_fences() {
+            self.take_present_fence()?
+        } else {
+            vk::Fence::null()
+        };
+
+        // Swapchain images are created with concurrent sharing when the graphics and present
+        // families differ, so no ownership transfer is needed before presenting.
+        let status = self.vk_device.present_queue().present(
+            // The submission just moved the ring along, so this frame's context is the previous
+            // one.
+            &[self.frames.previous().render_sem()],
+            self.vk_swapchain,
+            image_idx,
+            fence,
+        );
+
+        // Even a present to an outdated swapchain still waits on its semaphores, signaling the
+        // fence once it has.
+        if fence != vk::Fence::null() {
+            self.present_fences.push(fence);
+        }
+        status
+    }
+
+    /// Returns an unsignaled fence for the next present, reusing a free one if possible.
+    fn take_present_fence(&mut self) -> Result<vk::Fence> {
+        let device = self.vk_device.device();
+        unsafe {
+            match self.free_present_fences.pop() {
+                Some(fence) => {
+                    if let Err(err) = device.reset_fences(&[fence]) {
+                        self.free_present_fences.push(fence);
+                        return Err(err.into());
+                    }
+                    Ok(fence)
+                }
+                None => Ok(device.create_fence(
+                    &vk::FenceCreateInfo::default(),
+                    self.vk_device.allocation_callbacks(),
+                )?),
+            }
+        }
+    }
+
+    /// Moves the fences of presents from the current swapchain that have finished back to the
+    /// free list.
+    fn recycle_present_fences(&mut self) -> Result<()> {
+        let device = self.vk_device.device();
+        let mut idx = 0;
+        while idx < self.present_fences.len() {
+            if unsafe { device.get_fence_status(self.present_fences[idx])? } {
+                let fence = self.present_fences.swap_remove(idx);
+                self.free_present_fences.push(fence);
+            } else {
+                idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the commands for drawing a frame into swapchain image `image_idx` into our command
+    /// buffer.
+    fn record_frame(&self, image_idx: usize) -> Result<()> {
+        let framebuffers = self
+            .framebuffers
+            .as_ref()
+            .expect("no framebuffers to render into");
+
+        let [r, g, b] = encode_color(
+            self.clear_color,
+            self.vk_swapchain_format,
+            self.vk_color_space,
+        );
+
+        // The caller waited for the frame context, so its last submission is done.
+        unsafe {
+            self.frames.record(|cmd| {
+                match &self.gradient {
+                    Some(gradient) => gradient.cmd_blit(
+                        cmd,
+                        self.vk_swapchain_images[image_idx],
+                        framebuffers.extent(),
+                    ),
+                    None => self.renderer.cmd_draw(
+                        cmd,
+                        framebuffers,
+                        image_idx,
+                        [r, g, b, 1.0],
+                        self.demo == Demo::Triangle,
+                        self.wireframe,
+                    ),
+                }
+                Ok(())
+            })
+        }
+    }
+
+    /// Switches between redrawing on every frame and only on [`Window::request_redraw`], e.g. to
+    /// run continuously only while an animation plays.
+    fn set_render_mode(&mut self, render_mode: RenderMode) -> Result<()> {
+        if render_mode == self.render_mode {
+            return Ok(());
+        }
+        debug!("render mode: {:?} -> {render_mode:?}", self.render_mode);
+        self.render_mode = render_mode;
+
+        // The frame loop may have stopped while idling, and continuous rendering needs it going.
+        if render_mode == RenderMode::Continuous {
+            self.request_redraw()?;
+        }
+        Ok(())
+    }
+
+    /// Switches the swapchain to present with `preference`, or FIFO if the surface doesn't support
+    /// it. The swapchain is rebuilt before the next frame.
+    ///
+    /// If the surface's present modes can't be queried, the old preference stays in effect.
+    pub fn set_present_preference(&mut self, preference: PresentPreference) -> Result<()> {
+        let present_mode = select_present_mode(
+            &self.vk_device,
+            self.vk_surface,
+            Some(preference.present_mode()),
+        )?;
+
+        debug!(
+            "present preference: {:?} -> {preference:?}",
+            self.present_preference
+        );
+        self.present_preference = preference;
+        if present_mode != self.vk_present_mode {
+            self.vk_present_mode = present_mode;
+            // Before the first configure there is no swapchain yet, and the first one will use the
+            // new mode anyway.
+            if self.vk_swapchain != vk::SwapchainKHR::null() {
+                self.swapchain_outdated = true;
+                self.request_redraw()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Schedules a redraw for when the window's content has changed.
+    ///
+    /// This only matters in on-demand mode; otherwise every frame is redrawn anyway. The redraw
+    /// happens when the next frame callback fires, so that we never render faster than the
+    /// compositor can display. Multiple requests before then result in a single frame.
+    ///
+    /// Before the window is mapped, this draws the first frame right away, which is where errors
+    /// come from.
+    pub fn request_redraw(&mut self) -> Result<()> {
+        self.needs_redraw = true;
+
+        if self.frame_callback_pending || !self.configured || self.suspended || self.offscreen {
+            // Either a frame is already on its way, or the frame loop is restarted once we become
+            // visible.
+            return Ok(());
+        }
+
+        if !self.mapped {
+            // There won't be any frame callbacks until we've attached something.
+            return self.handle_frame(Duration::ZERO);
+        }
+
+        // Ask for a callback without drawing anything yet; the redraw happens once it fires.
+        self.surface
+            .frame(&self.input_qh, FrameCallbackToken(self.id));
+        self.frame_callback_pending = true;
+        self.surface.commit();
+        Ok(())
+    }
+
+    /// Waits for everything in flight to finish, so that the window can then be dropped without
+    /// destroying anything still in use.
+    pub fn shutdown(&mut self) -> Result<()> {
+        // The presents have to finish first, as they are what make the WSI commit their buffers to
+        // the compositor. They wait for the frames they show, and dropping the window waits for
+        // anything else.
+        self.vk_device.present_queue().wait_idle()?;
+
+        // Now make sure the compositor has actually processed those commits, and sent back any
+        // buffer releases, before the swapchain images backing them go away.
+        self.conn.roundtrip()?;
+
+        Ok(())
+    }
+
+    /// Repeatedly rebuilds the swapchain at varying sizes, presenting a frame from each one, and
+    /// prints statistics on how long the rebuilds took.
+    ///
+    /// Two steps are timed separately: creating the new swapchain along with its framebuffers, and
+    /// retiring the old one, i.e. waiting for the device to go idle and then destroying it. The
+    /// wait includes finishing the frame just presented, which isn't timed otherwise. The window
+    /// must have been configured already.
+    ///
+    /// The results go to stdout rather than the log, so that they show up regardless of the log
+    /// level.
+    pub fn bench_resize(&mut self) -> Result<()> {
+        let (width, height) = (self.width, self.height);
+        let mut create_times = Vec::with_capacity(BENCH_RESIZE_ITERATIONS as usize);
+        let mut retire_times = Vec::with_capacity(BENCH_RESIZE_ITERATIONS as usize);
+
+        for i in 0..BENCH_RESIZE_ITERATIONS {
+            // Sweep sizes between half and one and a half times the original, so that consecutive
+            // rebuilds always change the extent.
+            self.width = width / 2 + (i * 37) % width;
+            self.height = height / 2 + (i * 53) % height;
+
+            let start = Instant::now();
+            self.replace_swapchain()?;
+            create_times.push(start.elapsed());
+
+            // A swapchain that was never presented from may be much cheaper to tear down.
+            self.needs_redraw = true;
+            self.handle_frame(Duration::ZERO)?;
+
+            let start = Instant::now();
+            unsafe {
+                self.vk_device.device().device_wait_idle()?;
+            }
+            self.destroy_retired_swapchains()?;
+            retire_times.push(start.elapsed());
+        }
+
+        self.width = width;
+        self.height = height;
+        self.recreate_swapchain()?;
+
+        print_bench_stats("swapchain creation", &create_times);
+        print_bench_stats("swapchain retirement", &retire_times);
+
+        Ok(())
+    }
+
+    /// Forces everything to be drawn and damaged from scratch on the next frame, regardless of
+    /// what changed.
+    ///
+    /// This is for when the previously presented contents can't be trusted anymore, such as after
+    /// the window was hidden for a while.
+    pub fn invalidate(&mut self) -> Result<()> {
+        if let Some(test_pattern) = &mut self.test_pattern {
+            test_pattern.redraw(&self.input_qh)?;
+        }
+        if let Some(quit_overlay) = &mut self.quit_overlay
+            && let Err(err) = quit_overlay.redraw(&self.input_qh)
+        {
+            warn!("failed to redraw quit overlay, dismissing it: {err}");
+            self.quit_overlay = None;
+        }
+        if let Some(titlebar) = &mut self.titlebar
+            && let Err(err) = titlebar.redraw(&self.input_qh)
+        {
+            self.drop_titlebar(err);
+        }
+
+        // Presenting always damages the whole swapchain image, so all the main surface needs is a
+        // new frame.
+        self.request_redraw()
+    }
+
+    /// Notes that the pointer entered `surface`, which must be one of the window's own.
+    pub fn pointer_enter(&mut self, surface: WlSurface) {
+        self.pointer_surface = Some(surface);
+    }
+
+    pub fn pointer_leave(&mut self) {
+        self.pointer_surface = None;
+    }
+
+    /// Notes that the window gained keyboard focus, with `keys` already held down.
+    pub fn keyboard_enter(&mut self, keys: impl IntoIterator<Item = u32>) {
+        self.ctrl_held = keys.into_iter().any(is_ctrl);
+    }
+
+    pub fn keyboard_leave(&mut self) {
+        self.ctrl_held = false;
+    }
+
+    /// Handles a decoded input event, whether it came from the compositor or a replay.
+    pub fn handle_input(&mut self, qh: &QueueHandle<App>, event: InputEvent) -> Result<()> {
+        match event {
+            InputEvent::Key { key, pressed } => {
+                if is_ctrl(key) {
+                    self.ctrl_held = pressed;
+                } else if pressed {
+                    self.handle_key(qh, key)?;
+                }
+            }
+            InputEvent::PointerMotion { x, y } => self.pointer_pos = (x, y),
+        }
+        Ok(())
+    }
+
+    fn handle_key(&mut self, qh: &QueueHandle<App>, key: u32) -> Result<()> {
+        if self.popup.is_some() && key == KEY_ESC {
+            self.close_popup();
+            return Ok(());
+        }
+
+        if self.quit_overlay.is_some() {
+            // The overlay is modal, so it swallows all other keys while it is up.
+            match key {
+                KEY_Y => self.closed = true,
+                KEY_N | KEY_ESC => {
+                    self.quit_overlay = None;
+                    self.request_redraw()?;
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match key {
+            KEY_Q if self.ctrl_held => {
+                match QuitOverlay::new(
+                    &self.compositor,
+                    &self.subcompositor,
+                    &self.shm,
+                    &self.surface,
+                    self.logical_size(),
+                    self.scale(),
+                    qh,
+                ) {
+                    Ok(quit_overlay) => {
+                        self.quit_overlay = Some(quit_overlay);
+                        // The overlay is a synchronized subsurface, so it needs a commit of ours
+                        // to show.
+                        self.request_redraw()?;
+                    }
+                    Err(err) => warn!("failed to show quit overlay: {err}"),
+                }
+            }
+            KEY_M if self.ctrl_held => self.minimize(),
+            KEY_F10 => {
+                if self.states.contains(ToplevelStates::MAXIMIZED) {
+                    self.unmaximize();
+                } else {
+                    self.maximize();
+                }
+            }
+            KEY_F11 => self.set_fullscreen(!self.states.contains(ToplevelStates::FULLSCREEN)),
+            KEY_R => {
+                let render_mode = match self.render_mode {
+                    RenderMode::Continuous => RenderMode::OnDemand,
+                    RenderMode::OnDemand => RenderMode::Continuous,
+                };
+                self.set_render_mode(render_mode)?;
+                self.update_title();
+            }
+            KEY_V => {
+                let preference = self.present_preference.next();
+                match self.set_present_preference(preference) {
+                    Ok(()) => self.update_title(),
+                    Err(err) => warn!(
+                        "failed to switch to {preference:?}, staying with {:?}: {err}",
+                        self.present_preference
+                    ),
+                }
+            }
+            KEY_W if self.demo == Demo::Triangle => {
+                if self.renderer.supports_wireframe() {
+                    self.wireframe = !self.wireframe;
+                    self.request_redraw()?;
+                } else {
+                    warn!("device does not support wireframe rendering");
+                }
+            }
+            KEY_1..=KEY_9 => {
+                self.clear_color = CLEAR_COLOR_PRESETS[(key - KEY_1) as usize];
+                self.request_redraw()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn set_suspended(&mut self, suspended: bool) -> Result<()> {
+        if suspended != self.suspended {
+            debug!("suspended: {} -> {}", self.suspended, suspended);
+            self.suspended = suspended;
+
+            if !suspended {
+                self.invalidate()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_offscreen(&mut self, offscreen: bool) -> Result<()> {
+        if offscreen != self.offscreen {
+            debug!("offscreen: {} -> {}", self.offscreen, offscreen);
+            self.offscreen = offscreen;
+
+            if offscreen {
+                // Frame callbacks stop along with the outputs, so make sure none of our frames are
+                // left running on the GPU either. Other windows keep rendering.
+                self.frames.wait_idle()?;
+            } else {
+                // The outputs may have been reconfigured while they were off.
+                if self.configured {
+                    self.recreate_swapchain()?;
+                }
+                self.invalidate()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn surface_enter(&mut self, output: WlOutput) -> Result<()> {
+        if !self.surface_outputs.contains(&output) {
+            self.surface_outputs.push(output);
+        }
+        self.set_offscreen(false)
+    }
+
+    fn surface_leave(&mut self, output: &WlOutput) -> Result<()> {
+        self.surface_outputs.retain(|o| o != output);
+        if self.surface_outputs.is_empty() {
+            self.set_offscreen(true)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets about an output that is going away.
+    pub fn output_removed(&mut self, output: &WlOutput) -> Result<()> {
+        // The compositor may not have sent a leave before removing the output.
+        if self.surface_outputs.contains(output) {
+            self.surface_leave(output)?;
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self, qh: &QueueHandle<App>) -> Result<()> {
+        // The configured size is that of the whole window geometry, titlebar included.
+        let titlebar_height = self.titlebar_height();
+        let size = self
+            .pending_size
+            .take()
+            .map(|(width, height)| (width, height.saturating_sub(titlebar_height).max(1)))
+            // Compositors are only asked to respect the limits, so enforce them ourselves.
+            .map(|size| self.clamp_size(size));
+
+        if self.configured {
+            if let Some((width, height)) = size {
+                self.resize(qh, width, height)?;
+            }
+            return Ok(());
+        }
+
+        self.configured = true;
+
+        // Take the compositor's size if it picked one for us. Otherwise, shrink the initial size to
+        // fit if needed, rather than having the compositor squeeze us with another configure right
+        // after the first frame.
+        let (width, height) = size.unwrap_or_else(|| match self.configure_bounds {
+            Some((bounds_width, bounds_height)) => {
+                let clamp = |size: u32, bound: u32| if bound > 0 { size.min(bound) } else { size };
+                (
+                    clamp(self.width, bounds_width),
+                    clamp(self.height, bounds_height),
+                )
+            }
+            None => (self.width, self.height),
+        });
+
+        let resized = (width, height) != (self.width, self.height);
+        if resized {
+            debug!(
+                "initial size: {}x{} -> {width}x{height}",
+                self.width, self.height
+            );
+            self.width = width;
+            self.height = height;
+
+            // A titlebar may already have been set up by the decoration configure preceding this
+            // one.
+            if let Some(titlebar) = &mut self.titlebar
+                && let Err(err) = titlebar.set_width(width, qh)
+            {
+                self.drop_titlebar(err);
+            }
+            self.update_window_geometry();
+        }
+
+        let old_scale = self.scale;
+        if let Some(scale) = self.pending_scale.take() {
+            self.set_scale(qh, scale)?;
+        }
+
+        // Changing the scale already built the swapchain at the new size.
+        if self.scale == old_scale {
+            self.recreate_swapchain()?;
+        }
+
+        // Created only now that the scale is settled, since it's drawn at the physical size.
+        if self.demo == Demo::TestPattern {
+            self.test_pattern = Some(TestPattern::new(
+                &self.compositor,
+                &self.subcompositor,
+                &self.viewporter,
+                &self.shm,
+                &self.surface,
+                self.logical_size(),
+                self.physical_size(),
+                qh,
+            )?);
+        }
+
+        // Kick off the frame timer by drawing our first frame, unless applying the scale already
+        // did.
+        self.request_redraw()
+    }
+
+    fn set_decoration_mode(
+        &mut self,
+        qh: &QueueHandle<App>,
+        mode: zxdg_toplevel_decoration_v1::Mode,
+    ) -> Result<()> {
+        debug!("decoration mode: {mode:?}");
+
+        match mode {
+            zxdg_toplevel_decoration_v1::Mode::ClientSide if self.titlebar.is_none() => {
+                match Titlebar::new(
+                    &self.compositor,
+                    &self.subcompositor,
+                    &self.shm,
+                    &self.surface,
+                    self.width,
+                    self.scale,
+                    qh,
+                ) {
+                    Ok(titlebar) => self.titlebar = Some(titlebar),
+                    Err(err) => {
+                        // The window is still usable without decorations, just harder to move.
+                        warn!("failed to create titlebar, going without: {err}");
+                        return Ok(());
+                    }
+                }
+            }
+            zxdg_toplevel_decoration_v1::Mode::ServerSide => self.titlebar = None,
+            _ => return Ok(()),
+        }
+
+        self.update_window_geometry();
+        self.update_size_limits();
+        self.request_redraw()
+    }
+
+    /// Applies a new content size from the compositor, resizing everything drawn to match.
+    fn resize(&mut self, qh: &QueueHandle<App>, width: u32, height: u32) -> Result<()> {
+        if (width, height) == (self.width, self.height) {
+            return Ok(());
+        }
+
+        debug!("size: {}x{} -> {width}x{height}", self.width, self.height);
+        self.width = width;
+        self.height = height;
+
+        self.update_window_geometry();
+
+        let logical_size = self.logical_size();
+        let physical_size = self.physical_size();
+
+        if let Some(test_pattern) = &mut self.test_pattern {
+            test_pattern.set_logical_size(logical_size);
+            test_pattern.set_physical_size(physical_size, qh)?;
+        }
+
+        if let Some(quit_overlay) = &mut self.quit_overlay {
+            quit_overlay.set_parent_size(logical_size);
+        }
+
+        if let Some(titlebar) = &mut self.titlebar
+            && let Err(err) = titlebar.set_width(width, qh)
+        {
+            self.drop_titlebar(err);
+        }
+
+        self.recreate_swapchain()?;
+        self.request_redraw()
+    }
+
+    /// Handles a left click: on our popup's entry, which closes the window, or on our
+    /// decorations, resizing from the window's edges, or closing or moving it from the titlebar.
+    pub fn handle_left_click(&mut self, seat: &WlSeat, serial: u32) {
+        let Some(pointer_surface) = &self.pointer_surface else {
+            return;
+        };
+
+        if self
+            .popup
+            .as_ref()
+            .is_some_and(|popup| popup.surface() == pointer_surface)
+        {
+            self.closed = true;
+            return;
+        }
+
+        let Some(titlebar) = &self.titlebar else {
+            return;
+        };
+        let Some(geometry_pos) = self.pointer_geometry_pos() else {
+            return;
+        };
+
+        let geometry_size = (self.width, self.height + self.titlebar_height());
+        if let Some(edge) = decoration::resize_edge(geometry_pos, geometry_size) {
+            self.begin_resize(seat, serial, edge);
+            return;
+        }
+
+        if pointer_surface == titlebar.surface() {
+            let (x, y) = self.pointer_pos;
+            match titlebar.hit_test(x, y) {
+                TitlebarPart::CloseButton => self.closed = true,
+                TitlebarPart::Background => self.begin_move(seat, serial),
+            }
+        }
+    }
+
+    /// Returns the pointer position relative to the window geometry, which starts at the top of
+    /// the titlebar, as long as the pointer is over the window itself rather than a popup.
+    fn pointer_geometry_pos(&self) -> Option<(f64, f64)> {
+        let pointer_surface = self.pointer_surface.as_ref()?;
+        let (x, y) = self.pointer_pos;
+
+        let on_titlebar = self
+            .titlebar
+            .as_ref()
+            .is_some_and(|titlebar| titlebar.surface() == pointer_surface);
+        // The test pattern covers the main surface exactly.
+        let on_content = *pointer_surface == self.surface
+            || self
+                .test_pattern
+                .as_ref()
+                .is_some_and(|test_pattern| test_pattern.surface() == pointer_surface);
+
+        if on_titlebar {
+            Some((x, y))
+        } else if on_content {
+            Some((x, y + self.titlebar_height() as f64))
+        } else {
+            None
+        }
+    }
+
+    /// Forwards the content size limits to the compositor, as window geometry sizes.
+    fn update_size_limits(&self) {
+        let titlebar_height = self.titlebar_height();
+        let to_geometry = |(width, height): (u32, u32)| {
+            // Zero has to stay zero, as it means there's no limit.
+            let height = if height > 0 {
+                height + titlebar_height
+            } else {
+                0
+            };
+            (width as i32, height as i32)
+        };
+
+        let (min_width, min_height) = to_geometry(self.min_size);
+        self.xdg_toplevel.set_min_size(min_width, min_height);
+        let (max_width, max_height) = to_geometry(self.max_size);
+        self.xdg_toplevel.set_max_size(max_width, max_height);
+    }
+
+    /// Clamps a content size to the limits set with `set_min_size` and `set_max_size`.
+    fn clamp_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let clamp = |size: u32, min: u32, max: u32| {
+            let size = size.max(min);
+            if max > 0 { size.min(max) } else { size }
+        };
+        let (min_width, min_height) = self.min_size;
+        let (max_width, max_height) = self.max_size;
+        (
+            clamp(width, min_width, max_width),
+            clamp(height, min_height, max_height),
+        )
+    }
+
+    /// Gives up on our titlebar after it failed to draw with `err`, leaving the window without
+    /// decorations rather than failing altogether.
+    fn drop_titlebar(&mut self, err: Error) {
+        warn!("failed to draw titlebar, going without: {err}");
+        self.titlebar = None;
+        self.update_window_geometry();
+        self.update_size_limits();
+    }
+
+    fn titlebar_height(&self) -> u32 {
+        if self.titlebar.is_some() {
+            TITLEBAR_HEIGHT
+        } else {
+            0
+        }
+    }
+
+    /// Tells the compositor where the window is within the main surface.
+    fn update_window_geometry(&self) {
+        // The titlebar counts as part of the window, so that the compositor places and sizes it
+        // along with the rest.
+        let titlebar_height = self.titlebar_height();
+        self.xdg_surface.set_window_geometry(
+            0,
+            -(titlebar_height as i32),
+            self.width as i32,
+            (self.height + titlebar_height) as i32,
+        );
+    }
+
+    fn set_scale(&mut self, qh: &QueueHandle<App>, scale: f64) -> Result<()> {
+        let scale = if self.native_resolution {
+            to_integer_scale(scale)
+        } else {
+            scale
+        };
+
+        if !self.configured {
+            // Rebuilding the swapchain now would be wasted, since nothing can be presented before
+            // the first configure anyway.
+            debug!("deferring buffer scale {scale} until configured");
+            self.pending_scale = Some(scale);
+            return Ok(());
+        }
+
+        if scale != self.scale {
+            debug!("buffer scale: {} -> {}", self.scale, scale);
+
+            self.scale = scale;
+            let physical_size = self.physical_size();
+
+            if let Some(test_pattern) = &mut self.test_pattern {
+                test_pattern.set_physical_size(physical_size, qh)?;
+            }
+
+            if let Some(quit_overlay) = &mut self.quit_overlay
+                && let Err(err) = quit_overlay.set_scale(scale, qh)
+            {
+                warn!("failed to rescale quit overlay, dismissing it: {err}");
+                self.quit_overlay = None;
+            }
+
+            if let Some(titlebar) = &mut self.titlebar
+                && let Err(err) = titlebar.set_scale(scale, qh)
+            {
+                self.drop_titlebar(err);
+            }
+
+            if let Some(popup) = &mut self.popup
+                && let Err(err) = popup.set_scale(scale, qh)
+            {
+                warn!("failed to rescale popup, closing it: {err}");
+                self.close_popup();
+            }
+
+            self.recreate_swapchain()?;
+            self.request_redraw()?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the swapchain with a new one matching the current size and scale.
+    fn recreate_swapchain(&mut self) -> Result<()> {
+        self.replace_swapchain()?;
+        // Nothing may be in flight anymore, e.g. when rebuilding while idle.
+        self.destroy_retired_swapchains()
+    }
+
+    /// Creates a swapchain matching the current size and scale, retiring the old one without
+    /// trying to destroy it yet.
+    fn replace_swapchain(&mut self) -> Result<()> {
+        let (width, height) = self.physical_size();
+        let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if self.gradient.is_some() {
+            image_usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        }
+        let (new_swapchain, new_images) = create_vk_swapchain(
+            &self.vk_device,
+            self.vk_surface,
+            vk::SurfaceFormatKHR {
+                format: self.vk_swapchain_format,
+                color_space: self.vk_color_space,
+            },
+            image_usage,
+            self.vk_present_mode,
+            self.vk_swapchain,
+            width,
+            height,
+        )?;
+
+        let new_framebuffers = match self
+            .renderer
+            .create_framebuffers(&new_images, width, height)
+        {
+            Ok(new_framebuffers) => new_framebuffers,
+            Err(err) => {
+                unsafe {
+                    self.vk_device
+                        .khr_swapchain_device()
+                        .destroy_swapchain(new_swapchain, self.vk_device.allocation_callbacks());
+                }
+                return Err(err);
+            }
+        };
+
+        if let Some(framebuffers) = self.framebuffers.take() {
+            self.retired_swapchains.push(RetiredSwapchain {
+                swapchain: self.vk_swapchain,
+                framebuffers,
+                frames_before_retirement: self.frames.submitted_frames(),
+                present_fences: mem::take(&mut self.present_fences),
+            });
+        }
+        self.vk_swapchain = new_swapchain;
+        self.vk_swapchain_images = new_images;
+        self.swapchain_outdated = false;
+        self.framebuffers = Some(new_framebuffers);
+
+        Ok(())
+    }
+
+    /// Destroys the retired swapchains that nothing presents from anymore, without waiting for
+    /// the GPU.
+    ///
+    /// With present fences, a swapchain is done once all of its presents have signaled theirs.
+    /// Otherwise, finished frames are all we can go by: rendering is done by then, which the
+    /// present waits for in turn, but the present itself may still be holding on to the image.
+    fn destroy_retired_swapchains(&mut self) -> Result<()> {
+        let finished_frames = self.frames.finished_frames()?;
+
+        let device = self.vk_device.device();
+        let mut idx = 0;
+        while idx < self.retired_swapchains.len() {
+            let retired = &self.retired_swapchains[idx];
+            let mut done = retired.frames_before_retirement <= finished_frames;
+            for &fence in &retired.present_fences {
+                done = done && unsafe { device.get_fence_status(fence)? };
+            }
+            if !done {
+                idx += 1;
+                continue;
+            }
+
+            let retired = self.retired_swapchains.swap_remove(idx);
+            trace!("destroying retired swapchain {:?}", retired.swapchain);
+            let fences = retired.destroy(&self.vk_device);
+            self.free_present_fences.extend(fences);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        let device = self.vk_device.device();
+        let allocation_callbacks = self.vk_device.allocation_callbacks();
+        let khr_surface_instance = load_khr_surface_instance(&self.vk_device);
+
+        unsafe {
+            let _ = device.device_wait_idle();
+            let mut fences = mem::take(&mut self.present_fences);
+            fences.append(&mut self.free_present_fences);
+            for retired in mem::take(&mut self.retired_swapchains) {
+                fences.extend(retired.destroy(&self.vk_device));
+            }
+            for fence in fences {
+                device.destroy_fence(fence, allocation_callbacks);
+            }
+            // Views of the swapchain images have to go before the swapchain itself.
+            self.framebuffers = None;
+            self.vk_device
+                .khr_swapchain_device()
+                .destroy_swapchain(self.vk_swapchain, allocation_callbacks);
+            khr_surface_instance.destroy_surface(self.vk_surface, allocation_callbacks);
+        }
+
+        // Tear down the Wayland side only once Vulkan is done with the surface, children first.
+        self.popup = None;
+        self.quit_overlay = None;
+        self.test_pattern = None;
+        self.titlebar = None;
+
+        self.viewport.destroy();
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.destroy();
+        }
+        if let Some(fifo) = &self.fifo {
+            fifo.destroy();
+        }
+        if let Some(commit_timer) = &self.commit_timer {
+            commit_timer.destroy();
+        }
+
+        // The decoration has to go before the toplevel, which has to go before the xdg_surface,
+        // which has to go before the surface.
+        if let Some(decoration) = &self.decoration {
+            decoration.destroy();
+        }
+        if let Some(dialog) = &self.dialog {
+            dialog.destroy();
+        }
+        self.leave_session();
+        if let Some(wm_dialog) = &self.wm_dialog {
+            wm_dialog.destroy();
+        }
+        self.icon = None;
+        if let Some(icon_manager) = &self.icon_manager {
+            icon_manager.destroy();
+        }
+        self.xdg_toplevel.destroy();
+        self.xdg_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+pub fn bind_global<I, State>(
+    globals: &GlobalList,
+    qh: &QueueHandle<State>,
+    version: RangeInclusive<u32>,
+) -> Result<I>
+where
+    I: Proxy + 'static,
+    State: Dispatch<I, ()> + 'static,
+{
+    let interface = I::interface().name;
+
+    let proxy = globals
+        .bind::<I, _, _>(qh, version, ())
+        .map_err(|err| match err {
+            BindError::UnsupportedVersion => Error::UnsupportedGlobalVersion {
+                interface,
+                version: advertised_version(globals, interface),
+            },
+            BindError::NotPresent => Error::MissingGlobal { interface },
+        })?;
+    debug!("bound {interface} version {}", proxy.version());
+    Ok(proxy)
+}
+
+pub fn bind_optional_global<I>(
+    globals: &GlobalList,
+    qh: &QueueHandle<App>,
+    version: RangeInclusive<u32>,
+) -> Option<I>
+where
+    I: Proxy + 'static,
+    App: Dispatch<I, ()>,
+{
+    bind_global(globals, qh, version)
+        .inspect_err(|err| debug!("not binding optional global: {err}"))
+        .ok()
+}
+
+/// Returns the version the compositor advertises `interface` at, or 0 if it doesn't.
+fn advertised_version(globals: &GlobalList, interface: &str) -> u32 {
+    globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|global| global.interface == interface)
+            .map(|global| global.version)
+            .max()
+            .unwrap_or(0)
+    })
+}
+
+/// Prints the minimum, average and maximum of `times`, as measured by `--bench-resize`.
+fn print_bench_stats(what: &str, times: &[Duration]) {
+    let (Some(min), Some(max)) = (times.iter().min(), times.iter().max()) else {
+        return;
+    };
+    let avg = times.iter().sum::<Duration>() / times.len() as u32;
+    println!(
+        "{what} ({} iterations): min {min:?}, avg {avg:?}, max {max:?}",
+        times.len()
+    );
+}
+
+/// Creates the Vulkan instance as configured by `options`.
+pub fn create_vk_instance(options: &Options) -> Result<Arc<vulkan::Instance>> {
+    if options.track_vk_allocations {
+        vulkan::Instance::with_allocation_callbacks(
+            vulkan::AllocationCallbacks::counting(),
+            options.validation,
+        )
+    } else {
+        vulkan::Instance::new(options.validation)
+    }
+}
+
+/// Creates the device all windows on `conn` render with, on a queue family that can present to
+/// the compositor's surfaces.
+pub fn create_vk_device(conn: &Connection, options: &Options) -> Result<Arc<vulkan::Device>> {
+    let vk_instance = create_vk_instance(options)?;
+    let display_ptr = raw_display_ptr(conn);
+
+    vk_instance.create_device(
+        &vulkan::DeviceFeatures {
+            // For the triangle demo's wireframe mode.
+            optional: vk::PhysicalDeviceFeatures {
+                fill_mode_non_solid: vk::TRUE,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        |_, _, properties| {
+            properties
+                .queue_flags
+                .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::TRANSFER)
+        },
+        |physical_device, idx, _| unsafe {
+            vk_instance
+                .khr_wayland_instance()
+                .get_physical_device_wayland_presentation_support(
+                    physical_device,
+                    idx,
+                    &mut *display_ptr,
+                )
+        },
+    )
+}
+
+fn raw_display_ptr(conn: &Connection) -> *mut c_void {
+    conn.display().id().as_ptr().cast()
+}
+
+fn raw_surface_ptr(surface: &WlSurface) -> *mut c_void {
+    surface.id().as_ptr().cast()
+}
+
+/// Checks whether `proxy` was bound at a version supporting a message introduced in `since`.
+///
+/// Child objects inherit the version of the global they were created from, so this works for
+/// them as well.
+pub fn supports(proxy: &impl Proxy, since: u32) -> bool {
+    proxy.version() >= since
+}
+
+/// Surface queries are rare enough that the entry points are only loaded when needed.
+fn load_khr_surface_instance(device: &vulkan::Device) -> khr::surface::Instance {
+    let instance = device.instance();
+    khr::surface::Instance::new(instance.entry(), instance.instance())
+}
+
+/// Checks whether swapchain images for `vk_surface` can be created with `usage`, on top of being
+/// color attachments.
+fn supports_swapchain_usage(
+    device: &vulkan::Device,
+    vk_surface: vk::SurfaceKHR,
+    usage: vk::ImageUsageFlags,
+) -> Result<bool> {
+    let capabilities = unsafe {
+        load_khr_surface_instance(device)
+            .get_physical_device_surface_capabilities(device.physical_device(), vk_surface)?
+    };
+    Ok(capabilities.supported_usage_flags.contains(usage))
+}
+
+/// Makes sure `vk_surface` can be presented to in `format`, with the color space we always use.
+fn check_surface_format(
+    device: &vulkan::Device,
+    vk_surface: vk::SurfaceKHR,
+    format: vk::Format,
+) -> Result<()> {
+    let surface_formats = unsafe {
+        load_khr_surface_instance(device)
+            .get_physical_device_surface_formats(device.physical_device(), vk_surface)?
+    };
+
+    if surface_formats.iter().any(|surface_format| {
+        surface_format.format == format
+            && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+    }) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedSurfaceFormat(format))
+    }
+}
+
+/// Picks a format in `color_space` that `vk_surface` supports, falling back to sRGB if it supports
+/// none of the formats we can use in it.
+///
+/// In sRGB, the first format from our preference list that the surface supports is picked, or
+/// whatever it does support if none of them are.
+fn select_surface_format(
+    device: &vulkan::Device,
+    vk_surface: vk::SurfaceKHR,
+    srgb: bool,
+    color_space: ColorSpace,
+) -> Result<vk::SurfaceFormatKHR> {
+    let surface_formats = unsafe {
+        load_khr_surface_instance(device)
+            .get_physical_device_surface_formats(device.physical_device(), vk_surface)?
+    };
+
+    if color_space != ColorSpace::Srgb {
+        // These need more precision than 8 bits to avoid banding, or in the case of scRGB, the
+        // range of a float format.
+        let (vk_color_space, preferred): (_, &[_]) = match color_space {
+            ColorSpace::Srgb => unreachable!(),
+            ColorSpace::DisplayP3 => (
+                vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+                &[
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::Format::A2R10G10B10_UNORM_PACK32,
+                    vk::Format::B8G8R8A8_UNORM,
+                    vk::Format::R8G8B8A8_UNORM,
+                ],
+            ),
+            ColorSpace::ExtendedSrgb => (
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+                &[vk::Format::R16G16B16A16_SFLOAT],
+            ),
+            ColorSpace::Hdr10 => (
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                &[
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::Format::A2R10G10B10_UNORM_PACK32,
+                    vk::Format::R16G16B16A16_SFLOAT,
+                ],
+            ),
+        };
+
+        let found = device
+            .instance()
+            .supports_swapchain_colorspace()
+            .then(|| {
+                preferred.iter().find(|&&format| {
+                    surface_formats.iter().any(|surface_format| {
+                        surface_format.format == format
+                            && surface_format.color_space == vk_color_space
+                    })
+                })
+            })
+            .flatten();
+        match found {
+            Some(&format) => {
+                debug!("using swapchain format {format:?} in {vk_color_space:?}");
+                return Ok(vk::SurfaceFormatKHR {
+                    format,
+                    color_space: vk_color_space,
+                });
+            }
+            None => warn!("color space {vk_color_space:?} not supported, falling back to sRGB"),
+        }
+    }
+
+    let preferred: &[_] = if srgb {
+        &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB]
+    } else {
+        &[vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM]
+    };
+    let available: Vec<_> = surface_formats
+        .iter()
+        .filter(|surface_format| surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
+        .map(|surface_format| surface_format.format)
+        .collect();
+
+    let format = match preferred.iter().find(|format| available.contains(format)) {
+        Some(&format) => {
+            debug!("using swapchain format {format:?}");
+            format
+        }
+        None => {
+            // Anything of the right color space works, as the clear color is converted as needed
+            // and the pipeline is built for whatever format we pick.
+            let &format = available.first().ok_or(Error::NoSurfaceFormat)?;
+            warn!(
+                "none of the preferred formats {preferred:?} supported, falling back to {format:?}"
+            );
+            format
+        }
+    };
+    Ok(vk::SurfaceFormatKHR {
+        format,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    })
+}
+
+/// Picks `requested` as the present mode, or MAILBOX if nothing specific was requested, falling back
+/// to FIFO (which is always supported) when the surface doesn't support it.
+fn select_present_mode(
+    device: &vulkan::Device,
+    vk_surface: vk::SurfaceKHR,
+    requested: Option<vk::PresentModeKHR>,
+) -> Result<vk::PresentModeKHR> {
+    let preferred = requested.unwrap_or(vk::PresentModeKHR::MAILBOX);
+
+    let present_modes = unsafe {
+        load_khr_surface_instance(device)
+            .get_physical_device_surface_present_modes(device.physical_device(), vk_surface)?
+    };
+
+    if present_modes.contains(&preferred) {
+        debug!("using present mode {preferred:?}");
+        Ok(preferred)
+    } else {
+        // Only warn if the user asked for something specific.
+        if requested.is_some() {
+            warn!("present mode {preferred:?} not supported, falling back to FIFO");
+        } else {
+            debug!("present mode {preferred:?} not supported, falling back to FIFO");
+        }
+        Ok(vk::PresentModeKHR::FIFO)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_vk_swapchain(
+    device: &vulkan::Device,
+    vk_surface: vk::SurfaceKHR,
+    surface_format: vk::SurfaceFormatKHR,
+    image_usage: vk::ImageUsageFlags,
+    present_mode: vk::PresentModeKHR,
+    old_swapchain: vk::SwapchainKHR,
+    width: u32,
+    height: u32,
+) -> Result<(vk::SwapchainKHR, Vec<vk::Image>)> {
+    let khr_swapchain_device = device.khr_swapchain_device();
+
+    let capabilities = unsafe {
+        load_khr_surface_instance(device)
+            .get_physical_device_surface_capabilities(device.physical_device(), vk_surface)?
+    };
+
+    // Double buffering is all we need, but the surface may insist on more. A maximum of 0 means
+    // there is no limit.
+    let mut min_image_count = capabilities.min_image_count.max(2);
+    if capabilities.max_image_count != 0 {
+        min_image_count = min_image_count.min(capabilities.max_image_count);
+    }
+
+    // Wayland compositors generally only support opaque or premultiplied alpha; our alpha is always
+    // 1 anyway.
+    let composite_alpha = [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::INHERIT,
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+    ]
+    .into_iter()
+    .find(|&mode| capabilities.supported_composite_alpha.contains(mode))
+    .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE);
+
+    // We don't rotate anything ourselves, so leave that to the compositor where possible.
+    let pre_transform = if capabilities
+        .supported_transforms
+        .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+    {
+        vk::SurfaceTransformFlagsKHR::IDENTITY
+    } else {
+        capabilities.current_transform
+    };
+
+    let queue_family_indices = [
+        device.graphics_queue_family_index(),
+        device.present_queue_family_index(),
+    ];
+
+    // Stick to exclusive ownership when possible, as it may be faster.
+    let (image_sharing_mode, queue_family_index_count) =
+        if queue_family_indices[0] == queue_family_indices[1] {
+            (vk::SharingMode::EXCLUSIVE, 1)
+        } else {
+            (vk::SharingMode::CONCURRENT, 2)
+        };
+
+    let vk_swapchain = unsafe {
+        khr_swapchain_device.create_swapchain(
+            &vk::SwapchainCreateInfoKHR {
+                surface: vk_surface,
+                min_image_count,
+                image_format: surface_format.format,
+                image_color_space: surface_format.color_space,
+                image_extent: vk::Extent2D { width, height },
+                image_array_layers: 1,
+                image_usage,
+                image_sharing_mode,
+                queue_family_index_count,
+                p_queue_family_indices: queue_family_indices.as_ptr(),
+                pre_transform,
+                composite_alpha,
+                present_mode,
+                clipped: vk::TRUE,
+                old_swapchain,
+                ..Default::default()
+            },
+            device.allocation_callbacks(),
+        )
+    }
+    .map_err(Error::SwapchainCreation)?;
+
+    let vk_swapchain_images = unsafe { khr_swapchain_device.get_swapchain_images(vk_swapchain)? };
+
+    Ok((vk_swapchain, vk_swapchain_images))
+}
+
+/// Makes sure `proxy` is alive and belongs to `conn`.
+fn check_connection(conn: &Connection, proxy: &impl Proxy) -> Result<()> {
+    if !proxy.is_alive() || proxy.backend().upgrade() != Some(conn.backend()) {
+        return Err(Error::ForeignObject {
+            interface: proxy.id().interface().name,
+        });
+    }
+    Ok(())
+}
+
+/// We only care about a handful of fixed keys, so raw evdev keycodes are enough here and we can
+/// skip loading the keymap. This also means we track ctrl ourselves instead of relying on the
+/// keymap-dependent modifier masks.
+fn is_ctrl(key: u32) -> bool {
+    matches!(key, KEY_LEFTCTRL | KEY_RIGHTCTRL)
+}
+
+/// Rounds a fractional scale to the nearest integer scale usable with
+/// `wl_surface.set_buffer_scale`.
+fn to_integer_scale(scale: f64) -> f64 {
+    scale.round().max(1.0)
+}
+
+/// Converts a length in surface-local coordinates to buffer pixels at the given scale.
+fn to_physical(logical: u32, scale: f64) -> u32 {
+    (logical as f64 * scale).round() as u32
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB
+    )
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Encodes a linear value with the PQ (SMPTE ST 2084) transfer function, where 1 is 10000 nits.
+fn linear_to_pq(c: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let y = c.max(0.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// Multiplies `color` by the row-major matrix `m`.
+fn transform_color(m: [[f32; 3]; 3], color: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * color[0] + row[1] * color[1] + row[2] * color[2])
+}
+
+/// Linear BT.709 (sRGB) primaries to linear Display P3 primaries, both with a D65 white point.
+const SRGB_TO_DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.8225, 0.1774, 0.0000],
+    [0.0332, 0.9669, 0.0000],
+    [0.0171, 0.0724, 0.9108],
+];
+
+/// Linear BT.709 (sRGB) primaries to linear BT.2020 primaries.
+const SRGB_TO_BT2020: [[f32; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+/// The brightness sRGB white maps to in HDR10, per ITU-R BT.2408.
+const HDR_REFERENCE_WHITE_NITS: f32 = 203.0;
+
+/// Converts `color`, specified in sRGB, into the values to write to an image of `format` presented
+/// in `color_space`, so that it looks the same.
+fn encode_color(color: [f32; 3], format: vk::Format, color_space: vk::ColorSpaceKHR) -> [f32; 3] {
+    let linear = color.map(srgb_to_linear);
+    match color_space {
+        vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT => {
+            transform_color(SRGB_TO_DISPLAY_P3, linear).map(linear_to_srgb)
+        }
+        // scRGB keeps the sRGB primaries, with 1 as sRGB white.
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => linear,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT => transform_color(SRGB_TO_BT2020, linear)
+            .map(|c| linear_to_pq(c * HDR_REFERENCE_WHITE_NITS / 10000.0)),
+        // With an sRGB format, the hardware encodes whatever we write, so it gets the linear
+        // values instead.
+        _ if is_srgb(format) => linear,
+        _ => color,
+    }
+}
+
+/// Decodes a Wayland array of native-endian `u32`s, as used for enum sets.
+pub fn decode_u32_array(array: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    array
+        .chunks_exact(4)
+        .map(|val| u32::from_ne_bytes(val.try_into().unwrap()))
+}
+
+struct FrameCallbackToken(WindowId);
+
+/// A swapchain replaced by a newer one, which frames may still be presenting from.
+struct RetiredSwapchain {
+    swapchain: vk::SwapchainKHR,
+    framebuffers: Framebuffers,
+    /// How many frames had been submitted when the swapchain was replaced.
+    frames_before_retirement: u64,
+    /// Fences signaled by the presents from the swapchain, if the device supports them.
+    present_fences: Vec<vk::Fence>,
+}
+
+impl RetiredSwapchain {
+    /// Destroys the swapchain, handing back its present fences for reuse.
+    fn destroy(self, device: &vulkan::Device) -> Vec<vk::Fence> {
+        // Views of the swapchain images have to go before the swapchain itself.
+        drop(self.framebuffers);
+        unsafe {
+            device
+                .khr_swapchain_device()
+                .destroy_swapchain(self.swapchain, device.allocation_callbacks());
+        }
+        self.present_fences
+    }
+}
+
+delegate_noop!(App: ignore WlCompositor);
+delegate_noop!(App: ignore WlShm);
+delegate_noop!(App: ignore WlShmPool);
+delegate_noop!(App: ignore WlSubcompositor);
+delegate_noop!(App: ignore WlSubsurface);
+delegate_noop!(App: ignore WpViewporter);
+delegate_noop!(App: ignore WpViewport);
+delegate_noop!(App: ignore WpFractionalScaleManagerV1);
+delegate_noop!(App: ignore WpFifoManagerV1);
+delegate_noop!(App: ignore WpFifoV1);
+delegate_noop!(App: ignore ZxdgDecorationManagerV1);
+delegate_noop!(App: ignore WpCommitTimingManagerV1);
+delegate_noop!(App: ignore WpCommitTimerV1);
+// We always provide the same icon sizes, so the preferred ones don't matter.
+delegate_noop!(App: ignore XdgToplevelIconManagerV1);
+delegate_noop!(App: XdgToplevelIconV1);
+delegate_noop!(App: XdgWmDialogV1);
+delegate_noop!(App: XdgDialogV1);
+// The clock is taken from `CompositorCaps` instead.
+delegate_noop!(App: ignore WpPresentation);
+
+impl Dispatch<WlSurface, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        _surface: &WlSurface,
+        event: wl_surface::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(*id) else {
+            return;
+        };
+
+        let result = match event {
+            wl_surface::Event::Enter { output } => window.surface_enter(output),
+            wl_surface::Event::Leave { output } => window.surface_leave(&output),
+            wl_surface::Event::PreferredBufferScale { factor }
+                if window.fractional_scale.is_none() =>
+            {
+                window.set_scale(qh, factor as f64)
+            }
+            _ => Ok(()),
+        };
+        app.record_result(result);
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(*id) else {
+            return;
+        };
+
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            let result = window.set_scale(qh, (scale as f64) / 120.0);
+            app.record_result(result);
+        }
+    }
+}
+
+impl Dispatch<XdgWmBase, ()> for App {
+    fn event(
+        _app: &mut Self,
+        xdg_wm_base: &XdgWmBase,
+        event: xdg_wm_base::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            xdg_wm_base.pong(serial)
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        xdg_surface: &XdgSurface,
+        event: xdg_surface::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(*id) else {
+            return;
+        };
+
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+            let result = window.configure(qh);
+            app.record_result(result);
+        }
+    }
+}
+
+impl Dispatch<XdgToplevel, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        _xdg_toplevel: &XdgToplevel,
+        event: xdg_toplevel::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(*id) else {
+            return;
+        };
+
+        match event {
+            xdg_toplevel::Event::Close => window.closed = true,
+            xdg_toplevel::Event::Configure {
+                width,
+                height,
+                states,
+            } => {
+                let states = ToplevelStates::from_array(&states);
+                if states != window.states {
+                    debug!("states: {:?} -> {:?}", window.states, states);
+                }
+
+                if window.states.is_floating() && !states.is_floating() {
+                    window.windowed_size =
+                        Some((window.width, window.height + window.titlebar_height()));
+                }
+                let windowed_size = if states.is_floating() {
+                    window.windowed_size.take()
+                } else {
+                    None
+                };
+                window.states = states;
+
+                // A zero size leaves the choice to us, so keep whatever we have, unless we're
+                // coming back from fullscreen, maximized or tiled.
+                if width > 0 && height > 0 {
+                    window.pending_size = Some((width as u32, height as u32));
+                } else if windowed_size.is_some() {
+                    window.pending_size = windowed_size;
+                }
+
+                let result = window.set_suspended(states.contains(ToplevelStates::SUSPENDED));
+                app.record_result(result);
+            }
+            xdg_toplevel::Event::ConfigureBounds { width, height } => {
+                window.configure_bounds = Some((width as u32, height as u32));
+            }
+            xdg_toplevel::Event::WmCapabilities { capabilities } => {
+                let capabilities = WmCapabilities::from_array(&capabilities);
+                debug!("wm capabilities: {capabilities:?}");
+                window.wm_capabilities = capabilities;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZxdgToplevelDecorationV1, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        _decoration: &ZxdgToplevelDecorationV1,
+        event: zxdg_toplevel_decoration_v1::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(*id) else {
+            return;
+        };
+
+        if let zxdg_toplevel_decoration_v1::Event::Configure {
+            mode: WEnum::Value(mode),
+        } = event
+        {
+            let result = window.set_decoration_mode(qh, mode);
+            app.record_result(result);
+        }
+    }
+}
+
+impl Dispatch<WlCallback, FrameCallbackToken> for App {
+    fn event(
+        app: &mut Self,
+        _callback: &WlCallback,
+        event: wl_callback::Event,
+        token: &FrameCallbackToken,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(token.0) else {
+            return;
+        };
+
+        if let wl_callback::Event::Done { callback_data } = event {
+            window.frame_callback_pending = false;
+            window
+                .presentation_history
+                .record_frame_callback(callback_data);
+            let result = window.handle_frame(Duration::from_millis(callback_data as u64));
+            app.record_result(result);
+        }
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, WindowId> for App {
+    fn event(
+        app: &mut Self,
+        _feedback: &WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = app.window_mut(*id) else {
+            return;
+        };
+
+        if let wp_presentation_feedback::Event::Presented {
+            tv_sec_hi,
+            tv_sec_lo,
+            tv_nsec,
+            refresh,
+            ..
+        } = event
+        {
+            window.presentation_history.record_presented(
+                presentation::decode_timestamp(tv_sec_hi, tv_sec_lo, tv_nsec),
+                Duration::from_nanos(refresh as u64),
+            );
         }
     }
 }